@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+
+use geo::{Euclidean, Length, LineString};
+use rstar::{primitives::GeomWithData, RTree};
+
+use crate::map_model::{DiagonalFilter, ViaWayRestriction};
+use crate::route::{ProfileRouterInput, Router, RouterInput, VehicleProfile};
+use crate::{
+    Intersection, IntersectionID, MapModel, ModalFilter, Neighbourhood, Position, Road, RoadID,
+    TravelFlow,
+};
+
+/// How far a resident has to drive from the nearest main road to reach each interior road, before
+/// and after edits. Filters can force long detours to get home, even when every cell is still
+/// connected to a main road.
+pub struct AccessPenalties {
+    /// Meters from the nearest main road to the middle of each reachable interior road, before
+    /// edits
+    pub before: HashMap<RoadID, f64>,
+    /// Same as `before`, but after edits
+    pub after: HashMap<RoadID, f64>,
+}
+
+impl AccessPenalties {
+    pub fn new(map: &MapModel, neighbourhood: &Neighbourhood) -> Self {
+        // Some filters let residents through
+        Self {
+            before: access_distances(
+                map,
+                neighbourhood,
+                &ProfileRouterInput {
+                    inner: &neighbourhood.shortcuts_router_input_before(map),
+                    profile: VehicleProfile::Resident,
+                },
+            ),
+            after: access_distances(
+                map,
+                neighbourhood,
+                &ProfileRouterInput {
+                    inner: &neighbourhood.shortcuts_router_input_after(map),
+                    profile: VehicleProfile::Resident,
+                },
+            ),
+        }
+    }
+
+    /// Extra meters driven to reach a road after edits. None if the road isn't reachable from a
+    /// main road before or after edits.
+    pub fn penalty(&self, r: RoadID) -> Option<f64> {
+        Some(self.after.get(&r)? - self.before.get(&r)?)
+    }
+
+    /// Returns the (max, mean) penalty over all roads reachable both before and after edits
+    pub fn summary(&self) -> (f64, f64) {
+        let penalties: Vec<f64> = self.after.keys().filter_map(|r| self.penalty(*r)).collect();
+        if penalties.is_empty() {
+            return (0.0, 0.0);
+        }
+        let max = penalties.iter().cloned().fold(0.0, f64::max);
+        let mean = penalties.iter().sum::<f64>() / (penalties.len() as f64);
+        (max, mean)
+    }
+}
+
+/// Starting from every main road at a border intersection, find the driving distance along the
+/// quickest route to the middle of every interior road. Routes stay in the interior, and can't
+/// pass through a border intersection; that's the same as starting from its main road.
+fn access_distances(
+    map: &MapModel,
+    neighbourhood: &Neighbourhood,
+    router_input: &impl RouterInput,
+) -> HashMap<RoadID, f64> {
+    let interior = InteriorRouterInput::new(map, neighbourhood, router_input);
+    let router = Router::new(&interior, 1.0);
+
+    let mut starts = Vec::new();
+    for i in &neighbourhood.border_intersections {
+        let intersection = map.get_i(*i);
+        for r in &intersection.roads {
+            if !neighbourhood.main_roads.contains(r) {
+                continue;
+            }
+            for (next, dir) in intersection.allowed_movements_from(*r, router_input) {
+                if neighbourhood.interior_roads.contains(&next) {
+                    starts.push((next, dir));
+                }
+            }
+        }
+    }
+
+    let mut distances = HashMap::new();
+    for r in &neighbourhood.interior_roads {
+        // Roads with a modal filter can't be driven through, but each side is reachable from its
+        // own end. Measure to the middle of the closest side. With multiple filters, the stretch
+        // between them isn't reachable at all.
+        let Some((first, last)) = router_input.blocking_filter_range(*r) else {
+            let end = Position {
+                road: *r,
+                percent_along: 0.5,
+            };
+            if let Some(route) = router.route_from_any(&interior, &starts, end) {
+                distances.insert(*r, route.get_distance_and_time(&interior).0);
+            }
+            continue;
+        };
+
+        let road = map.get_r(*r);
+        let length = Euclidean.length(&road.linestring);
+        let mut sides = Vec::new();
+        for (i, percent_along, from_i) in [
+            (road.src_i, first / 2.0, first / 2.0),
+            (road.dst_i, (1.0 + last) / 2.0, (1.0 - last) / 2.0),
+        ] {
+            // The side touching a main road is reached directly from it
+            if neighbourhood.border_intersections.contains(&i) {
+                sides.push(from_i * length);
+                continue;
+            }
+            let end = Position {
+                road: *r,
+                percent_along,
+            };
+            if let Some(route) = router.route_from_any(&interior, &starts, end) {
+                sides.push(route.get_distance_and_time(&interior).0);
+            }
+        }
+        if let Some(distance) = sides.into_iter().min_by(|a, b| a.total_cmp(b)) {
+            distances.insert(*r, distance);
+        }
+    }
+    distances
+}
+
+/// Only the interior roads of a neighbourhood. Turns between two interior roads at a border
+/// intersection are banned.
+struct InteriorRouterInput<'a, R: RouterInput> {
+    inner: &'a R,
+    map: &'a MapModel,
+    neighbourhood: &'a Neighbourhood,
+    turn_restrictions: HashMap<IntersectionID, Vec<(RoadID, RoadID)>>,
+}
+
+impl<'a, R: RouterInput> InteriorRouterInput<'a, R> {
+    fn new(map: &'a MapModel, neighbourhood: &'a Neighbourhood, inner: &'a R) -> Self {
+        let mut turn_restrictions = HashMap::new();
+        for i in &neighbourhood.border_intersections {
+            let mut banned = inner.turn_restrictions(*i).clone();
+            let interior: Vec<RoadID> = map
+                .get_i(*i)
+                .roads
+                .iter()
+                .filter(|r| neighbourhood.interior_roads.contains(r))
+                .cloned()
+                .collect();
+            for from in &interior {
+                for to in &interior {
+                    if from != to {
+                        banned.push((*from, *to));
+                    }
+                }
+            }
+            turn_restrictions.insert(*i, banned);
+        }
+        Self {
+            inner,
+            map,
+            neighbourhood,
+            turn_restrictions,
+        }
+    }
+}
+
+impl<R: RouterInput> RouterInput for InteriorRouterInput<'_, R> {
+    fn roads_iter(&self) -> impl Iterator<Item = &Road> {
+        self.neighbourhood
+            .interior_roads
+            .iter()
+            .map(|r| self.map.get_r(*r))
+    }
+
+    fn closest_road(&self) -> &RTree<GeomWithData<LineString, RoadID>> {
+        self.inner.closest_road()
+    }
+
+    fn get_r(&self, r: RoadID) -> &Road {
+        self.inner.get_r(r)
+    }
+
+    fn get_i(&self, i: IntersectionID) -> &Intersection {
+        self.inner.get_i(i)
+    }
+
+    fn modal_filters(&self, r: RoadID) -> &[ModalFilter] {
+        self.inner.modal_filters(r)
+    }
+
+    fn vehicle_profile(&self) -> VehicleProfile {
+        self.inner.vehicle_profile()
+    }
+
+    fn travel_flow(&self, r: RoadID) -> TravelFlow {
+        self.inner.travel_flow(r)
+    }
+
+    fn speed_mph(&self, r: RoadID) -> f64 {
+        self.inner.speed_mph(r)
+    }
+
+    fn diagonal_filter(&self, i: IntersectionID) -> Option<&DiagonalFilter> {
+        self.inner.diagonal_filter(i)
+    }
+
+    fn turn_restrictions(&self, i: IntersectionID) -> &Vec<(RoadID, RoadID)> {
+        self.turn_restrictions
+            .get(&i)
+            .unwrap_or_else(|| self.inner.turn_restrictions(i))
+    }
+
+    fn via_way_restrictions(&self) -> &Vec<ViaWayRestriction> {
+        self.inner.via_way_restrictions()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_tests::{load_osm_xml, rectangle_neighbourhood, WEST_NEIGHBOURHOOD};
+    use crate::FilterKind;
+
+    #[test]
+    fn filters_disconnect_the_middle_road() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let neighbourhood = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        let length = |map: &MapModel, r: RoadID| Euclidean.length(&map.get_r(r).linestring);
+
+        // One road in the middle of the neighbourhood, and the others each connecting it to a
+        // main road
+        let (entrances, middle): (Vec<RoadID>, Vec<RoadID>) =
+            neighbourhood.interior_roads.iter().partition(|r| {
+                let road = map.get_r(**r);
+                neighbourhood.border_intersections.contains(&road.src_i)
+                    || neighbourhood.border_intersections.contains(&road.dst_i)
+            });
+        assert_eq!(entrances.len(), 6);
+        assert_eq!(middle.len(), 1);
+        let middle = middle[0];
+
+        let access = AccessPenalties::new(&map, &neighbourhood);
+        for r in &neighbourhood.interior_roads {
+            assert_eq!(access.penalty(*r), Some(0.0));
+        }
+        let expected = entrances
+            .iter()
+            .map(|r| length(&map, *r))
+            .fold(f64::MAX, f64::min)
+            + length(&map, middle) / 2.0;
+        assert!((access.before[&middle] - expected).abs() < 0.01);
+
+        // Filter every entrance in the middle
+        for r in &entrances {
+            map.modal_filters.insert(
+                *r,
+                vec![ModalFilter {
+                    kind: FilterKind::WalkCycleOnly,
+                    percent_along: 0.5,
+                }],
+            );
+        }
+        let access = AccessPenalties::new(&map, &neighbourhood);
+        assert_eq!(access.penalty(middle), None);
+        for r in &entrances {
+            // Only the side touching the main road is still reachable
+            assert!((access.after[r] - length(&map, *r) / 4.0).abs() < 0.01);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+
+use geojson::FeatureCollection;
+
+use crate::{MapModel, Neighbourhood};
+
+/// Every named boundary as a neighbourhood. Calculated when first needed, and kept until `clear`
+/// is called after an edit or a boundary change.
+#[derive(Default)]
+pub struct AllNeighbourhoods {
+    cached: Option<BTreeMap<String, Neighbourhood>>,
+}
+
+impl AllNeighbourhoods {
+    /// Boundaries without any interior roads are skipped.
+    pub fn get(&mut self, map: &MapModel) -> &BTreeMap<String, Neighbourhood> {
+        self.cached.get_or_insert_with(|| {
+            let mut all = BTreeMap::new();
+            for (name, boundary) in &map.boundaries {
+                match Neighbourhood::new(map, boundary.clone()) {
+                    Ok(neighbourhood) => {
+                        all.insert(name.clone(), neighbourhood);
+                    }
+                    Err(err) => {
+                        warn!("Skipping neighbourhood {name}: {err}");
+                    }
+                }
+            }
+            all
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.cached = None;
+    }
+
+    /// The same features as `Neighbourhood::to_gj`, except each neighbourhood has its boundary
+    /// instead of a mask. Every feature has a `neighbourhood` property with the name.
+    pub fn to_gj(&mut self, map: &MapModel) -> FeatureCollection {
+        let mut features = Vec::new();
+        for (name, neighbourhood) in self.get(map) {
+            let mut boundary = neighbourhood.boundary.to_feature(map);
+            boundary.set_property("neighbourhood", name.clone());
+            features.push(boundary);
+
+            for mut f in neighbourhood.to_gj(map).features {
+                // Skip the mask
+                if f.property("kind") == Some(&"boundary".into()) {
+                    continue;
+                }
+                f.set_property("neighbourhood", name.clone());
+                features.push(f);
+            }
+        }
+        FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_model::Command;
+    use crate::osm_tests::{
+        get_road_by_name, load_osm_xml, rectangle_neighbourhood, EAST_NEIGHBOURHOOD,
+        WEST_NEIGHBOURHOOD,
+    };
+    use crate::RoadID;
+
+    /// The kind of the road in the named neighbourhood's rendering
+    fn road_kind(gj: &FeatureCollection, name: &str, r: RoadID) -> String {
+        gj.features
+            .iter()
+            .find(|f| {
+                f.property("neighbourhood") == Some(&name.into())
+                    && f.property("road") == Some(&r.0.into())
+            })
+            .unwrap()
+            .property("kind")
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn clearing_reflects_edits_and_boundaries() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let west = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        map.boundaries.insert("west".to_string(), west.boundary);
+        let r = get_road_by_name(&map, "west cross");
+
+        let mut all = AllNeighbourhoods::default();
+        assert_eq!(road_kind(&all.to_gj(&map), "west", r), "interior_road");
+
+        // The cache is stale until cleared
+        map.apply_command(Command::SetMainRoad(r, true));
+        assert_eq!(road_kind(&all.to_gj(&map), "west", r), "interior_road");
+        all.clear();
+        assert_eq!(road_kind(&all.to_gj(&map), "west", r), "main_road");
+
+        map.undo().unwrap();
+        all.clear();
+        assert_eq!(road_kind(&all.to_gj(&map), "west", r), "interior_road");
+
+        let east = rectangle_neighbourhood(&map, "east", EAST_NEIGHBOURHOOD);
+        map.boundaries.insert("east".to_string(), east.boundary);
+        all.clear();
+        assert_eq!(
+            all.get(&map).keys().collect::<Vec<_>>(),
+            vec!["east", "west"]
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::map_model::Command;
+use crate::{IntersectionID, MapModel, RoadID};
+
+/// Something a user can place or change on the map, which can have an `Annotation`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Intervention {
+    ModalFilter {
+        road: RoadID,
+    },
+    DiagonalFilter {
+        intersection: IntersectionID,
+    },
+    TurnRestriction {
+        intersection: IntersectionID,
+        from_road: RoadID,
+        to_road: RoadID,
+    },
+    TravelFlow {
+        road: RoadID,
+    },
+}
+
+/// Details about an intervention for consultation, so a project can serve as a register of
+/// schemes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub notes: String,
+    pub status: InterventionStatus,
+    /// An external ID, like a traffic order or scheme reference
+    pub reference: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterventionStatus {
+    Proposed,
+    Approved,
+    Built,
+}
+
+impl Intervention {
+    pub fn kind(self) -> &'static str {
+        match self {
+            Self::ModalFilter { .. } => "modal_filter",
+            Self::DiagonalFilter { .. } => "diagonal_filter",
+            Self::TurnRestriction { .. } => "turn_restriction",
+            Self::TravelFlow { .. } => "travel_flow",
+        }
+    }
+}
+
+impl MapModel {
+    /// Deleting an intervention clears its annotation, but annotations on interventions that
+    /// don't exist, like from savefiles, are ignored anyway.
+    pub fn intervention_exists(&self, intervention: Intervention) -> bool {
+        match intervention {
+            Intervention::ModalFilter { road } => self.modal_filters.contains_key(&road),
+            Intervention::DiagonalFilter { intersection } => {
+                self.diagonal_filters.contains_key(&intersection)
+            }
+            Intervention::TurnRestriction {
+                intersection,
+                from_road,
+                to_road,
+            } => self
+                .turn_restrictions
+                .get(intersection.0)
+                .is_some_and(|list| list.contains(&(from_road, to_road))),
+            // Every road has some direction
+            Intervention::TravelFlow { road } => road.0 < self.roads.len(),
+        }
+    }
+
+    pub fn get_annotation(&self, intervention: Intervention) -> Option<&Annotation> {
+        if !self.intervention_exists(intervention) {
+            return None;
+        }
+        self.annotations.get(&intervention)
+    }
+
+    /// Every annotation on an intervention that currently exists
+    pub fn current_annotations(&self) -> impl Iterator<Item = (&Intervention, &Annotation)> {
+        self.annotations
+            .iter()
+            .filter(|(intervention, _)| self.intervention_exists(**intervention))
+    }
+
+    /// Sets or clears the annotation, as one undoable edit
+    pub fn set_annotation(
+        &mut self,
+        intervention: Intervention,
+        annotation: Option<Annotation>,
+    ) -> Result<()> {
+        if !self.intervention_exists(intervention) {
+            bail!("Can't annotate {intervention:?}, because it doesn't exist");
+        }
+        self.apply_command(Command::SetAnnotation(intervention, annotation));
+        Ok(())
+    }
+
+    /// Extends a command that deletes an intervention to also clear its annotation, so that a new
+    /// intervention in the same place doesn't inherit it. Undoing restores both.
+    pub(crate) fn clearing_annotation(&self, cmd: Command, intervention: Intervention) -> Command {
+        if self.annotations.contains_key(&intervention) {
+            Command::Multiple(vec![cmd, Command::SetAnnotation(intervention, None)])
+        } else {
+            cmd
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_tests::{get_road_by_name, load_osm_xml};
+    use crate::FilterKind;
+
+    #[test]
+    fn deleting_a_filter_clears_its_annotation() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let r = get_road_by_name(&map, "west cross");
+        let pt = map.get_r(r).linestring.0[0];
+        let intervention = Intervention::ModalFilter { road: r };
+        let annotation = Annotation {
+            notes: "consulted".to_string(),
+            status: InterventionStatus::Approved,
+            reference: None,
+        };
+
+        map.add_modal_filter(pt, Some(vec![r]), FilterKind::WalkCycleOnly);
+        map.set_annotation(intervention, Some(annotation.clone()))
+            .unwrap();
+        map.delete_modal_filter(r, None);
+        assert!(map.annotations.is_empty());
+
+        // A new filter on the same road starts without the old annotation
+        map.add_modal_filter(pt, Some(vec![r]), FilterKind::NoEntry);
+        assert_eq!(map.get_annotation(intervention), None);
+
+        // Undoing the new filter and the deletion restores the annotation
+        map.undo().unwrap();
+        map.undo().unwrap();
+        assert_eq!(map.get_annotation(intervention), Some(&annotation));
+    }
+}
@@ -1,8 +1,12 @@
 use crate::boundary_stats::BoundaryStats;
 use crate::geo_helpers::buffer_polygon;
+use crate::neighbourhood::WayPoint;
 use crate::{MapModel, NeighbourhoodBoundary, NeighbourhoodDefinition};
 use anyhow::Result;
-use geo::{Area, BoundingRect, LineString, MultiPolygon, Polygon, Relate};
+use geo::{
+    Area, BooleanOps, BoundingRect, Contains, Coord, Intersects, LineString, MultiPolygon, Point,
+    Polygon, Rect, Relate,
+};
 use geojson::Feature;
 use rstar::RTree;
 use serde::{Deserialize, Serialize};
@@ -132,6 +136,80 @@ impl MapModel {
         boundaries
     }
 
+    /// Finds the smallest area around a point enclosed by the current main roads (including any
+    /// edits), railways, and waterways. Returns a definition with no name.
+    pub fn boundary_from_point(&self, pt: Coord) -> Result<NeighbourhoodDefinition> {
+        let boundary_mercator = self.mercator.to_mercator(&self.boundary_wgs84);
+        let Some(study_area) = boundary_mercator
+            .0
+            .into_iter()
+            .find(|polygon| polygon.contains(&Point::from(pt)))
+        else {
+            bail!("Point is outside the study area");
+        };
+        let splitters = RTree::bulk_load(
+            self.roads
+                .iter()
+                .filter(|r| self.is_main_road[&r.id])
+                .map(|r| &r.linestring)
+                .chain(self.railways.iter())
+                .chain(self.waterways.iter())
+                .cloned()
+                .collect(),
+        );
+
+        // Only split the part of the study area near the point, growing the window until the
+        // area found is enclosed by splitters, not by the edge of the window
+        let mut radius = 500.0;
+        loop {
+            let window = Rect::new(
+                Coord {
+                    x: pt.x - radius,
+                    y: pt.y - radius,
+                },
+                Coord {
+                    x: pt.x + radius,
+                    y: pt.y + radius,
+                },
+            )
+            .to_polygon();
+            let window_covers_everything = window.contains(&study_area);
+            let envelope = rstar::AABB::from_corners(
+                Point::new(pt.x - radius, pt.y - radius),
+                Point::new(pt.x + radius, pt.y + radius),
+            );
+
+            for clipped in study_area.intersection(&window) {
+                if !clipped.contains(&Point::from(pt)) {
+                    continue;
+                }
+                let Some(polygon) = split_polygon(
+                    &clipped,
+                    splitters.locate_in_envelope_intersecting(&envelope),
+                )
+                .into_iter()
+                .find(|polygon| polygon.contains(&Point::from(pt))) else {
+                    bail!("Point is on a main road");
+                };
+
+                if window_covers_everything || !polygon.intersects(window.exterior()) {
+                    let mut waypoints = WayPoint::waypoints_for_ring(polygon.exterior());
+                    self.mercator.to_wgs84_in_place(&mut waypoints);
+                    return Ok(NeighbourhoodDefinition {
+                        geometry: polygon,
+                        name: String::new(),
+                        waypoints: Some(waypoints.0),
+                    });
+                }
+            }
+
+            if window_covers_everything {
+                bail!("Couldn't find an area around the point");
+            }
+            radius *= 2.0;
+        }
+    }
+
     pub fn generate_merged_boundary(
         &self,
         boundaries_to_merge: Vec<Polygon>,
@@ -181,3 +259,77 @@ impl GeneratedBoundary {
         geojson::ser::to_feature(projected).expect("should have no unserializable fields")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_tests::{
+        get_roads_by_name, load_osm_xml, rectangle_neighbourhood, EAST_NEIGHBOURHOOD,
+        WEST_NEIGHBOURHOOD,
+    };
+    use crate::Neighbourhood;
+
+    fn load_map() -> MapModel {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        map.boundary_wgs84 = MultiPolygon::from(
+            Rect::new(
+                Coord {
+                    x: -0.116,
+                    y: 55.702,
+                },
+                Coord {
+                    x: -0.106,
+                    y: 55.708,
+                },
+            )
+            .to_polygon(),
+        );
+        map
+    }
+
+    /// Between the west main road and the west cross street
+    fn west_pt(map: &MapModel) -> Coord {
+        map.mercator.pt_to_mercator(Coord {
+            x: -0.114,
+            y: 55.7048,
+        })
+    }
+
+    fn boundary_neighbourhood(map: &MapModel, pt: Coord) -> Neighbourhood {
+        let definition = map.boundary_from_point(pt).unwrap();
+        Neighbourhood::new(map, NeighbourhoodBoundary::new(definition, None)).unwrap()
+    }
+
+    #[test]
+    fn enclosed_by_main_roads() {
+        let map = load_map();
+        let found = boundary_neighbourhood(&map, west_pt(&map));
+        let west = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        assert_eq!(found.interior_roads, west.interior_roads);
+        assert_eq!(found.main_roads, west.main_roads);
+
+        let outside = map.mercator.pt_to_mercator(Coord {
+            x: -0.12,
+            y: 55.7048,
+        });
+        assert!(map.boundary_from_point(outside).is_err());
+    }
+
+    #[test]
+    fn uses_edited_main_roads() {
+        let mut map = load_map();
+        for r in get_roads_by_name(&map, "middle") {
+            map.is_main_road.insert(r, false);
+        }
+        let found = boundary_neighbourhood(&map, west_pt(&map));
+
+        // Both neighbourhoods merge, with the middle road in the interior
+        let west = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        let east = rectangle_neighbourhood(&map, "east", EAST_NEIGHBOURHOOD);
+        assert!(found.interior_roads.is_superset(&west.interior_roads));
+        assert!(found.interior_roads.is_superset(&east.interior_roads));
+        for r in get_roads_by_name(&map, "middle") {
+            assert!(found.interior_roads.contains(&r));
+        }
+    }
+}
@@ -1,14 +1,12 @@
 use std::collections::{BTreeMap, BTreeSet};
 
-use geo::{Euclidean, Length};
-
 use crate::{IntersectionID, MapModel, Neighbourhood, Road, RoadID};
 
 /// A partitioning of the interior of a neighbourhood based on driving connectivity
 pub struct Cell {
-    /// Most roads are fully in one cell. Roads with modal filters on them are sometimes split
-    /// between two cells, and the PercentInterval indicates the split.
-    pub roads: BTreeMap<RoadID, PercentInterval>,
+    /// Most roads are fully in one cell. Roads with modal filters on them are split between
+    /// cells, and the PercentIntervals indicate which parts of the road are in this cell.
+    pub roads: BTreeMap<RoadID, Vec<PercentInterval>>,
     /// Intersections where this cell touches the boundary of the neighbourhood.
     pub border_intersections: BTreeSet<IntersectionID>,
     /// The cell only contains service roads and can be visually de-emphasized
@@ -51,43 +49,45 @@ impl Cell {
             cells.push(cell);
         }
 
-        // Filtered roads right along the perimeter have a tiny cell
-        for (r, filter) in &map.modal_filters {
+        // Filtered roads right along the perimeter have a tiny cell, and so does the stretch
+        // between two filters on one road
+        for (r, filters) in &map.modal_filters {
             let road = map.get_r(*r);
+            let first = filters[0].percent_along;
+            let last = filters.last().unwrap().percent_along;
             if neighbourhood.border_intersections.contains(&road.src_i) {
-                let mut cell = Cell {
-                    roads: BTreeMap::new(),
-                    border_intersections: BTreeSet::from([road.src_i]),
-                    unimportant: false,
-                };
-                cell.roads.insert(
-                    road.id,
-                    PercentInterval {
-                        start: 0.0,
-                        end: filter.percent_along,
-                    },
-                );
-                cells.push(cell);
+                cells.push(Cell::filtered_stretch(*r, 0.0, first, Some(road.src_i)));
             }
             if neighbourhood.border_intersections.contains(&road.dst_i) {
-                let mut cell = Cell {
-                    roads: BTreeMap::new(),
-                    border_intersections: BTreeSet::from([road.dst_i]),
-                    unimportant: false,
-                };
-                cell.roads.insert(
-                    road.id,
-                    PercentInterval {
-                        start: filter.percent_along,
-                        end: 1.0,
-                    },
-                );
-                cells.push(cell);
+                cells.push(Cell::filtered_stretch(*r, last, 1.0, Some(road.dst_i)));
+            }
+            if neighbourhood.interior_roads.contains(r) {
+                for pair in filters.windows(2) {
+                    cells.push(Cell::filtered_stretch(
+                        *r,
+                        pair[0].percent_along,
+                        pair[1].percent_along,
+                        None,
+                    ));
+                }
             }
         }
 
         cells
     }
+
+    fn filtered_stretch(
+        r: RoadID,
+        start: f64,
+        end: f64,
+        border_intersection: Option<IntersectionID>,
+    ) -> Cell {
+        Cell {
+            roads: BTreeMap::from([(r, vec![PercentInterval { start, end }])]),
+            border_intersections: border_intersection.into_iter().collect(),
+            unimportant: false,
+        }
+    }
 }
 
 /// An interval of percentages along a road's length, with start < end.
@@ -97,7 +97,7 @@ pub struct PercentInterval {
 }
 
 fn floodfill(map: &MapModel, start: RoadID, neighbourhood: &Neighbourhood) -> Cell {
-    let mut visited_roads: BTreeMap<RoadID, PercentInterval> = BTreeMap::new();
+    let mut visited_roads: BTreeMap<RoadID, Vec<PercentInterval>> = BTreeMap::new();
     let mut cell_borders = BTreeSet::new();
     // We don't need a priority queue
     let mut queue = vec![start];
@@ -112,10 +112,10 @@ fn floodfill(map: &MapModel, start: RoadID, neighbourhood: &Neighbourhood) -> Ce
         }
         visited_roads.insert(
             current.id,
-            PercentInterval {
+            vec![PercentInterval {
                 start: 0.0,
                 end: 1.0,
-            },
+            }],
         );
 
         for i in [current.src_i, current.dst_i] {
@@ -135,34 +135,30 @@ fn floodfill(map: &MapModel, start: RoadID, neighbourhood: &Neighbourhood) -> Ce
                         continue;
                     }
                 }
-                if let Some(ref filter) = map.modal_filters.get(next) {
-                    // Which ends of the filtered road have we reached?
-                    let mut visited_start = next_road.src_i == i;
-                    let mut visited_end = next_road.dst_i == i;
-                    // We may have visited previously from the other side.
-                    if let Some(interval) = visited_roads.get(next) {
-                        if interval.start == 0.0 {
-                            visited_start = true;
-                        }
-                        if interval.end == Euclidean.length(&next_road.linestring) {
-                            visited_end = true;
-                        }
-                    }
-                    visited_roads.insert(
-                        *next,
-                        PercentInterval {
-                            start: if visited_start {
-                                0.0
-                            } else {
-                                filter.percent_along
+                if let Some(filters) = map.modal_filters.get(next) {
+                    // Only the stretch up to the closest filter is reachable from this end. We
+                    // may have visited previously from the other side.
+                    let intervals = visited_roads.entry(*next).or_default();
+                    for (reached, interval) in [
+                        (
+                            next_road.src_i == i,
+                            PercentInterval {
+                                start: 0.0,
+                                end: filters[0].percent_along,
                             },
-                            end: if visited_end {
-                                1.0
-                            } else {
-                                filter.percent_along
+                        ),
+                        (
+                            next_road.dst_i == i,
+                            PercentInterval {
+                                start: filters.last().unwrap().percent_along,
+                                end: 1.0,
                             },
-                        },
-                    );
+                        ),
+                    ] {
+                        if reached && !intervals.iter().any(|x| x.start == interval.start) {
+                            intervals.push(interval);
+                        }
+                    }
                     continue;
                 }
 
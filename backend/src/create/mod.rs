@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 
 use anyhow::Result;
 use geo::line_measures::InterpolatableLine;
@@ -9,6 +9,8 @@ use rstar::{primitives::GeomWithData, RTree};
 use utils::osm2graph::{EdgeID, Graph};
 
 use crate::boundary_stats::ContextData;
+use crate::map_model::{Direction, ViaWayRestriction};
+use crate::scenarios::DEFAULT_SCENARIO;
 use crate::{
     impact::Impact, od::DemandModel, FilterKind, Intersection, IntersectionID, MapModel, Road,
     RoadID, Router, TravelFlow,
@@ -62,6 +64,9 @@ pub fn create_from_osm(
     for ls in &mut osm.waterways {
         graph.mercator.to_mercator_in_place(ls);
     }
+    for path in &mut osm.active_travel_paths {
+        graph.mercator.to_mercator_in_place(&mut path.linestring);
+    }
 
     info!("Building RTrees");
     let closest_road = RTree::bulk_load(
@@ -81,9 +86,11 @@ pub fn create_from_osm(
 
     let mut travel_flows = BTreeMap::new();
     let mut is_main_road = BTreeMap::new();
+    let mut speed_limits = BTreeMap::new();
     for r in &roads {
         travel_flows.insert(r.id, TravelFlow::from_osm(&r.tags));
         is_main_road.insert(r.id, r.is_severance());
+        speed_limits.insert(r.id, r.speed_mph);
     }
 
     let num_intersections = intersections.len();
@@ -100,10 +107,13 @@ pub fn create_from_osm(
 
         railways: osm.railways,
         waterways: osm.waterways,
+        active_travel_paths: osm.active_travel_paths,
 
         router_before: Router::empty(),
         router_after: None,
         router_before_with_penalty: None,
+        profile_routers_before: HashMap::new(),
+        profile_routers_after: HashMap::new(),
 
         original_modal_filters: BTreeMap::new(),
         modal_filters: BTreeMap::new(),
@@ -115,19 +125,28 @@ pub fn create_from_osm(
         original_turn_restrictions: std::iter::repeat_with(Vec::new)
             .take(num_intersections)
             .collect(),
+        via_way_restrictions: Vec::new(),
 
         travel_flows,
         is_main_road,
+        speed_limits,
+        annotations: BTreeMap::new(),
 
         impact: Some(Impact::default()),
         demand: None,
 
         undo_stack: Vec::new(),
         redo_stack: Vec::new(),
+        history_owner: None,
+        other_histories: BTreeMap::new(),
+        current_scenario: DEFAULT_SCENARIO.to_string(),
+        scenarios: BTreeMap::new(),
         boundaries: BTreeMap::new(),
         serialized_context_data,
         context_data: None,
         hide_unimportant_cells: false,
+        demand_weighted_shortcuts: false,
+        exact_cell_polygons: false,
     };
     if let Some(mut demand) = demand {
         info!("Load demand data");
@@ -250,38 +269,152 @@ fn apply_existing_filters(
     map.redo_stack.clear();
 }
 
-fn apply_turn_restrictions(
-    map: &mut MapModel,
-    mut turn_restrictions: HashMap<NodeID, Vec<(WayID, WayID)>>,
-) {
-    for intersection in &map.intersections {
-        if let Some(list) = turn_restrictions.remove(&intersection.node) {
-            for (from_way, to_way) in list {
+fn apply_turn_restrictions(map: &mut MapModel, turn_restrictions: Vec<parse::OsmTurnRestriction>) {
+    let intersections_by_node: HashMap<NodeID, IntersectionID> =
+        map.intersections.iter().map(|i| (i.node, i.id)).collect();
+    let mut roads_by_way: HashMap<WayID, Vec<RoadID>> = HashMap::new();
+    for road in &map.roads {
+        roads_by_way.entry(road.way).or_default().push(road.id);
+    }
+
+    for restriction in turn_restrictions {
+        match restriction.via {
+            parse::OsmTurnRestrictionVia::Node(node) => {
+                let Some(i) = intersections_by_node.get(&node) else {
+                    continue;
+                };
                 // One OSM way turns into multiple Roads. The restriction only makes sense on the
                 // Road connected to this intersection. So search only this intersection's roads.
+                let intersection = map.get_i(*i);
                 let mut from = None;
                 let mut to = None;
                 for r in &intersection.roads {
                     let way = map.roads[r.0].way;
-                    if way == from_way {
+                    if way == restriction.from {
                         from = Some(*r);
-                    } else if way == to_way {
+                    } else if way == restriction.to {
                         to = Some(*r);
                     }
                 }
 
                 if let (Some(from), Some(to)) = (from, to) {
+                    let banned = banned_turns(map, *i, from, to, restriction.only);
                     // Set this directly; don't bother with Command and then fixing the undo/redo
                     // queues
-                    map.original_turn_restrictions[intersection.id.0].push((from, to));
+                    map.original_turn_restrictions[i.0].extend(banned);
+                }
+            }
+            parse::OsmTurnRestrictionVia::Ways(via_ways) => {
+                let Some((path, exit_i)) = find_via_way_path(
+                    map,
+                    &roads_by_way,
+                    restriction.from,
+                    &via_ways,
+                    restriction.to,
+                ) else {
+                    continue;
+                };
+                for (_, banned_to) in
+                    banned_turns(map, exit_i, path.from.0, path.to, restriction.only)
+                {
+                    if path.via.is_empty() {
+                        // Collapsing a dog-leg can remove the via road entirely
+                        map.original_turn_restrictions[exit_i.0].push((path.from.0, banned_to));
+                    } else {
+                        map.via_way_restrictions.push(ViaWayRestriction {
+                            from: path.from,
+                            via: path.via.clone(),
+                            to: banned_to,
+                        });
+                    }
                 }
             }
         }
     }
 
+    for list in &mut map.original_turn_restrictions {
+        list.sort();
+        list.dedup();
+    }
     map.turn_restrictions = map.original_turn_restrictions.clone();
 }
 
+/// Expands a restriction at one intersection into banned (from, to) movements. For `only`
+/// restrictions, every other road is banned, including a U-turn back onto `from`. `from` may not
+/// be connected to the intersection directly, for via-way restrictions.
+fn banned_turns(
+    map: &MapModel,
+    i: IntersectionID,
+    from: RoadID,
+    to: RoadID,
+    only: bool,
+) -> Vec<(RoadID, RoadID)> {
+    if !only {
+        return vec![(from, to)];
+    }
+    map.get_i(i)
+        .roads
+        .iter()
+        .filter(|r| **r != to)
+        .map(|r| (from, *r))
+        .collect()
+}
+
+/// Finds the shortest sequence of roads belonging to `via_ways` connecting a road from `from_way`
+/// to one from `to_way`. Also returns the intersection at the end of the via roads.
+fn find_via_way_path(
+    map: &MapModel,
+    roads_by_way: &HashMap<WayID, Vec<RoadID>>,
+    from_way: WayID,
+    via_ways: &[WayID],
+    to_way: WayID,
+) -> Option<(ViaWayRestriction, IntersectionID)> {
+    let via_roads: BTreeSet<RoadID> = via_ways
+        .iter()
+        .flat_map(|w| roads_by_way.get(w).into_iter().flatten().cloned())
+        .collect();
+    let to_roads = roads_by_way.get(&to_way)?;
+
+    // Breadth-first search, starting from both ends of every road on the from way
+    let mut queue = VecDeque::new();
+    for r in roads_by_way.get(&from_way)? {
+        let road = map.get_r(*r);
+        queue.push_back(((*r, Direction::Forwards), road.dst_i, Vec::new()));
+        queue.push_back(((*r, Direction::Backwards), road.src_i, Vec::new()));
+    }
+    let mut visited = BTreeSet::new();
+    while let Some((from, i, via)) = queue.pop_front() {
+        let last_road = via.last().map(|(r, _)| *r).unwrap_or(from.0);
+        let intersection = map.get_i(i);
+        if let Some(to) = intersection
+            .roads
+            .iter()
+            .find(|r| **r != last_road && to_roads.contains(r))
+        {
+            // The via roads must actually be crossed, unless they were removed
+            if !via.is_empty() || !intersection.roads.iter().any(|r| via_roads.contains(r)) {
+                return Some((ViaWayRestriction { from, via, to: *to }, i));
+            }
+        }
+
+        for r in &intersection.roads {
+            if !via_roads.contains(r) || !visited.insert(*r) {
+                continue;
+            }
+            let road = map.get_r(*r);
+            let (dir, next_i) = if road.src_i == i {
+                (Direction::Forwards, road.dst_i)
+            } else {
+                (Direction::Backwards, road.src_i)
+            };
+            let mut via = via.clone();
+            via.push((*r, dir));
+            queue.push_back((from, next_i, via));
+        }
+    }
+    None
+}
+
 // TODO Consider upstreaming to osm2graph
 fn remove_disconnected_components(graph: &mut Graph) {
     let mut scc_graph: UnGraphMap<utils::osm2graph::IntersectionID, EdgeID> = UnGraphMap::new();
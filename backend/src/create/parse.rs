@@ -5,15 +5,16 @@ use osm_reader::{NodeID, OsmID, RelationID, WayID};
 use utils::{osm2graph::OsmReader, Tags};
 
 use crate::boundary_stats::{POIKind, POI};
+use crate::permeability::ActiveTravelPath;
 
 #[derive(Default)]
 pub struct Osm {
     pub bus_routes_on_roads: HashMap<WayID, Vec<String>>,
     pub railways: Vec<LineString>,
     pub waterways: Vec<LineString>,
+    pub active_travel_paths: Vec<ActiveTravelPath>,
     pub barrier_nodes: BTreeSet<NodeID>,
-    // Only represent one case of restricted turns (from, to) on a particular node
-    pub turn_restrictions: HashMap<NodeID, Vec<(WayID, WayID)>>,
+    pub turn_restrictions: Vec<OsmTurnRestriction>,
     pub pois: Vec<POI>,
 }
 
@@ -56,6 +57,12 @@ impl OsmReader for Osm {
             self.waterways.push(LineString(
                 node_ids.into_iter().map(|n| node_mapping[&n]).collect(),
             ));
+        } else if let Some((walking, cycling)) = ActiveTravelPath::modes_from_osm(tags) {
+            self.active_travel_paths.push(ActiveTravelPath {
+                linestring: LineString(node_ids.into_iter().map(|n| node_mapping[&n]).collect()),
+                walking,
+                cycling,
+            });
         }
 
         self.pois.extend(get_poi(
@@ -82,54 +89,93 @@ impl OsmReader for Osm {
             }
         }
 
-        // https://wiki.openstreetmap.org/wiki/Relation:restriction describes many cases. Only handle
-        // the simplest: a banned turn involving exactly 2 ways and 1 node.
-        if tags.is("type", "restriction")
-            && tags.is_any(
-                "restriction",
-                vec![
-                    "no_right_turn",
-                    "no_left_turn",
-                    "no_u_turn",
-                    "no_straight_on",
-                ],
-            )
-        {
-            let mut from = None;
-            let mut via = None;
-            let mut to = None;
-            for (role, member) in members {
-                match member {
-                    OsmID::Way(w) => {
-                        if role == "from" && from.is_none() {
-                            from = Some(*w);
-                        } else if role == "to" && to.is_none() {
-                            to = Some(*w);
-                        } else {
-                            // Some other case, bail out
-                            return;
-                        }
-                    }
-                    OsmID::Node(n) => {
-                        if role == "via" && via.is_none() {
-                            via = Some(*n);
-                        } else {
-                            return;
-                        }
-                    }
-                    OsmID::Relation(_) => {
-                        return;
-                    }
-                }
+        if tags.is("type", "restriction") {
+            if let Some(restriction) = OsmTurnRestriction::parse(members, tags) {
+                self.turn_restrictions.push(restriction);
             }
+        }
+    }
+}
+
+/// A turn restriction from OSM that applies to motor vehicles
+pub struct OsmTurnRestriction {
+    pub from: WayID,
+    pub via: OsmTurnRestrictionVia,
+    pub to: WayID,
+    /// For `only_*` restrictions, every movement from `from` except to `to` is banned. Otherwise
+    /// only the movement to `to` is banned.
+    pub only: bool,
+}
+
+pub enum OsmTurnRestrictionVia {
+    Node(NodeID),
+    /// The ways crossed between `from` and `to`, not necessarily in order
+    Ways(Vec<WayID>),
+}
 
-            if let (Some(from), Some(via), Some(to)) = (from, via, to) {
-                self.turn_restrictions
-                    .entry(via)
-                    .or_insert_with(Vec::new)
-                    .push((from, to));
+impl OsmTurnRestriction {
+    /// https://wiki.openstreetmap.org/wiki/Relation:restriction describes many cases. Handle
+    /// restrictions with one `from` and `to` way, and a `via` node or one or more `via` ways.
+    fn parse(members: &Vec<(String, OsmID)>, tags: &Tags) -> Option<Self> {
+        // Restrictions specific to motor vehicles take precedence. Ones for other vehicles, like
+        // `restriction:bus`, don't matter.
+        let restriction = tags
+            .get("restriction:motorcar")
+            .or_else(|| tags.get("restriction:motor_vehicle"))
+            .or_else(|| tags.get("restriction"))?;
+        if let Some(except) = tags.get("except") {
+            if except
+                .split(';')
+                .any(|x| x.trim() == "motorcar" || x.trim() == "motor_vehicle")
+            {
+                return None;
             }
         }
+        let only = match restriction.as_str() {
+            "no_right_turn" | "no_left_turn" | "no_u_turn" | "no_straight_on" => false,
+            "only_right_turn" | "only_left_turn" | "only_u_turn" | "only_straight_on" => true,
+            // no_entry and no_exit have multiple from or to ways, and aren't handled yet
+            _ => return None,
+        };
+
+        let mut from = None;
+        let mut via_node = None;
+        let mut via_ways = Vec::new();
+        let mut to = None;
+        for (role, member) in members {
+            match (role.as_str(), member) {
+                ("from", OsmID::Way(w)) if from.is_none() => {
+                    from = Some(*w);
+                }
+                ("to", OsmID::Way(w)) if to.is_none() => {
+                    to = Some(*w);
+                }
+                ("via", OsmID::Node(n)) if via_node.is_none() && via_ways.is_empty() => {
+                    via_node = Some(*n);
+                }
+                ("via", OsmID::Way(w)) if via_node.is_none() => {
+                    via_ways.push(*w);
+                }
+                // Some other case, bail out
+                _ => {
+                    return None;
+                }
+            }
+        }
+
+        let via = if let Some(n) = via_node {
+            OsmTurnRestrictionVia::Node(n)
+        } else if !via_ways.is_empty() {
+            OsmTurnRestrictionVia::Ways(via_ways)
+        } else {
+            return None;
+        };
+        Some(Self {
+            from: from?,
+            via,
+            to: to?,
+            only,
+        })
     }
 }
 
@@ -0,0 +1,339 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use geo::LineString;
+use geojson::FeatureCollection;
+use rstar::{primitives::GeomWithData, RTree};
+
+use crate::map_model::{DiagonalFilter, Direction, ViaWayRestriction};
+use crate::route::{Router, RouterInput};
+use crate::{
+    Intersection, IntersectionID, MapModel, ModalFilter, Neighbourhood, Position, Road, RoadID,
+    TravelFlow,
+};
+
+/// A shortcut that cuts through two neighbourhoods, crossing between them on a road that's a main
+/// road for both. `Shortcuts` can't see these, because each neighbourhood only
+/// considers routes entering and exiting through its own main roads.
+pub struct CrossNeighbourhoodShortcut {
+    pub steps: Vec<(RoadID, Direction)>,
+    /// Names of the two neighbourhoods passed through, in order. A neighbourhood may appear more
+    /// than once, if the shortcut crosses back and forth.
+    pub neighbourhoods: Vec<String>,
+    /// Main roads shared between neighbourhoods that this shortcut uses
+    pub boundary_roads: Vec<RoadID>,
+}
+
+/// Finds shortcuts after edits that start on a main road of one neighbourhood and end on a main
+/// road of a different neighbourhood, only using interior roads and main roads shared between
+/// the two neighbourhoods in between.
+pub fn find_cross_neighbourhood_shortcuts(
+    map: &MapModel,
+    neighbourhoods: &Vec<&Neighbourhood>,
+) -> Vec<CrossNeighbourhoodShortcut> {
+    let mut interior_owner: HashMap<RoadID, usize> = HashMap::new();
+    let mut main_road_owners: HashMap<RoadID, usize> = HashMap::new();
+    for (idx, neighbourhood) in neighbourhoods.iter().enumerate() {
+        for r in &neighbourhood.interior_roads {
+            interior_owner.insert(*r, idx);
+        }
+        for r in &neighbourhood.main_roads {
+            *main_road_owners.entry(*r).or_insert(0) += 1;
+        }
+    }
+
+    // Every main road leading into a neighbourhood that isn't shared with another one
+    let mut entries: Vec<BTreeSet<RoadID>> = vec![BTreeSet::new(); neighbourhoods.len()];
+    for (idx, neighbourhood) in neighbourhoods.iter().enumerate() {
+        for i in &neighbourhood.border_intersections {
+            for r in &map.get_i(*i).roads {
+                if neighbourhood.main_roads.contains(r) && main_road_owners[r] == 1 {
+                    entries[idx].insert(*r);
+                }
+            }
+        }
+    }
+
+    let mut results = Vec::new();
+    for idx1 in 0..neighbourhoods.len() {
+        for idx2 in (idx1 + 1)..neighbourhoods.len() {
+            let shared_main_roads: BTreeSet<RoadID> = neighbourhoods[idx1]
+                .main_roads
+                .intersection(&neighbourhoods[idx2].main_roads)
+                .cloned()
+                .collect();
+            if shared_main_roads.is_empty() {
+                continue;
+            }
+
+            // Only the two neighbourhoods are routable. Other main roads, including the start and
+            // end of each shortcut, aren't part of the graph, so routes can only finish on them.
+            let router_input = CrossNeighbourhoodRouterInput {
+                map,
+                roads: neighbourhoods[idx1]
+                    .interior_roads
+                    .iter()
+                    .chain(&neighbourhoods[idx2].interior_roads)
+                    .chain(&shared_main_roads)
+                    .cloned()
+                    .collect(),
+            };
+            // Like Shortcuts, heavily penalize using main roads
+            let router = Router::new(&router_input, 2.0);
+
+            for (start_idx, end_idx) in [(idx1, idx2), (idx2, idx1)] {
+                for start_r in &entries[start_idx] {
+                    let starts = entry_steps(map, neighbourhoods[start_idx], *start_r);
+                    for end_r in &entries[end_idx] {
+                        let end = Position {
+                            road: *end_r,
+                            percent_along: 0.5,
+                        };
+                        let Some(route) = router.route_from_any(&router_input, &starts, end) else {
+                            continue;
+                        };
+                        if let Some(shortcut) = CrossNeighbourhoodShortcut::new(
+                            map,
+                            neighbourhoods,
+                            &interior_owner,
+                            &shared_main_roads,
+                            *start_r,
+                            route.steps,
+                        ) {
+                            results.push(shortcut);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    results
+}
+
+/// Every interior road and direction that a vehicle on `main_road` can turn into
+fn entry_steps(
+    map: &MapModel,
+    neighbourhood: &Neighbourhood,
+    main_road: RoadID,
+) -> Vec<(RoadID, Direction)> {
+    let road = map.get_r(main_road);
+    let router_input = map.router_input_after();
+    let mut steps = Vec::new();
+    for i in [road.src_i, road.dst_i] {
+        if !neighbourhood.border_intersections.contains(&i) {
+            continue;
+        }
+        for (next, dir) in map
+            .get_i(i)
+            .allowed_movements_from(main_road, &router_input)
+        {
+            if neighbourhood.interior_roads.contains(&next) {
+                steps.push((next, dir));
+            }
+        }
+    }
+    steps
+}
+
+impl CrossNeighbourhoodShortcut {
+    /// `steps` goes from an interior road to the main road where the shortcut ends. Returns
+    /// `None` if the route doesn't cross between the neighbourhoods on a shared main road.
+    fn new(
+        map: &MapModel,
+        neighbourhoods: &Vec<&Neighbourhood>,
+        interior_owner: &HashMap<RoadID, usize>,
+        shared_main_roads: &BTreeSet<RoadID>,
+        start_r: RoadID,
+        mut steps: Vec<(RoadID, Direction)>,
+    ) -> Option<Self> {
+        let mut visited: Vec<usize> = Vec::new();
+        let mut boundary_roads = Vec::new();
+        for (r, _) in &steps[..steps.len() - 1] {
+            if let Some(idx) = interior_owner.get(r) {
+                if visited.last() != Some(idx) {
+                    visited.push(*idx);
+                }
+            } else if shared_main_roads.contains(r) && !boundary_roads.contains(r) {
+                boundary_roads.push(*r);
+            }
+        }
+        if boundary_roads.is_empty() || visited.iter().all(|x| *x == visited[0]) {
+            return None;
+        }
+
+        // Start with the main road, driving towards the first interior road
+        let (first_road, first_dir) = steps[0];
+        let entered_at = if first_dir == Direction::Forwards {
+            map.get_r(first_road).src_i
+        } else {
+            map.get_r(first_road).dst_i
+        };
+        steps.insert(
+            0,
+            (
+                start_r,
+                Direction::forwards(map.get_r(start_r).dst_i == entered_at),
+            ),
+        );
+
+        Some(Self {
+            steps,
+            neighbourhoods: visited
+                .into_iter()
+                .map(|idx| neighbourhoods[idx].name().to_string())
+                .collect(),
+            boundary_roads,
+        })
+    }
+}
+
+/// Each shortcut as a LineString, followed by each boundary road used, with the number of
+/// shortcuts using it.
+pub fn cross_neighbourhood_shortcuts_to_gj(
+    map: &MapModel,
+    shortcuts: &Vec<CrossNeighbourhoodShortcut>,
+) -> FeatureCollection {
+    let mut features = Vec::new();
+    let mut count_per_boundary_road: BTreeMap<RoadID, usize> = BTreeMap::new();
+    for shortcut in shortcuts {
+        let mut pts = Vec::new();
+        for (r, direction) in &shortcut.steps {
+            let road = map.get_r(*r);
+            if *direction == Direction::Forwards {
+                pts.extend(road.linestring.0.iter().cloned());
+            } else {
+                pts.extend(road.linestring.0.iter().rev().cloned());
+            }
+        }
+        let mut f = map.mercator.to_wgs84_gj(&LineString::new(pts));
+        f.set_property("kind", "shortcut");
+        f.set_property("neighbourhoods", shortcut.neighbourhoods.clone());
+        f.set_property(
+            "boundary_roads",
+            shortcut
+                .boundary_roads
+                .iter()
+                .map(|r| r.0)
+                .collect::<Vec<_>>(),
+        );
+        features.push(f);
+
+        for r in &shortcut.boundary_roads {
+            *count_per_boundary_road.entry(*r).or_insert(0) += 1;
+        }
+    }
+
+    for (r, count) in count_per_boundary_road {
+        let mut f = map.get_r(r).to_gj(&map.mercator);
+        f.set_property("kind", "boundary_road");
+        f.set_property("shortcuts", count);
+        features.push(f);
+    }
+
+    FeatureCollection {
+        features,
+        bbox: None,
+        foreign_members: None,
+    }
+}
+
+struct CrossNeighbourhoodRouterInput<'a> {
+    map: &'a MapModel,
+    roads: BTreeSet<RoadID>,
+}
+
+impl RouterInput for CrossNeighbourhoodRouterInput<'_> {
+    fn roads_iter(&self) -> impl Iterator<Item = &Road> {
+        self.roads.iter().map(|r| self.map.get_r(*r))
+    }
+
+    fn closest_road(&self) -> &RTree<GeomWithData<LineString, RoadID>> {
+        &self.map.closest_road
+    }
+
+    fn get_r(&self, r: RoadID) -> &Road {
+        self.map.get_r(r)
+    }
+
+    fn get_i(&self, i: IntersectionID) -> &Intersection {
+        self.map.get_i(i)
+    }
+
+    fn modal_filters(&self, r: RoadID) -> &[ModalFilter] {
+        self.map
+            .modal_filters
+            .get(&r)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn travel_flow(&self, r: RoadID) -> TravelFlow {
+        self.map.travel_flows[&r]
+    }
+
+    fn speed_mph(&self, r: RoadID) -> f64 {
+        self.map.speed_limits[&r]
+    }
+
+    fn diagonal_filter(&self, i: IntersectionID) -> Option<&DiagonalFilter> {
+        self.map.diagonal_filters.get(&i)
+    }
+
+    fn turn_restrictions(&self, i: IntersectionID) -> &Vec<(RoadID, RoadID)> {
+        &self.map.turn_restrictions[i.0]
+    }
+
+    fn via_way_restrictions(&self) -> &Vec<ViaWayRestriction> {
+        &self.map.via_way_restrictions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_tests::{
+        get_roads_by_name, load_osm_xml, rectangle_neighbourhood, EAST_NEIGHBOURHOOD,
+        WEST_NEIGHBOURHOOD,
+    };
+
+    #[test]
+    fn shortcuts_cross_the_middle_road() {
+        let map = load_osm_xml("two_neighbourhoods");
+        let west = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        let east = rectangle_neighbourhood(&map, "east", EAST_NEIGHBOURHOOD);
+        let middle = get_roads_by_name(&map, "middle");
+
+        let shortcuts = find_cross_neighbourhood_shortcuts(&map, &vec![&west, &east]);
+        assert!(!shortcuts.is_empty());
+        for shortcut in &shortcuts {
+            let (start, _) = shortcut.steps[0];
+            let (end, _) = *shortcut.steps.last().unwrap();
+            let (from, to) = if west.main_roads.contains(&start) {
+                (&west, &east)
+            } else {
+                (&east, &west)
+            };
+            assert!(from.main_roads.contains(&start) && !to.main_roads.contains(&start));
+            assert!(to.main_roads.contains(&end) && !from.main_roads.contains(&end));
+            assert_eq!(shortcut.neighbourhoods[0], from.name());
+            assert_eq!(shortcut.neighbourhoods.last().unwrap(), to.name());
+            assert!(shortcut.boundary_roads.iter().all(|r| middle.contains(r)));
+
+            // Everything in between is inside one of the neighbourhoods
+            for (r, _) in &shortcut.steps[1..shortcut.steps.len() - 1] {
+                assert!(
+                    west.interior_roads.contains(r)
+                        || east.interior_roads.contains(r)
+                        || middle.contains(r)
+                );
+            }
+        }
+
+        // Shortcuts in each direction
+        assert!(shortcuts
+            .iter()
+            .any(|shortcut| shortcut.neighbourhoods[0] == "west"));
+        assert!(shortcuts
+            .iter()
+            .any(|shortcut| shortcut.neighbourhoods[0] == "east"));
+    }
+}
@@ -1,7 +1,8 @@
 use anyhow::Result;
 use geo::{
-    BooleanOps, Buffer, Coord, Distance, Euclidean, Intersects, Length, Line, LineIntersection,
-    LineLocatePoint, LineString, MultiPolygon, Point, Polygon, Validation,
+    BooleanOps, Buffer, Coord, Densify, Distance, Euclidean, InterpolatableLine, Intersects,
+    Length, Line, LineIntersection, LineLocatePoint, LineString, MultiPolygon, Point, Polygon,
+    Validation,
 };
 
 /// Looks for the first place ls2 crosses ls1. Returns the percent_along ls1 of that point.
@@ -25,6 +26,55 @@ pub fn linestring_intersection(ls1: &LineString, ls2: &LineString) -> Option<f64
     None
 }
 
+/// Checks if ls1 runs alongside ls2, staying within `threshold` meters of it. Returns true if ls1
+/// points the same way as ls2, false if it points the opposite way, or None if it doesn't follow
+/// ls2.
+pub fn linestring_follows(ls1: &LineString, ls2: &LineString, threshold: f64) -> Option<bool> {
+    let num_samples = 10;
+    for idx in 0..=num_samples {
+        let pt = ls1.point_at_ratio_from_start(&Euclidean, idx as f64 / num_samples as f64)?;
+        if Euclidean.distance(&pt, ls2) > threshold {
+            return None;
+        }
+    }
+    let start = ls2.line_locate_point(&(*ls1.0.first()?).into())?;
+    let end = ls2.line_locate_point(&(*ls1.0.last()?).into())?;
+    if start == end {
+        return None;
+    }
+    Some(start < end)
+}
+
+/// The discrete Fréchet distance between two linestrings, after adding points so no segment is
+/// longer than `step` meters. Small when the lines have the same shape and direction; reverse one
+/// line to compare the other way.
+pub fn frechet_distance(ls1: &LineString, ls2: &LineString, step: f64) -> f64 {
+    let pts1 = Euclidean.densify(ls1, step).0;
+    let pts2 = Euclidean.densify(ls2, step).0;
+    if pts1.is_empty() || pts2.is_empty() {
+        return f64::INFINITY;
+    }
+
+    // Only keep the previous row of the dynamic programming table
+    let mut prev: Vec<f64> = Vec::with_capacity(pts2.len());
+    let mut current: Vec<f64> = Vec::with_capacity(pts2.len());
+    for (i, pt1) in pts1.iter().enumerate() {
+        current.clear();
+        for (j, pt2) in pts2.iter().enumerate() {
+            let dist = Euclidean.distance(*pt1, *pt2);
+            let best_before = match (i, j) {
+                (0, 0) => 0.0,
+                (0, _) => current[j - 1],
+                (_, 0) => prev[0],
+                _ => prev[j].min(prev[j - 1]).min(current[j - 1]),
+            };
+            current.push(dist.max(best_before));
+        }
+        std::mem::swap(&mut prev, &mut current);
+    }
+    prev[pts2.len() - 1]
+}
+
 /// Buffers a polygon, returning the largest of the output Polygons
 ///
 /// Buffering can leave floating artifacts.
@@ -392,6 +442,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_linestring_follows() {
+        let line = wkt!(LINESTRING(0. 0.,100. 0.,100. 100.));
+        assert_eq!(
+            Some(true),
+            linestring_follows(&wkt!(LINESTRING(10. 2.,90. 3.)), &line, 5.0)
+        );
+        assert_eq!(
+            Some(false),
+            linestring_follows(&wkt!(LINESTRING(101. 90.,99. 10.)), &line, 5.0)
+        );
+        // Crossing isn't following
+        assert_eq!(
+            None,
+            linestring_follows(&wkt!(LINESTRING(50. -50.,50. 50.)), &line, 5.0)
+        );
+    }
+
+    #[test]
+    fn test_frechet_distance() {
+        let line = wkt!(LINESTRING(0. 0.,100. 0.));
+        // Shifted sideways
+        assert_relative_eq!(
+            3.0,
+            frechet_distance(&line, &wkt!(LINESTRING(0. 3.,100. 3.)), 1.0)
+        );
+        // Reversed, the start of one line is far from the start of the other
+        assert_relative_eq!(
+            100.0,
+            frechet_distance(&line, &wkt!(LINESTRING(100. 0.,0. 0.)), 1.0)
+        );
+        // Same endpoints, but a detour in the middle
+        assert_relative_eq!(
+            40.0,
+            frechet_distance(&line, &wkt!(LINESTRING(0. 0.,50. 40.,100. 0.)), 1.0),
+            epsilon = 1.0
+        );
+    }
+
     #[test]
     fn test_split_bearing() {
         assert_eq!(45.0, split_bearing(0., 90.));
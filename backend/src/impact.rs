@@ -34,24 +34,10 @@ impl Impact {
     /// Returns a feature per road, with `before` and `after` counts, and a `max_count` foreign
     /// member
     pub fn recalculate(&mut self, map: &MapModel, fast_sample: bool) -> FeatureCollection {
-        // Which requests are we using?
+        self.calculate_requests(map, fast_sample);
         let requests = if fast_sample {
-            if self.sampled_requests.is_empty() {
-                info!("Calculating a fast sample of requests");
-                self.sampled_requests = match &map.demand {
-                    Some(demand) => demand.make_requests(fast_sample),
-                    None => od::synthetic_od_requests(map),
-                };
-            }
             &self.sampled_requests
         } else {
-            if self.all_requests.is_empty() {
-                info!("Calculating all requests");
-                self.all_requests = match &map.demand {
-                    Some(demand) => demand.make_requests(fast_sample),
-                    None => od::synthetic_od_requests(map),
-                };
-            }
             &self.all_requests
         };
 
@@ -78,34 +64,47 @@ impl Impact {
         }
         self.last_fast_sample = fast_sample;
 
-        let mut features = Vec::new();
-        let mut max_count = 0;
-        for road in &map.roads {
-            let before = self.counts_before.get(&road.id).cloned().unwrap_or(0);
-            let after = self.counts_after.get(&road.id).cloned().unwrap_or(0);
-            // Don't show unchanged roads, but to scale the absolute counts, do look at the max
-            // count seen anywhere
-            max_count = max_count.max(before.max(after));
-            if before != after && (before > 0 || after > 0) {
-                let mut f = map.mercator.to_wgs84_gj(&road.linestring);
-                f.set_property("id", road.id.0);
-                f.set_property("before", before);
-                f.set_property("after", after);
-                features.push(f);
-            }
-        }
+        counts_to_gj(map, &self.counts_before, &self.counts_after)
+    }
+
+    /// Counts routes per road using the current edits. Unlike `recalculate`, this doesn't
+    /// remember the result, so it can be used for scenarios besides the current one.
+    pub fn counts_after_edits(
+        &mut self,
+        map: &MapModel,
+        fast_sample: bool,
+    ) -> HashMap<RoadID, usize> {
+        self.calculate_requests(map, fast_sample);
+        let requests = if fast_sample {
+            &self.sampled_requests
+        } else {
+            &self.all_requests
+        };
+        info!(
+            "Calculating impacts after edits ({} requests)",
+            requests.len()
+        );
+        map.router_after
+            .as_ref()
+            .expect("need to rebuild_router")
+            .od_to_counts(&map.router_input_after(), requests)
+    }
 
-        FeatureCollection {
-            features,
-            bbox: None,
-            foreign_members: Some(
-                serde_json::json!({
-                    "max_count": max_count,
-                })
-                .as_object()
-                .unwrap()
-                .clone(),
-            ),
+    fn calculate_requests(&mut self, map: &MapModel, fast_sample: bool) {
+        if fast_sample {
+            if self.sampled_requests.is_empty() {
+                info!("Calculating a fast sample of requests");
+                self.sampled_requests = match &map.demand {
+                    Some(demand) => demand.make_requests(fast_sample),
+                    None => od::synthetic_od_requests(map),
+                };
+            }
+        } else if self.all_requests.is_empty() {
+            info!("Calculating all requests");
+            self.all_requests = match &map.demand {
+                Some(demand) => demand.make_requests(fast_sample),
+                None => od::synthetic_od_requests(map),
+            };
         }
     }
 
@@ -160,3 +159,41 @@ impl Impact {
         changed_paths
     }
 }
+
+/// Returns a feature per road with different counts, with `before` and `after` counts, and a
+/// `max_count` foreign member
+pub fn counts_to_gj(
+    map: &MapModel,
+    counts_before: &HashMap<RoadID, usize>,
+    counts_after: &HashMap<RoadID, usize>,
+) -> FeatureCollection {
+    let mut features = Vec::new();
+    let mut max_count = 0;
+    for road in &map.roads {
+        let before = counts_before.get(&road.id).cloned().unwrap_or(0);
+        let after = counts_after.get(&road.id).cloned().unwrap_or(0);
+        // Don't show unchanged roads, but to scale the absolute counts, do look at the max
+        // count seen anywhere
+        max_count = max_count.max(before.max(after));
+        if before != after && (before > 0 || after > 0) {
+            let mut f = map.mercator.to_wgs84_gj(&road.linestring);
+            f.set_property("id", road.id.0);
+            f.set_property("before", before);
+            f.set_property("after", after);
+            features.push(f);
+        }
+    }
+
+    FeatureCollection {
+        features,
+        bbox: None,
+        foreign_members: Some(
+            serde_json::json!({
+                "max_count": max_count,
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ),
+    }
+}
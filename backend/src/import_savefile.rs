@@ -0,0 +1,502 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use geo::{Intersects, Polygon};
+use geojson::{Feature, FeatureCollection};
+
+use crate::annotations::Intervention;
+use crate::map_model::{Command, SavefileEdit};
+use crate::savefile_matching::{InvalidFeature, Match};
+use crate::savefile_migrations::migrate_savefile;
+use crate::{IntersectionID, MapModel, ModalFilter, RoadID, TravelFlow};
+
+/// Which edits to import from another savefile
+pub enum ImportSelection {
+    /// Edits within a named boundary from the other savefile
+    Boundary(String),
+    /// Edits within a polygon, in WGS84
+    Polygon(Polygon),
+}
+
+// Boundaries and the study area aren't interventions
+const IMPORTED_KINDS: [&str; 8] = [
+    "modal_filter",
+    "deleted_existing_modal_filter",
+    "travel_flow",
+    "main_road",
+    "speed_limit",
+    "turn_restriction",
+    "deleted_existing_turn_restriction",
+    "annotation",
+];
+
+impl MapModel {
+    /// Copies edits from another savefile into the current scenario, as one undoable edit. Only
+    /// edits from the other savefile's current scenario are used. Edits that disagree with an
+    /// edit already made here, or that don't match any road or intersection here, are skipped.
+    ///
+    /// Returns the skipped features from the other savefile, each with a `conflict` property
+    /// explaining why, and `num_imported` and `num_conflicts` foreign members. Malformed features
+    /// are skipped too, with an `invalid` property instead, and counted in `num_invalid`. Edits
+    /// that were imported onto a guessed match are also returned, with a `low_confidence` reason,
+    /// and counted in `num_low_confidence`.
+    pub fn import_from_savefile(
+        &mut self,
+        mut gj: FeatureCollection,
+        selection: ImportSelection,
+    ) -> Result<FeatureCollection> {
+        migrate_savefile(&mut gj)?;
+        let polygon = match selection {
+            ImportSelection::Boundary(name) => {
+                let Some(f) = gj.features.iter().find(|f| {
+                    f.property("kind").and_then(|x| x.as_str()) == Some("boundary")
+                        && f.property("name").and_then(|x| x.as_str()) == Some(name.as_str())
+                }) else {
+                    bail!("No boundary named {name} in the other savefile");
+                };
+                Polygon::try_from(f.clone())?
+            }
+            ImportSelection::Polygon(polygon) => polygon,
+        };
+
+        let mut cmds = Vec::new();
+        // The final list of turn restrictions at each changed intersection
+        let mut turn_restrictions: BTreeMap<IntersectionID, Vec<(RoadID, RoadID)>> =
+            BTreeMap::new();
+        // The other savefile has all of the filters for each road it changed, so they replace
+        // the filters here. Also remember the features, in case the whole list conflicts.
+        let mut modal_filters: BTreeMap<RoadID, (Vec<ModalFilter>, Vec<Feature>)> = BTreeMap::new();
+        let mut conflicts = Vec::new();
+        let mut invalid = Vec::new();
+        let mut low_confidence = Vec::new();
+        let mut num_imported = 0;
+
+        for (idx, f) in gj.features.into_iter().enumerate() {
+            match is_selected(&f, &polygon) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => {
+                    invalid.push(invalid_feature(idx, f, err));
+                    continue;
+                }
+            }
+
+            let mut original = f.clone();
+            let edit = match self.resolve_savefile_edit(f) {
+                Ok(Match::Good(edit)) => edit,
+                Ok(Match::LowConfidence(edit, reason)) => {
+                    warn!("Savefile feature {idx} matched with low confidence: {reason}");
+                    original.set_property("low_confidence", reason);
+                    edit
+                }
+                Ok(Match::NotFound(reason)) => {
+                    let mut f = original;
+                    f.set_property("conflict", reason);
+                    conflicts.push(f);
+                    continue;
+                }
+                Err(err) => {
+                    invalid.push(invalid_feature(idx, original, err));
+                    continue;
+                }
+            };
+            match edit {
+                SavefileEdit::Command(cmd) => {
+                    if let Some(conflict) = self.import_conflict(&cmd) {
+                        let mut f = original;
+                        f.set_property("conflict", conflict);
+                        conflicts.push(f);
+                        continue;
+                    }
+                    cmds.push(cmd);
+                }
+                SavefileEdit::AddModalFilter(r, filter) => {
+                    // Only counted once the whole list is checked
+                    let (filters, features) = modal_filters.entry(r).or_default();
+                    filters.push(filter);
+                    features.push(original);
+                    num_imported += 1;
+                    continue;
+                }
+                SavefileEdit::AddTurnRestriction(i, from, to) => {
+                    // Deleting an existing restriction here disagrees with keeping it there
+                    if self.original_turn_restrictions[i.0].contains(&(from, to))
+                        && !self.turn_restrictions[i.0].contains(&(from, to))
+                    {
+                        let mut f = original;
+                        f.set_property(
+                            "conflict",
+                            format!("The turn from {from} to {to} was allowed here"),
+                        );
+                        conflicts.push(f);
+                        continue;
+                    }
+                    let list = turn_restrictions
+                        .entry(i)
+                        .or_insert_with(|| self.turn_restrictions[i.0].clone());
+                    if !list.contains(&(from, to)) {
+                        list.push((from, to));
+                    }
+                }
+                SavefileEdit::DeleteTurnRestriction(i, from, to) => {
+                    if self
+                        .get_annotation(Intervention::TurnRestriction {
+                            intersection: i,
+                            from_road: from,
+                            to_road: to,
+                        })
+                        .is_some()
+                    {
+                        let mut f = original;
+                        f.set_property(
+                            "conflict",
+                            format!("The turn restriction from {from} to {to} is annotated here"),
+                        );
+                        conflicts.push(f);
+                        continue;
+                    }
+                    turn_restrictions
+                        .entry(i)
+                        .or_insert_with(|| self.turn_restrictions[i.0].clone())
+                        .retain(|(a, b)| (*a, *b) != (from, to));
+                }
+            }
+            if original.property("low_confidence").is_some() {
+                low_confidence.push(original);
+            }
+            num_imported += 1;
+        }
+
+        for (r, (filters, features)) in modal_filters {
+            if let Some(conflict) = self.filters_conflict(r, &filters) {
+                num_imported -= features.len();
+                for mut f in features {
+                    f.set_property("conflict", conflict.clone());
+                    conflicts.push(f);
+                }
+                continue;
+            }
+            low_confidence.extend(
+                features
+                    .into_iter()
+                    .filter(|f| f.property("low_confidence").is_some()),
+            );
+            cmds.push(Command::SetModalFilters(r, filters));
+        }
+        for (i, list) in turn_restrictions {
+            cmds.push(Command::SetTurnRestrictions(i, list));
+        }
+        if !cmds.is_empty() {
+            self.apply_command(Command::Multiple(cmds));
+        }
+
+        let num_conflicts = conflicts.len();
+        let num_invalid = invalid.len();
+        let num_low_confidence = low_confidence.len();
+        conflicts.extend(invalid);
+        conflicts.extend(low_confidence);
+        Ok(FeatureCollection {
+            features: conflicts,
+            bbox: None,
+            foreign_members: Some(
+                serde_json::json!({
+                    "num_imported": num_imported,
+                    "num_conflicts": num_conflicts,
+                    "num_invalid": num_invalid,
+                    "num_low_confidence": num_low_confidence,
+                })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ),
+        })
+    }
+
+    /// Imported filters replace every filter on the road. If the filters here were edited and are
+    /// different, describe the conflict.
+    fn filters_conflict(&self, r: RoadID, filters: &[ModalFilter]) -> Option<String> {
+        let existing = self.modal_filters.get(&r).cloned().unwrap_or_default();
+        let original = self
+            .original_modal_filters
+            .get(&r)
+            .cloned()
+            .unwrap_or_default();
+        let mut filters = filters.to_vec();
+        filters.sort_by(|a, b| a.percent_along.total_cmp(&b.percent_along));
+        if existing == original || existing == filters {
+            return None;
+        }
+        let kinds: Vec<_> = existing.iter().map(|f| f.kind.to_string()).collect();
+        Some(if kinds.is_empty() {
+            format!("The filters on {r} were deleted here")
+        } else {
+            format!("{r} already has different filters: {}", kinds.join(", "))
+        })
+    }
+
+    /// If an imported command would overwrite a different edit made here, describe why
+    fn import_conflict(&self, cmd: &Command) -> Option<String> {
+        match cmd {
+            // A saved road split into pieces
+            Command::Multiple(cmds) => {
+                return cmds.iter().find_map(|cmd| self.import_conflict(cmd));
+            }
+            Command::SetModalFilters(r, filters) => {
+                return self.filters_conflict(*r, filters);
+            }
+            Command::SetDiagonalFilter(i, Some(filter)) => {
+                let existing = self.diagonal_filters.get(i)?;
+                if existing != filter {
+                    return Some(format!("{i} already has a different diagonal filter"));
+                }
+            }
+            Command::SetTravelFlow(r, dir) => {
+                let existing = self.travel_flows[r];
+                if existing != TravelFlow::from_osm(&self.get_r(*r).tags) && existing != *dir {
+                    return Some(format!(
+                        "{r} has already been changed to {}",
+                        existing.to_string()
+                    ));
+                }
+            }
+            Command::SetMainRoad(r, is_main_road) => {
+                let existing = self.is_main_road[r];
+                if existing != self.get_r(*r).is_severance() && existing != *is_main_road {
+                    return Some(if existing {
+                        format!("{r} has already been made a main road")
+                    } else {
+                        format!("{r} has already been made a local road")
+                    });
+                }
+            }
+            Command::SetSpeedLimit(r, speed_mph) => {
+                let existing = self.speed_limits[r];
+                if existing != self.get_r(*r).speed_mph && existing != *speed_mph {
+                    return Some(format!("{r} already has a {existing} mph limit"));
+                }
+            }
+            Command::SetAnnotation(intervention, Some(annotation)) => {
+                let existing = self.get_annotation(*intervention)?;
+                if existing != annotation {
+                    return Some(format!(
+                        "The {} already has a different annotation",
+                        intervention.kind().replace('_', " ")
+                    ));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Is a savefile feature an intervention within the selection? Fails for malformed features.
+fn is_selected(f: &Feature, polygon: &Polygon) -> Result<bool> {
+    let Some(kind) = f.property("kind").and_then(|x| x.as_str()) else {
+        bail!("savefile feature missing `kind`");
+    };
+    if !IMPORTED_KINDS.contains(&kind) || f.property("scenario").is_some() {
+        return Ok(false);
+    }
+    let Some(ref geometry) = f.geometry else {
+        bail!("savefile feature missing geometry");
+    };
+    let geometry: geo::Geometry = geometry.clone().try_into()?;
+    Ok(geometry.intersects(polygon))
+}
+
+fn invalid_feature(idx: usize, mut f: Feature, err: anyhow::Error) -> Feature {
+    let invalid = InvalidFeature::new(idx, &f, err);
+    warn!("Not importing: {invalid}");
+    f.set_property("invalid", invalid.reason);
+    f
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotations::{Annotation, InterventionStatus};
+    use crate::osm_tests::{get_road_by_name, load_osm_xml};
+    use crate::FilterKind;
+    use geo::{LineString, Rect};
+    use geojson::{Geometry, Value};
+
+    fn everywhere() -> ImportSelection {
+        ImportSelection::Polygon(Rect::new((-0.12, 55.70), (-0.10, 55.71)).to_polygon())
+    }
+
+    fn num(report: &FeatureCollection, key: &str) -> u64 {
+        report.foreign_members.as_ref().unwrap()[key]
+            .as_u64()
+            .unwrap()
+    }
+
+    fn set_kind(gj: &mut FeatureCollection, from: &str, to: &str) {
+        for f in &mut gj.features {
+            if f.property("kind").and_then(|x| x.as_str()) == Some(from) {
+                f.set_property("kind", to);
+            }
+        }
+    }
+
+    #[test]
+    fn main_road_conflicts() {
+        let mut other = load_osm_xml("two_neighbourhoods");
+        let r = get_road_by_name(&other, "west cross");
+        other.apply_command(Command::SetMainRoad(r, true));
+        let mut savefile = other.to_savefile();
+
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let report = map
+            .import_from_savefile(savefile.clone(), everywhere())
+            .unwrap();
+        assert_eq!(num(&report, "num_imported"), 1);
+        assert!(map.is_main_road[&r]);
+
+        // Pretend the road was a main road in the other savefile's basemap, and made local there
+        for f in &mut savefile.features {
+            if f.property("kind").and_then(|x| x.as_str()) == Some("main_road") {
+                f.set_property("is_main_road", false);
+            }
+        }
+        let report = map.import_from_savefile(savefile, everywhere()).unwrap();
+        assert_eq!(num(&report, "num_conflicts"), 1);
+        assert!(map.is_main_road[&r]);
+    }
+
+    #[test]
+    fn filter_list_conflicts() {
+        let filter = |kind, percent_along| ModalFilter {
+            kind,
+            percent_along,
+        };
+
+        let mut other = load_osm_xml("two_neighbourhoods");
+        let r = get_road_by_name(&other, "west cross");
+        other.apply_command(Command::SetModalFilters(
+            r,
+            vec![filter(FilterKind::WalkCycleOnly, 0.5)],
+        ));
+        let savefile = other.to_savefile();
+
+        // The same kind of filter was added here, but the imported list would replace both
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let here = vec![
+            filter(FilterKind::WalkCycleOnly, 0.2),
+            filter(FilterKind::NoEntry, 0.8),
+        ];
+        map.apply_command(Command::SetModalFilters(r, here.clone()));
+        let report = map
+            .import_from_savefile(savefile.clone(), everywhere())
+            .unwrap();
+        assert_eq!(num(&report, "num_conflicts"), 1);
+        assert_eq!(num(&report, "num_imported"), 0);
+        assert_eq!(map.modal_filters[&r], here);
+
+        // With no edits here, the filters are imported
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let report = map.import_from_savefile(savefile, everywhere()).unwrap();
+        assert_eq!(num(&report, "num_conflicts"), 0);
+        assert_eq!(num(&report, "num_imported"), 1);
+        assert_eq!(map.modal_filters[&r].len(), 1);
+    }
+
+    #[test]
+    fn turn_restriction_conflicts() {
+        let original = load_osm_xml("no_left_turn");
+        let i = original
+            .intersections
+            .iter()
+            .find(|i| i.roads.len() > 1)
+            .unwrap()
+            .id;
+        let (from, to) = original.turn_restrictions[i.0][0];
+        let intervention = Intervention::TurnRestriction {
+            intersection: i,
+            from_road: from,
+            to_road: to,
+        };
+
+        let mut other = load_osm_xml("no_left_turn");
+        other.delete_turn_restriction(i, from, to).unwrap();
+        let mut savefile = other.to_savefile();
+
+        // Deleting an annotated restriction conflicts
+        let mut map = load_osm_xml("no_left_turn");
+        map.set_annotation(
+            intervention,
+            Some(Annotation {
+                notes: String::new(),
+                status: InterventionStatus::Built,
+                reference: None,
+            }),
+        )
+        .unwrap();
+        let report = map
+            .import_from_savefile(savefile.clone(), everywhere())
+            .unwrap();
+        assert_eq!(num(&report, "num_conflicts"), 1);
+        assert_eq!(map.turn_restrictions[i.0], vec![(from, to)]);
+
+        // Pretend the other savefile added the restriction. Here it was deleted, so that
+        // conflicts.
+        set_kind(
+            &mut savefile,
+            "deleted_existing_turn_restriction",
+            "turn_restriction",
+        );
+        let mut map = load_osm_xml("no_left_turn");
+        map.delete_turn_restriction(i, from, to).unwrap();
+        let report = map.import_from_savefile(savefile, everywhere()).unwrap();
+        assert_eq!(num(&report, "num_conflicts"), 1);
+        assert!(map.turn_restrictions[i.0].is_empty());
+    }
+
+    #[test]
+    fn low_confidence_matches_are_reported() {
+        let mut other = load_osm_xml("two_neighbourhoods");
+        let r = get_road_by_name(&other, "west cross");
+        other.apply_command(Command::SetMainRoad(r, true));
+        let mut savefile = other.to_savefile();
+        // Move the road about 10m north, so it no longer matches closely
+        for f in &mut savefile.features {
+            if f.property("kind").and_then(|x| x.as_str()) == Some("main_road") {
+                let mut line: LineString = f.geometry.take().unwrap().value.try_into().unwrap();
+                for pt in &mut line.0 {
+                    pt.y += 0.0001;
+                }
+                f.geometry = Some(Geometry::new(Value::from(&line)));
+            }
+        }
+
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let report = map.import_from_savefile(savefile, everywhere()).unwrap();
+        assert_eq!(num(&report, "num_imported"), 1);
+        assert_eq!(num(&report, "num_low_confidence"), 1);
+        assert_eq!(num(&report, "num_conflicts"), 0);
+        assert!(report.features[0].property("low_confidence").is_some());
+        assert!(map.is_main_road[&r]);
+    }
+
+    #[test]
+    fn invalid_features_are_reported() {
+        let mut other = load_osm_xml("two_neighbourhoods");
+        let r = get_road_by_name(&other, "west cross");
+        other.set_speed_limit(r, 10.0).unwrap();
+        other.apply_command(Command::SetMainRoad(r, true));
+        let mut savefile = other.to_savefile();
+        for f in &mut savefile.features {
+            if f.property("kind").and_then(|x| x.as_str()) == Some("speed_limit") {
+                f.remove_property("kind");
+            }
+        }
+
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let report = map.import_from_savefile(savefile, everywhere()).unwrap();
+        assert_eq!(num(&report, "num_invalid"), 1);
+        assert_eq!(num(&report, "num_imported"), 1);
+        assert!(report.features[0].property("invalid").is_some());
+        assert!(map.is_main_road[&r]);
+        assert_eq!(map.speed_limits[&r], map.get_r(r).speed_mph);
+    }
+}
@@ -12,35 +12,56 @@ pub use self::neighbourhood::{Neighbourhood, NeighbourhoodBoundary, Neighbourhoo
 use self::render_cells::RenderCells;
 pub use self::route::Router;
 pub use self::shortcuts::Shortcuts;
+use crate::all_neighbourhoods::AllNeighbourhoods;
+use crate::annotations::{Annotation, Intervention};
+use crate::cross_neighbourhood::{
+    cross_neighbourhood_shortcuts_to_gj, find_cross_neighbourhood_shortcuts,
+};
 use crate::geo_helpers::make_polygon_valid;
+use crate::import_savefile::ImportSelection;
 use crate::map_model::{Command, ProjectDetails};
 use crate::neighbourhood::WayPoint;
+use crate::permeability::Permeability;
+use crate::route::VehicleProfile;
 use geo::{Coord, LineString, Polygon};
 use geojson::{Feature, FeatureCollection, GeoJson, Geometry};
 use serde::Deserialize;
+use std::collections::BTreeSet;
 use std::sync::Once;
 use wasm_bindgen::prelude::*;
 
+mod access;
+mod all_neighbourhoods;
+mod annotations;
 mod auto_boundaries;
 pub mod boundary_stats;
 mod cells;
 mod create;
+mod cross_neighbourhood;
 mod geo_helpers;
 mod impact;
+mod import_savefile;
 mod map_model;
 mod movements;
 mod neighbourhood;
 pub mod od;
 #[cfg(test)]
 mod osm_tests;
+mod permeability;
 mod render_cells;
 mod route;
 mod route_snapper;
+mod savefile_matching;
+mod savefile_migrations;
+mod scenarios;
+mod scorecard;
 mod shortcuts;
+mod suggest_filters;
 // TODO: We could hide this behind a feature flag - it's used by both tests and benches
 pub mod test_fixtures;
 #[cfg(test)]
 mod tests;
+mod validation;
 
 static START: Once = Once::new();
 
@@ -48,6 +69,10 @@ static START: Once = Once::new();
 pub struct LTN {
     map: MapModel,
     neighbourhood: Option<Neighbourhood>,
+    // The most recent results of suggestModalFilters, as commands ready to apply
+    filter_suggestions: Vec<Command>,
+    // Cleared after any edit
+    all_neighbourhoods: AllNeighbourhoods,
 }
 
 #[wasm_bindgen]
@@ -99,6 +124,8 @@ impl LTN {
         Ok(LTN {
             map,
             neighbourhood: None,
+            filter_suggestions: Vec::new(),
+            all_neighbourhoods: AllNeighbourhoods::default(),
         })
     }
 
@@ -189,6 +216,42 @@ impl LTN {
         })
     }
 
+    /// Renders every named neighbourhood together, with the same features as
+    /// `renderNeighbourhood`, except each neighbourhood has its boundary instead of a mask.
+    /// Every feature has a `neighbourhood` property with the name. Boundaries without any
+    /// interior roads are skipped.
+    #[wasm_bindgen(js_name = renderAllNeighbourhoods)]
+    pub fn render_all_neighbourhoods(&mut self) -> Result<String, JsValue> {
+        Ok(serde_json::to_string(&self.all_neighbourhoods.to_gj(&self.map)).map_err(err_to_js)?)
+    }
+
+    /// Finds shortcuts cutting through more than one of the named neighbourhoods, crossing
+    /// between them on shared main roads. If `names` is empty, all neighbourhoods are used.
+    #[wasm_bindgen(js_name = getCrossNeighbourhoodShortcuts)]
+    pub fn get_cross_neighbourhood_shortcuts(
+        &mut self,
+        names: Vec<String>,
+    ) -> Result<String, JsValue> {
+        let all = self.all_neighbourhoods.get(&self.map);
+        let mut neighbourhoods = Vec::new();
+        if names.is_empty() {
+            neighbourhoods.extend(all.values());
+        } else {
+            for name in &names {
+                let Some(neighbourhood) = all.get(name) else {
+                    return Err(format!("unknown or empty neighbourhood {name}").into());
+                };
+                neighbourhoods.push(neighbourhood);
+            }
+        }
+
+        let shortcuts = find_cross_neighbourhood_shortcuts(&self.map, &neighbourhoods);
+        Ok(
+            serde_json::to_string(&cross_neighbourhood_shortcuts_to_gj(&self.map, &shortcuts))
+                .map_err(err_to_js)?,
+        )
+    }
+
     #[wasm_bindgen(js_name = generatedBoundaries)]
     pub fn generated_boundaries(&self) -> Result<String, JsValue> {
         let gj = GeoJson::from(
@@ -201,6 +264,17 @@ impl LTN {
         Ok(serde_json::to_string(&gj).map_err(err_to_js)?)
     }
 
+    /// Takes a LngLat inside the desired area. Returns a boundary Feature with waypoints, enclosed
+    /// by the current main roads, railways, and waterways.
+    #[wasm_bindgen(js_name = boundaryFromPoint)]
+    pub fn boundary_from_point(&self, x: f64, y: f64) -> Result<String, JsValue> {
+        let definition = self
+            .map
+            .boundary_from_point(self.map.mercator.pt_to_mercator(Coord { x, y }))
+            .map_err(err_to_js)?;
+        Ok(serde_json::to_string(&definition.to_feature(&self.map)).map_err(err_to_js)?)
+    }
+
     /// `boundaries_to_merge`: FeatureCollection of Polygon geometries.
     #[wasm_bindgen(js_name = generateMergedBoundary)]
     pub fn generate_merged_boundary(
@@ -252,6 +326,7 @@ impl LTN {
         let boundary =
             NeighbourhoodBoundary::new(neighbourhood_definition, self.map.context_data.as_ref());
         self.map.boundaries.insert(name, boundary.clone());
+        self.all_neighbourhoods.clear();
 
         self.neighbourhood = Some(Neighbourhood::new(&self.map, boundary).map_err(err_to_js)?);
         Ok(())
@@ -260,33 +335,29 @@ impl LTN {
     #[wasm_bindgen(js_name = deleteNeighbourhoodBoundary)]
     pub fn delete_neighbourhood_boundary(&mut self, name: String) {
         self.map.boundaries.remove(&name);
+        self.map.delete_history(&name);
+        self.all_neighbourhoods.clear();
     }
 
     #[wasm_bindgen(js_name = renameNeighbourhoodBoundary)]
     pub fn rename_neighbourhood_boundary(&mut self, old_name: String, new_name: String) {
         let mut boundary = self.map.boundaries.remove(&old_name).unwrap();
         boundary.definition.name = new_name.clone();
+        self.map.rename_history(&old_name, &new_name);
         self.map.boundaries.insert(new_name, boundary);
+        self.all_neighbourhoods.clear();
     }
 
     #[wasm_bindgen(js_name = setCurrentNeighbourhood)]
     pub fn set_current_neighbourhood(&mut self, name: String) -> Result<(), JsValue> {
         let boundary = self.map.boundaries.get(&name).unwrap();
-
-        // Are we still editing the same neighbourhood, just switching edit_perimeter_roads?
-        let editing_same = self
-            .neighbourhood
-            .as_ref()
-            .map(|n| n.name() == name)
-            .unwrap_or(false);
         self.neighbourhood =
             Some(Neighbourhood::new(&self.map, boundary.clone()).map_err(err_to_js)?);
 
-        // Undoing edits in another neighbourhood doesn't make sense
-        if !editing_same {
-            self.map.undo_stack.clear();
-            self.map.redo_stack.clear();
-        }
+        // Undoing edits in another neighbourhood doesn't make sense, so each one has its own
+        // history. This does nothing when we're still editing the same neighbourhood, just
+        // switching edit_perimeter_roads.
+        self.map.switch_history(&name);
 
         Ok(())
     }
@@ -323,9 +394,11 @@ impl LTN {
         Ok(())
     }
 
+    /// Deletes the filter with `filter_idx` from `renderModalFilters`, or every filter on the road
+    /// if it's missing
     #[wasm_bindgen(js_name = deleteModalFilter)]
-    pub fn delete_modal_filter(&mut self, road: usize) {
-        self.map.delete_modal_filter(RoadID(road));
+    pub fn delete_modal_filter(&mut self, road: usize, filter_idx: Option<usize>) {
+        self.map.delete_modal_filter(RoadID(road), filter_idx);
         self.after_edit();
     }
 
@@ -338,7 +411,8 @@ impl LTN {
         Ok(())
     }
 
-    /// Takes an IntersectionID
+    /// Takes an IntersectionID. At a T-junction, this only toggles between 2 of the 3 ways to
+    /// separate one road; use `setDiagonalFilterGroups` for the third.
     #[wasm_bindgen(js_name = rotateDiagonalFilter)]
     pub fn rotate_diagonal_filter(&mut self, intersection_id: usize) -> Result<(), JsValue> {
         self.map
@@ -347,6 +421,25 @@ impl LTN {
         Ok(())
     }
 
+    /// Takes an IntersectionID and a list of RoadIDs at that intersection. Traffic can't pass
+    /// between these roads and the rest.
+    #[wasm_bindgen(js_name = setDiagonalFilterGroups)]
+    pub fn set_diagonal_filter_groups(
+        &mut self,
+        intersection_id: usize,
+        group_a: JsValue,
+    ) -> Result<(), JsValue> {
+        let group_a: Vec<usize> = serde_wasm_bindgen::from_value(group_a)?;
+        self.map
+            .set_diagonal_filter_groups(
+                IntersectionID(intersection_id),
+                group_a.into_iter().map(RoadID).collect(),
+            )
+            .map_err(err_to_js)?;
+        self.after_edit();
+        Ok(())
+    }
+
     /// Takes an IntersectionID
     #[wasm_bindgen(js_name = deleteDiagonalFilter)]
     pub fn delete_diagonal_filter(&mut self, intersection_id: usize) -> Result<(), JsValue> {
@@ -379,6 +472,75 @@ impl LTN {
         Ok(())
     }
 
+    /// Returns a JSON list of ranked options, each with a FeatureCollection of proposed filters
+    #[wasm_bindgen(js_name = suggestModalFilters)]
+    pub fn suggest_modal_filters(
+        &mut self,
+        kind: String,
+        num_options: usize,
+    ) -> Result<String, JsValue> {
+        let Some(ref neighbourhood) = self.neighbourhood else {
+            return Err("no current neighbourhood".into());
+        };
+        let kind = FilterKind::from_string(&kind).map_err(err_to_js)?;
+        let suggestions = neighbourhood.suggest_filters(&mut self.map, kind, num_options);
+        let out = suggestions
+            .iter()
+            .enumerate()
+            .map(|(rank, suggestion)| suggestion.to_json(&self.map, rank))
+            .collect::<Vec<_>>();
+        self.filter_suggestions = suggestions.into_iter().map(|s| s.cmd).collect();
+        Ok(serde_json::to_string(&out).map_err(err_to_js)?)
+    }
+
+    /// Applies one option from the last call to suggestModalFilters as a single edit
+    #[wasm_bindgen(js_name = applyModalFilterSuggestion)]
+    pub fn apply_modal_filter_suggestion(&mut self, rank: usize) -> Result<(), JsValue> {
+        if rank >= self.filter_suggestions.len() {
+            return Err("no such filter suggestion".into());
+        }
+        let cmd = self.filter_suggestions.remove(rank);
+        self.map.apply_command(cmd);
+        self.after_edit();
+        Ok(())
+    }
+
+    /// Returns a FeatureCollection of warnings about the current edits. Checks about cells only
+    /// happen when there's a current neighbourhood.
+    #[wasm_bindgen(js_name = validateEdits)]
+    pub fn validate_edits(&mut self) -> Result<String, JsValue> {
+        let gj = self.map.validate_edits(self.neighbourhood.as_ref());
+        Ok(serde_json::to_string(&gj).map_err(err_to_js)?)
+    }
+
+    /// Sets or clears the annotation on an intervention. The input is an object with
+    /// `intervention` and `annotation`, which may be null.
+    #[wasm_bindgen(js_name = setAnnotation)]
+    pub fn set_annotation(&mut self, input: JsValue) -> Result<(), JsValue> {
+        let input: AnnotationInput = serde_wasm_bindgen::from_value(input)?;
+        self.map
+            .set_annotation(input.intervention, input.annotation)
+            .map_err(err_to_js)?;
+        self.after_edit();
+        Ok(())
+    }
+
+    /// Returns a list of every annotation, as objects with `intervention` and `annotation`
+    #[wasm_bindgen(js_name = getAnnotations)]
+    pub fn get_annotations(&self) -> Result<String, JsValue> {
+        let list: Vec<_> = self
+            .map
+            .current_annotations()
+            .map(|(intervention, annotation)| {
+                serde_json::json!({
+                    "intervention": intervention,
+                    "annotation": annotation,
+                })
+            })
+            .collect();
+        Ok(serde_json::to_string(&list).map_err(err_to_js)?)
+    }
+
     #[wasm_bindgen(js_name = getTurnRestrictionTargets)]
     pub fn get_turn_restriction_targets_wasm(&self, road: usize) -> Result<String, JsValue> {
         Ok(
@@ -422,6 +584,90 @@ impl LTN {
         self.after_main_road_edit()
     }
 
+    /// Takes a RoadID and a speed limit in mph
+    #[wasm_bindgen(js_name = setSpeedLimit)]
+    pub fn set_speed_limit(&mut self, road: usize, speed_mph: f64) -> Result<(), JsValue> {
+        self.map
+            .set_speed_limit(RoadID(road), speed_mph)
+            .map_err(err_to_js)?;
+        self.after_edit();
+        Ok(())
+    }
+
+    /// Sets the speed limit in mph along a line drawn through intersections
+    #[wasm_bindgen(js_name = setSpeedLimits)]
+    pub fn set_speed_limits(
+        &mut self,
+        intersections: Vec<usize>,
+        speed_mph: f64,
+    ) -> Result<(), JsValue> {
+        // Beyond map.intersections.length, these represent synthetic, planar nodes created by
+        // route_snapper.
+        let intersection_ids: Vec<IntersectionID> = intersections
+            .into_iter()
+            .filter(|i| *i < self.map.intersections.len())
+            .map(IntersectionID)
+            .collect();
+        self.map
+            .set_speed_limits(intersection_ids, speed_mph)
+            .map_err(err_to_js)?;
+        self.after_edit();
+        Ok(())
+    }
+
+    /// Takes a drawn LineString and sets the travel flow (`forwards`, `backwards`, or `both`,
+    /// relative to the line) of every road it follows in the current neighbourhood
+    #[wasm_bindgen(js_name = setTravelFlowAlongLine)]
+    pub fn set_travel_flow_along_line(
+        &mut self,
+        input: JsValue,
+        travel_flow: String,
+    ) -> Result<(), JsValue> {
+        let gj: Feature = serde_wasm_bindgen::from_value(input)?;
+        let mut linestring: LineString = gj.try_into().map_err(err_to_js)?;
+        self.map.mercator.to_mercator_in_place(&mut linestring);
+        let dir = TravelFlow::from_string(&travel_flow).map_err(err_to_js)?;
+
+        let Some(ref neighbourhood) = self.neighbourhood else {
+            return Err("no current neighbourhood".into());
+        };
+        let candidates: BTreeSet<RoadID> = neighbourhood
+            .interior_roads
+            .union(&neighbourhood.main_roads)
+            .cloned()
+            .collect();
+        self.map
+            .set_travel_flow_along_line(linestring, &candidates, dir);
+        self.after_edit();
+        Ok(())
+    }
+
+    /// Takes a drawn LineString and changes every road it follows in the current neighbourhood to
+    /// be a main road or not
+    #[wasm_bindgen(js_name = setMainRoadAlongLine)]
+    pub fn set_main_road_along_line(
+        &mut self,
+        input: JsValue,
+        make_main_road: bool,
+    ) -> Result<(), JsValue> {
+        let gj: Feature = serde_wasm_bindgen::from_value(input)?;
+        let mut linestring: LineString = gj.try_into().map_err(err_to_js)?;
+        self.map.mercator.to_mercator_in_place(&mut linestring);
+
+        let Some(ref neighbourhood) = self.neighbourhood else {
+            return Err("no current neighbourhood".into());
+        };
+        let candidates: BTreeSet<RoadID> = neighbourhood
+            .interior_roads
+            .union(&neighbourhood.main_roads)
+            .cloned()
+            .collect();
+        self.map
+            .set_main_road_along_line(linestring, &candidates, make_main_road);
+        self.after_edit();
+        self.after_main_road_edit()
+    }
+
     #[wasm_bindgen(js_name = eraseAllMainRoads)]
     pub fn erase_all_main_roads(&mut self) -> Result<(), JsValue> {
         self.map
@@ -431,12 +677,12 @@ impl LTN {
     }
 
     pub fn undo(&mut self) -> Result<(), JsValue> {
-        let maybe_cmd = self.map.undo();
+        let maybe_cmd = self.map.undo().map_err(err_to_js)?;
         self.after_cmd(maybe_cmd)
     }
 
     pub fn redo(&mut self) -> Result<(), JsValue> {
-        let maybe_cmd = self.map.redo();
+        let maybe_cmd = self.map.redo().map_err(err_to_js)?;
         self.after_cmd(maybe_cmd)
     }
 
@@ -444,18 +690,7 @@ impl LTN {
         self.after_edit();
         let Some(cmd) = cmd else { return Ok(()) };
 
-        let first_cmd = match &cmd {
-            Command::Multiple(cmds) => {
-                let Some(first_cmd) = cmds.first() else {
-                    debug_assert!(false, "Command::Multiple shouldn't be empty");
-                    return Ok(());
-                };
-                first_cmd
-            }
-            single_cmd => single_cmd,
-        };
-
-        if matches!(first_cmd, Command::SetMainRoad(_, _)) {
+        if cmd.touches_main_roads() {
             self.after_main_road_edit()
         } else {
             Ok(())
@@ -527,20 +762,202 @@ impl LTN {
         })
     }
 
+    /// Returns JSON with walking and cycling permeability scores for the current neighbourhood,
+    /// before and after edits, and GeoJSON of edited roads that cut cycling links
+    #[wasm_bindgen(js_name = getPermeability)]
+    pub fn get_permeability(&self) -> Result<String, JsValue> {
+        let Some(ref neighbourhood) = self.neighbourhood else {
+            return Err("no current neighbourhood".into());
+        };
+        Ok(
+            serde_json::to_string(&Permeability::new(&self.map, neighbourhood).to_json(&self.map))
+                .map_err(err_to_js)?,
+        )
+    }
+
+    /// Returns JSON with a `Scorecard` for the current neighbourhood, `before` and `after` edits
+    #[wasm_bindgen(js_name = getNeighbourhoodScorecard)]
+    pub fn get_neighbourhood_scorecard(&mut self) -> Result<String, JsValue> {
+        let Some(ref neighbourhood) = self.neighbourhood else {
+            return Err("no current neighbourhood".into());
+        };
+        let after = neighbourhood.scorecard(&self.map);
+        let before = self.run_before_edits(|ltn| {
+            let name = ltn.neighbourhood.as_ref().unwrap().name();
+            let boundary = ltn.map.boundaries.get(name).unwrap();
+            let neighbourhood =
+                Neighbourhood::new(&ltn.map, boundary.clone()).map_err(err_to_js)?;
+            Ok(serde_json::to_string(&neighbourhood.scorecard(&ltn.map)).map_err(err_to_js)?)
+        })?;
+        let before: serde_json::Value = serde_json::from_str(&before).map_err(err_to_js)?;
+        Ok(serde_json::to_string(&serde_json::json!({
+            "before": before,
+            "after": after,
+        }))
+        .map_err(err_to_js)?)
+    }
+
+    /// GJ of bridges and tunnels inside the current neighbourhood's boundary that were left out,
+    /// with a `reason`
+    #[wasm_bindgen(js_name = getExcludedRoads)]
+    pub fn get_excluded_roads(&self) -> Result<String, JsValue> {
+        let Some(ref neighbourhood) = self.neighbourhood else {
+            return Err("no current neighbourhood".into());
+        };
+        let mut features = Vec::new();
+        for (r, reason) in &neighbourhood.excluded_roads {
+            let mut f = self.map.get_r(*r).to_gj(&self.map.mercator);
+            f.set_property("road", r.0);
+            f.set_property("reason", reason.clone());
+            features.push(f);
+        }
+        Ok(serde_json::to_string(&GeoJson::from(features)).map_err(err_to_js)?)
+    }
+
+    /// Compares the current neighbourhood's shortcuts to the ones before any edits. See
+    /// `Shortcuts::diff_to_gj`.
+    #[wasm_bindgen(js_name = getShortcutsDiff)]
+    pub fn get_shortcuts_diff(&mut self) -> Result<String, JsValue> {
+        self.run_before_edits(|ltn| {
+            let Some(after) = ltn.neighbourhood.as_ref() else {
+                return Err("no current neighbourhood".into());
+            };
+            let boundary = ltn.map.boundaries.get(after.name()).unwrap();
+            let before = Neighbourhood::new(&ltn.map, boundary.clone()).map_err(err_to_js)?;
+            let shortcuts_after = &after
+                .derived
+                .as_ref()
+                .expect("neighbourhood has no derived state yet")
+                .shortcuts;
+            let shortcuts_before = &before
+                .derived
+                .as_ref()
+                .expect("neighbourhood has no derived state yet")
+                .shortcuts;
+            Ok(
+                serde_json::to_string(&shortcuts_after.diff_to_gj(shortcuts_before, &ltn.map))
+                    .map_err(err_to_js)?,
+            )
+        })
+    }
+
+    /// Compares the current neighbourhood's shortcuts in two scenarios, treating `a` as before and
+    /// `b` as after. See `Shortcuts::diff_to_gj`.
+    #[wasm_bindgen(js_name = getShortcutsDiffBetweenScenarios)]
+    pub fn get_shortcuts_diff_between_scenarios(
+        &mut self,
+        a: String,
+        b: String,
+    ) -> Result<String, JsValue> {
+        let Some(name) = self.neighbourhood.as_ref().map(|n| n.name().to_string()) else {
+            return Err("no current neighbourhood".into());
+        };
+        let boundary = self.map.boundaries.get(&name).unwrap().clone();
+        let before = self
+            .map
+            .with_scenario(&a, |map| Neighbourhood::new(map, boundary.clone()))
+            .map_err(err_to_js)?
+            .map_err(err_to_js)?;
+        let after = self
+            .map
+            .with_scenario(&b, |map| Neighbourhood::new(map, boundary))
+            .map_err(err_to_js)?
+            .map_err(err_to_js)?;
+        let shortcuts_before = &before
+            .derived
+            .as_ref()
+            .expect("neighbourhood has no derived state yet")
+            .shortcuts;
+        let shortcuts_after = &after
+            .derived
+            .as_ref()
+            .expect("neighbourhood has no derived state yet")
+            .shortcuts;
+        Ok(
+            serde_json::to_string(&shortcuts_after.diff_to_gj(shortcuts_before, &self.map))
+                .map_err(err_to_js)?,
+        )
+    }
+
+    /// Returns a list of scenario names, with the current one first
+    #[wasm_bindgen(js_name = listScenarios)]
+    pub fn list_scenarios(&self) -> Result<String, JsValue> {
+        Ok(serde_json::to_string(&self.map.list_scenarios()).map_err(err_to_js)?)
+    }
+
+    /// Creates a scenario starting from the basemap, or from a copy of the current edits
+    #[wasm_bindgen(js_name = newScenario)]
+    pub fn new_scenario(&mut self, name: String, copy_current: bool) -> Result<(), JsValue> {
+        self.map.new_scenario(name, copy_current).map_err(err_to_js)
+    }
+
+    #[wasm_bindgen(js_name = switchScenario)]
+    pub fn switch_scenario(&mut self, name: String) -> Result<(), JsValue> {
+        self.map.switch_scenario(&name).map_err(err_to_js)?;
+        self.after_edit();
+        // Main roads can differ between scenarios
+        self.after_main_road_edit()
+    }
+
+    #[wasm_bindgen(js_name = renameScenario)]
+    pub fn rename_scenario(&mut self, old_name: String, new_name: String) -> Result<(), JsValue> {
+        self.map
+            .rename_scenario(&old_name, new_name)
+            .map_err(err_to_js)
+    }
+
+    #[wasm_bindgen(js_name = deleteScenario)]
+    pub fn delete_scenario(&mut self, name: String) -> Result<(), JsValue> {
+        self.map.delete_scenario(&name).map_err(err_to_js)
+    }
+
     /// GJ with modal filters and named boundaries. This is meant for savefiles, so existing
     /// filters aren't included (and deletions of existing are included)
     #[wasm_bindgen(js_name = toSavefile)]
-    pub fn to_savefile(&self) -> Result<String, JsValue> {
+    pub fn to_savefile(&mut self) -> Result<String, JsValue> {
         // TODO Trim coordinates... in mercator?
         Ok(serde_json::to_string(&self.map.to_savefile()).map_err(err_to_js)?)
     }
 
+    /// Returns GJ with saved edits that matched the current map with low confidence or not at
+    /// all, because the OSM data changed. Malformed features fail the load, unless `skip_invalid`
+    /// is set, in which case they're in the returned GJ too.
     #[wasm_bindgen(js_name = loadSavefile)]
-    pub fn load_savefile(&mut self, input: JsValue) -> Result<(), JsValue> {
+    pub fn load_savefile(&mut self, input: JsValue, skip_invalid: bool) -> Result<String, JsValue> {
         let gj: FeatureCollection = serde_wasm_bindgen::from_value(input)?;
-        self.map.load_savefile(gj).map_err(err_to_js)?;
+        let report = self
+            .map
+            .load_savefile(gj, skip_invalid)
+            .map_err(err_to_js)?;
         self.neighbourhood = None;
-        Ok(())
+        self.all_neighbourhoods.clear();
+        Ok(serde_json::to_string(&report).map_err(err_to_js)?)
+    }
+
+    /// Copies edits from another savefile into this project. Pass either the name of a boundary in
+    /// the other savefile, or a GeoJSON Feature with a Polygon to select edits. Returns GJ with
+    /// edits that conflict with existing edits here, which aren't imported.
+    #[wasm_bindgen(js_name = importFromSavefile)]
+    pub fn import_from_savefile(
+        &mut self,
+        input: JsValue,
+        boundary_name: Option<String>,
+        polygon: JsValue,
+    ) -> Result<String, JsValue> {
+        let gj: FeatureCollection = serde_wasm_bindgen::from_value(input)?;
+        let selection = if let Some(name) = boundary_name {
+            ImportSelection::Boundary(name)
+        } else {
+            let feature: Feature = serde_wasm_bindgen::from_value(polygon)?;
+            ImportSelection::Polygon(Polygon::try_from(feature).map_err(err_to_js)?)
+        };
+        let conflicts = self
+            .map
+            .import_from_savefile(gj, selection)
+            .map_err(err_to_js)?;
+        self.after_edit();
+        self.after_main_road_edit()?;
+        Ok(serde_json::to_string(&conflicts).map_err(err_to_js)?)
     }
 
     #[wasm_bindgen(js_name = changeProjectName)]
@@ -570,6 +987,52 @@ impl LTN {
         )
     }
 
+    /// Like `compareRoute`, but for a vehicle profile: `through_traffic`, `resident`, `bus`, or
+    /// `emergency`. Each kind of modal filter lets different vehicles through.
+    #[wasm_bindgen(js_name = compareRouteForVehicle)]
+    pub fn compare_route_for_vehicle(
+        &mut self,
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        y2: f64,
+        main_road_penalty: f64,
+        profile: String,
+    ) -> Result<String, JsValue> {
+        let profile = VehicleProfile::from_string(&profile).map_err(err_to_js)?;
+        let pt1 = self.map.mercator.pt_to_mercator(Coord { x: x1, y: y1 });
+        let pt2 = self.map.mercator.pt_to_mercator(Coord { x: x2, y: y2 });
+        Ok(serde_json::to_string(&self.map.compare_route_for_profile(
+            pt1,
+            pt2,
+            main_road_penalty,
+            profile,
+        ))
+        .map_err(err_to_js)?)
+    }
+
+    /// Like `compareRoute`, but between two scenarios instead of before and after edits
+    #[wasm_bindgen(js_name = compareRouteBetweenScenarios)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn compare_route_between_scenarios(
+        &mut self,
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        y2: f64,
+        main_road_penalty: f64,
+        a: String,
+        b: String,
+    ) -> Result<String, JsValue> {
+        let pt1 = self.map.mercator.pt_to_mercator(Coord { x: x1, y: y1 });
+        let pt2 = self.map.mercator.pt_to_mercator(Coord { x: x2, y: y2 });
+        let gj = self
+            .map
+            .compare_route_between_scenarios(&a, &b, pt1, pt2, main_road_penalty)
+            .map_err(err_to_js)?;
+        Ok(serde_json::to_string(&gj).map_err(err_to_js)?)
+    }
+
     /// Returns GJ with a LineString per interior road
     #[wasm_bindgen(js_name = impactToOneDestination)]
     pub fn impact_to_one_destination(&mut self, x: f64, y: f64) -> Result<String, JsValue> {
@@ -593,6 +1056,21 @@ impl LTN {
         Ok(serde_json::to_string(&out).map_err(err_to_js)?)
     }
 
+    /// Like `predictImpact`, but `before` counts are from scenario `a` and `after` from `b`
+    #[wasm_bindgen(js_name = predictImpactBetweenScenarios)]
+    pub fn predict_impact_between_scenarios(
+        &mut self,
+        a: String,
+        b: String,
+        fast_sample: bool,
+    ) -> Result<String, JsValue> {
+        let out = self
+            .map
+            .predict_impact_between_scenarios(&a, &b, fast_sample)
+            .map_err(err_to_js)?;
+        Ok(serde_json::to_string(&out).map_err(err_to_js)?)
+    }
+
     /// Returns a JSON blob [{before, after}], with before and after being LineStrings
     #[wasm_bindgen(js_name = getImpactsOnRoad)]
     pub fn get_impacts_on_road(&self, road: usize, fast_sample: bool) -> Result<String, JsValue> {
@@ -678,9 +1156,27 @@ impl LTN {
         self.after_edit();
     }
 
+    /// Only has an effect when the study area has a demand model
+    #[wasm_bindgen(js_name = setDemandWeightedShortcuts)]
+    pub fn set_demand_weighted_shortcuts(&mut self, value: bool) {
+        self.map.demand_weighted_shortcuts = value;
+        self.after_edit();
+    }
+
+    /// Slower, but produces cleaner cell polygons, such as for printed maps
+    #[wasm_bindgen(js_name = setExactCellPolygons)]
+    pub fn set_exact_cell_polygons(&mut self, value: bool) {
+        self.map.exact_cell_polygons = value;
+        self.after_edit();
+    }
+
     // TODO This is also internal to MapModel. But not sure who should own Neighbourhood or how to
     // plumb, so duplicting here.
     fn after_edit(&mut self) {
+        // Suggestions are relative to the edits they were calculated from
+        self.filter_suggestions.clear();
+        // Edits, especially to main roads, can affect any neighbourhood
+        self.all_neighbourhoods.clear();
         if let Some(ref mut n) = self.neighbourhood {
             n.after_edit(&self.map);
         }
@@ -701,38 +1197,26 @@ impl LTN {
         &mut self,
         cb: F,
     ) -> Result<String, JsValue> {
-        // Preserve the edited state
-        let modal_filters = self.map.modal_filters.clone();
-        let diagonal_filters = self.map.diagonal_filters.clone();
-        let turn_restrictions = self.map.turn_restrictions.clone();
-        let travel_flows = self.map.travel_flows.clone();
-        let is_main_road = self.map.is_main_road.clone();
-
-        // Revert to the original
-        self.map.modal_filters = self.map.original_modal_filters.clone();
-        self.map.diagonal_filters.clear();
-        self.map.turn_restrictions = self.map.original_turn_restrictions.clone();
-        for (r, dir) in &mut self.map.travel_flows {
-            *dir = TravelFlow::from_osm(&self.map.roads[r.0].tags);
-        }
-        for (r, is_main_road) in &mut self.map.is_main_road {
-            *is_main_road = self.map.roads[r.0].is_severance();
-        }
+        // Revert to the original, preserving the edited state
+        let mut state = self.map.original_edit_state();
+        self.map.swap_edit_state(&mut state);
 
         // Run the callback
         let output = cb(self);
 
         // Restore the edited state
-        self.map.modal_filters = modal_filters;
-        self.map.diagonal_filters = diagonal_filters;
-        self.map.turn_restrictions = turn_restrictions;
-        self.map.travel_flows = travel_flows;
-        self.map.is_main_road = is_main_road;
+        self.map.swap_edit_state(&mut state);
 
         output
     }
 }
 
+#[derive(Deserialize)]
+struct AnnotationInput {
+    intervention: Intervention,
+    annotation: Option<Annotation>,
+}
+
 #[derive(Deserialize)]
 struct LngLat {
     lng: f64,
@@ -1,11 +1,16 @@
+use crate::annotations::{Annotation, Intervention};
 use crate::boundary_stats::{ContextData, PreparedContextData};
 use crate::geo_helpers::{
     angle_between_bearings, angle_of_pt_on_line, bearing_from_endpoint, invert_multi_polygon,
-    limit_angle, linestring_intersection, split_bearing,
+    limit_angle, linestring_follows, linestring_intersection, split_bearing,
 };
 use crate::impact::Impact;
 use crate::neighbourhood::{NeighbourhoodBoundary, NeighbourhoodDefinition};
-use crate::route::RouterInput;
+use crate::permeability::ActiveTravelPath;
+use crate::route::{ProfileRouterInput, RouterInput, VehicleProfile};
+use crate::savefile_matching::{InvalidFeature, Match, MatchReport};
+use crate::savefile_migrations::{migrate_savefile, SAVEFILE_VERSION};
+use crate::scenarios::{EditState, DEFAULT_SCENARIO};
 use crate::{od::DemandModel, Neighbourhood, Router};
 use anyhow::Result;
 use geo::{
@@ -19,6 +24,8 @@ use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
 use utils::{buffer_aabb, osm2graph, Mercator, Tags};
 
+/// Prebuilt maps are stored with bincode, which ignores `#[serde(default)]`. Changing any
+/// serialized field here or in `Router` needs new `maps_vN` files.
 #[derive(Serialize, Deserialize)]
 pub struct MapModel {
     pub roads: Vec<Road>,
@@ -35,6 +42,8 @@ pub struct MapModel {
     // Only those acting as severances; above or belowground don't count
     pub railways: Vec<LineString>,
     pub waterways: Vec<LineString>,
+    // Footways, cycleways, and other paths that aren't roads
+    pub active_travel_paths: Vec<ActiveTravelPath>,
 
     // TODO Wasteful, can share some
     pub router_before: Router,
@@ -42,20 +51,36 @@ pub struct MapModel {
     pub router_after: Option<Router>,
     // Calculated lazily. No edits, just main_road_penalty.
     pub router_before_with_penalty: Option<Router>,
+    // Calculated lazily, for vehicle profiles besides ThroughTraffic. Like the routers above,
+    // before changes with main_road_penalty, and after also with edits.
+    #[serde(skip)]
+    pub profile_routers_before: HashMap<VehicleProfile, Router>,
+    #[serde(skip)]
+    pub profile_routers_after: HashMap<VehicleProfile, Router>,
 
-    // Just from the basemap, existing filters
-    pub original_modal_filters: BTreeMap<RoadID, ModalFilter>,
-    pub modal_filters: BTreeMap<RoadID, ModalFilter>,
+    // Just from the basemap, existing filters. Each road's filters are sorted by percent_along,
+    // and a road with no filters isn't in the map.
+    pub original_modal_filters: BTreeMap<RoadID, Vec<ModalFilter>>,
+    pub modal_filters: BTreeMap<RoadID, Vec<ModalFilter>>,
     pub diagonal_filters: BTreeMap<IntersectionID, DiagonalFilter>,
 
     /// Indexed by IntersectionID. For each intersection, a list of (from, to) roads that are not
     /// allowed. May be redundant with the road TravelFlow.
     pub turn_restrictions: Vec<Vec<(RoadID, RoadID)>>,
     pub original_turn_restrictions: Vec<Vec<(RoadID, RoadID)>>,
+    /// Turn restrictions from OSM spanning more than one intersection. These can't be edited.
+    pub via_way_restrictions: Vec<ViaWayRestriction>,
 
     // Every road is filled out
     pub travel_flows: BTreeMap<RoadID, TravelFlow>,
     pub is_main_road: BTreeMap<RoadID, bool>,
+    /// In mph. Every road is filled out. Not serialized, since `finish_loading` can fill it from
+    /// the roads.
+    #[serde(skip)]
+    pub speed_limits: BTreeMap<RoadID, f64>,
+    /// Notes on interventions. These may refer to interventions that've since been deleted.
+    #[serde(skip)]
+    pub annotations: BTreeMap<Intervention, Annotation>,
 
     // Not optional, but wrapped for the borrow checker
     #[serde(skip)]
@@ -64,9 +89,21 @@ pub struct MapModel {
 
     // TODO Keep edits / state here or not?
     #[serde(skip)]
-    pub undo_stack: Vec<Command>,
+    pub undo_stack: Vec<HistoryEntry>,
+    #[serde(skip)]
+    pub redo_stack: Vec<HistoryEntry>,
+    /// The neighbourhood that `undo_stack` and `redo_stack` belong to
+    #[serde(skip)]
+    pub history_owner: Option<String>,
+    /// Edit history for every other neighbourhood, keyed by boundary name
+    #[serde(skip)]
+    pub other_histories: BTreeMap<String, EditHistory>,
+    /// The name of the scenario that the current edits belong to
+    #[serde(skip)]
+    pub current_scenario: String,
+    /// Every other scenario, keyed by name
     #[serde(skip)]
-    pub redo_stack: Vec<Command>,
+    pub scenarios: BTreeMap<String, EditState>,
     pub boundaries: BTreeMap<String, NeighbourhoodBoundary>,
 
     // Only present in serialized MapModels
@@ -80,6 +117,12 @@ pub struct MapModel {
 
     #[serde(skip)]
     pub hide_unimportant_cells: bool,
+    /// When there's a demand model, weight shortcuts by the trips that cut through
+    #[serde(skip)]
+    pub demand_weighted_shortcuts: bool,
+    /// Render cells with exact polygons, instead of the faster grid-based approximation
+    #[serde(skip)]
+    pub exact_cell_polygons: bool,
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -312,6 +355,8 @@ impl MapModel {
         }
 
         self.impact = Some(Impact::default());
+        self.current_scenario = DEFAULT_SCENARIO.to_string();
+        self.speed_limits = self.roads.iter().map(|r| (r.id, r.speed_mph)).collect();
     }
 
     pub fn get_r(&self, r: RoadID) -> &Road {
@@ -328,32 +373,40 @@ impl MapModel {
         candidate_roads: Option<Vec<RoadID>>,
         kind: FilterKind,
     ) {
-        let cmd = self.do_edit(self.add_modal_filter_cmd(pt, candidate_roads, kind));
-        self.undo_stack.push(cmd);
-        self.redo_stack.clear();
+        // Keep any other filters already on the road
+        let (r, filter) = self.closest_modal_filter(pt, candidate_roads, kind);
+        let mut filters = self.modal_filters.get(&r).cloned().unwrap_or_default();
+        filters.push(filter);
+        let cmd = self.do_edit(Command::SetModalFilters(r, filters));
+        self.push_undo(cmd);
         self.after_edited();
     }
 
-    fn add_modal_filter_cmd(
+    fn closest_modal_filter(
         &self,
         pt: Coord,
         candidate_roads: Option<Vec<RoadID>>,
-        mut kind: FilterKind,
-    ) -> Command {
+        kind: FilterKind,
+    ) -> (RoadID, ModalFilter) {
         let (r, percent_along) = self.closest_point_on_road(pt, candidate_roads).unwrap();
-        if self.get_bus_routes_on_road(r).is_some() && kind != FilterKind::BusGate {
-            info!("Using a BusGate instead of {kind:?} for a road");
-            kind = FilterKind::BusGate;
-        }
-        Command::SetModalFilter(
+        (
             r,
-            Some(ModalFilter {
+            ModalFilter {
                 percent_along,
-                kind,
-            }),
+                kind: self.filter_kind_for_road(r, kind),
+            },
         )
     }
 
+    /// Filters on bus routes become bus gates
+    fn filter_kind_for_road(&self, r: RoadID, kind: FilterKind) -> FilterKind {
+        if self.get_bus_routes_on_road(r).is_some() && kind.blocks(VehicleProfile::Bus) {
+            info!("Using a BusGate instead of {kind:?} for a road");
+            return FilterKind::BusGate;
+        }
+        kind
+    }
+
     fn closest_point_on_road(
         &self,
         click_pt: Coord,
@@ -388,29 +441,13 @@ impl MapModel {
             .map(|pair| (pair.1, pair.2))
     }
 
-    fn most_similar_linestring(&self, linestring: &LineString) -> RoadID {
-        // TODO Detect many possible cases of OSM data changing. Could at least compare the length
-        // of the candidate. Decide how to handle possible splits/merges.
-        self.roads
-            .iter()
-            .min_by_key(|r| {
-                let diff1 = Euclidean.distance(
-                    r.linestring.points().next().unwrap(),
-                    linestring.points().next().unwrap(),
-                );
-                let diff2 = Euclidean.distance(
-                    r.linestring.points().last().unwrap(),
-                    linestring.points().last().unwrap(),
-                );
-                ((diff1 + diff2) * 100.0) as usize
-            })
-            .unwrap()
-            .id
-    }
-
-    fn after_edited(&mut self) {
+    pub(crate) fn after_edited(&mut self) {
         self.router_after = None;
-        self.impact.as_mut().unwrap().invalidate_after_edits();
+        self.profile_routers_after.clear();
+        // Comparing scenarios temporarily takes the impact
+        if let Some(ref mut impact) = self.impact {
+            impact.invalidate_after_edits();
+        }
     }
 
     pub fn add_many_modal_filters(
@@ -423,31 +460,37 @@ impl MapModel {
         for r in candidate_roads {
             let road = self.get_r(*r);
             if let Some(percent_along) = linestring_intersection(&road.linestring, &along_line) {
-                let mut use_kind = kind;
-                if self.get_bus_routes_on_road(*r).is_some() && kind != FilterKind::BusGate {
-                    info!("Using a BusGate instead of {kind:?} for a road");
-                    use_kind = FilterKind::BusGate;
-                }
-
-                edits.push(Command::SetModalFilter(
-                    *r,
-                    Some(ModalFilter {
-                        percent_along,
-                        kind: use_kind,
-                    }),
-                ));
+                let mut filters = self.modal_filters.get(r).cloned().unwrap_or_default();
+                filters.push(ModalFilter {
+                    percent_along,
+                    kind: self.filter_kind_for_road(*r, kind),
+                });
+                edits.push(Command::SetModalFilters(*r, filters));
             }
         }
         let cmd = self.do_edit(Command::Multiple(edits));
-        self.undo_stack.push(cmd);
-        self.redo_stack.clear();
+        self.push_undo(cmd);
         self.after_edited();
     }
 
-    pub fn delete_modal_filter(&mut self, r: RoadID) {
-        let cmd = self.do_edit(Command::SetModalFilter(r, None));
-        self.undo_stack.push(cmd);
-        self.redo_stack.clear();
+    /// Deletes one filter from a road, indexed by position along the road, or all of them
+    pub fn delete_modal_filter(&mut self, r: RoadID, idx: Option<usize>) {
+        let mut filters = self.modal_filters.get(&r).cloned().unwrap_or_default();
+        match idx {
+            Some(idx) => {
+                if idx >= filters.len() {
+                    return;
+                }
+                filters.remove(idx);
+            }
+            None => filters.clear(),
+        }
+        let mut cmd = Command::SetModalFilters(r, filters.clone());
+        if filters.is_empty() {
+            cmd = self.clearing_annotation(cmd, Intervention::ModalFilter { road: r });
+        }
+        let cmd = self.do_edit(cmd);
+        self.push_undo(cmd);
         self.after_edited();
     }
 
@@ -456,8 +499,7 @@ impl MapModel {
         let diagonal_filter = DiagonalFilter::new(intersection, false, self);
         let cmd = Command::SetDiagonalFilter(i, Some(diagonal_filter));
         let undo_cmd = self.do_edit(cmd);
-        self.undo_stack.push(undo_cmd);
-        self.redo_stack.clear();
+        self.push_undo(undo_cmd);
         self.after_edited();
     }
 
@@ -466,16 +508,29 @@ impl MapModel {
         let diagonal_filter = DiagonalFilter::new(intersection, true, self);
         let cmd = Command::SetDiagonalFilter(i, Some(diagonal_filter));
         let undo_cmd = self.do_edit(cmd);
-        self.undo_stack.push(undo_cmd);
-        self.redo_stack.clear();
+        self.push_undo(undo_cmd);
         self.after_edited();
     }
 
+    /// Replaces the diagonal filter at an intersection with one splitting `group_a` from the
+    /// other roads
+    pub fn set_diagonal_filter_groups(
+        &mut self,
+        i: IntersectionID,
+        group_a: Vec<RoadID>,
+    ) -> Result<()> {
+        let diagonal_filter = DiagonalFilter::from_group(self.get_i(i), group_a, self)?;
+        self.apply_command(Command::SetDiagonalFilter(i, Some(diagonal_filter)));
+        Ok(())
+    }
+
     pub fn delete_diagonal_filter(&mut self, i: IntersectionID) {
-        let cmd = Command::SetDiagonalFilter(i, None);
+        let cmd = self.clearing_annotation(
+            Command::SetDiagonalFilter(i, None),
+            Intervention::DiagonalFilter { intersection: i },
+        );
         let undo_cmd = self.do_edit(cmd);
-        self.undo_stack.push(undo_cmd);
-        self.redo_stack.clear();
+        self.push_undo(undo_cmd);
         self.after_edited();
     }
 
@@ -499,8 +554,7 @@ impl MapModel {
 
         let cmd = Command::SetTurnRestrictions(i, restrictions);
         let undo_cmd = self.do_edit(cmd);
-        self.undo_stack.push(undo_cmd);
-        self.redo_stack.clear();
+        self.push_undo(undo_cmd);
         self.after_edited();
         Ok(())
     }
@@ -514,10 +568,16 @@ impl MapModel {
         let mut restrictions = self.turn_restrictions[i.0].clone();
         restrictions.retain(|(a, b)| (*a, *b) != (from, to));
 
-        let cmd = Command::SetTurnRestrictions(i, restrictions);
+        let cmd = self.clearing_annotation(
+            Command::SetTurnRestrictions(i, restrictions),
+            Intervention::TurnRestriction {
+                intersection: i,
+                from_road: from,
+                to_road: to,
+            },
+        );
         let undo_cmd = self.do_edit(cmd);
-        self.undo_stack.push(undo_cmd);
-        self.redo_stack.clear();
+        self.push_undo(undo_cmd);
         self.after_edited();
         Ok(())
     }
@@ -529,16 +589,47 @@ impl MapModel {
             TravelFlow::BothWays => TravelFlow::FORWARDS,
         };
         let cmd = self.do_edit(Command::SetTravelFlow(r, dir));
-        self.undo_stack.push(cmd);
-        self.redo_stack.clear();
+        self.push_undo(cmd);
         self.after_edited();
     }
 
+    pub fn set_speed_limit(&mut self, r: RoadID, speed_mph: f64) -> Result<()> {
+        check_speed_limit(speed_mph)?;
+        if r.0 >= self.roads.len() {
+            bail!("{r} doesn't exist");
+        }
+        self.apply_command(Command::SetSpeedLimit(r, speed_mph));
+        Ok(())
+    }
+
+    /// Sets the speed limit on every road along a line drawn through these intersections
+    pub fn set_speed_limits(
+        &mut self,
+        intersections: Vec<IntersectionID>,
+        speed_mph: f64,
+    ) -> Result<()> {
+        check_speed_limit(speed_mph)?;
+        let mut roads = BTreeSet::new();
+        for pair in intersections.windows(2) {
+            if let Some(road) = self.find_road_between(pair[0], pair[1]) {
+                roads.insert(road.id);
+            }
+        }
+        let cmds: Vec<Command> = roads
+            .into_iter()
+            .filter(|r| self.speed_limits[r] != speed_mph)
+            .map(|r| Command::SetSpeedLimit(r, speed_mph))
+            .collect();
+        if !cmds.is_empty() {
+            self.apply_command(Command::Multiple(cmds));
+        }
+        Ok(())
+    }
+
     pub fn toggle_main_road(&mut self, r: RoadID) {
         let is_main_road = !self.is_main_road[&r];
         let cmd = self.do_edit(Command::SetMainRoad(r, is_main_road));
-        self.undo_stack.push(cmd);
-        self.redo_stack.clear();
+        self.push_undo(cmd);
         self.after_edited();
     }
 
@@ -576,11 +667,75 @@ impl MapModel {
             return;
         }
         let undo_cmd = self.do_edit(Command::Multiple(cmds));
-        self.undo_stack.push(undo_cmd);
-        self.redo_stack.clear();
+        self.push_undo(undo_cmd);
         self.after_edited();
     }
 
+    /// Sets the travel flow on every candidate road that a drawn line follows. `dir` is relative
+    /// to the direction the line was drawn.
+    pub fn set_travel_flow_along_line(
+        &mut self,
+        along_line: LineString,
+        candidate_roads: &BTreeSet<RoadID>,
+        dir: TravelFlow,
+    ) {
+        let cmds = self
+            .roads_following_line(&along_line, candidate_roads)
+            .into_iter()
+            .map(|(r, same_direction)| {
+                let dir = match dir {
+                    TravelFlow::BothWays => TravelFlow::BothWays,
+                    _ if same_direction == (dir == TravelFlow::FORWARDS) => TravelFlow::FORWARDS,
+                    _ => TravelFlow::BACKWARDS,
+                };
+                (r, dir)
+            })
+            .filter(|(r, dir)| self.travel_flows[r] != *dir)
+            .map(|(r, dir)| Command::SetTravelFlow(r, dir))
+            .collect::<Vec<_>>();
+        if cmds.is_empty() {
+            return;
+        }
+        self.apply_command(Command::Multiple(cmds));
+    }
+
+    /// Sets every candidate road that a drawn line follows to be a main road or not
+    pub fn set_main_road_along_line(
+        &mut self,
+        along_line: LineString,
+        candidate_roads: &BTreeSet<RoadID>,
+        make_main_road: bool,
+    ) {
+        let cmds = self
+            .roads_following_line(&along_line, candidate_roads)
+            .into_iter()
+            .filter(|(r, _)| self.is_main_road[r] != make_main_road)
+            .map(|(r, _)| Command::SetMainRoad(r, make_main_road))
+            .collect::<Vec<_>>();
+        if cmds.is_empty() {
+            return;
+        }
+        self.apply_command(Command::Multiple(cmds));
+    }
+
+    /// Finds roads running alongside a drawn line, rather than crossing it. Also returns if each
+    /// road points the same direction as the line.
+    fn roads_following_line(
+        &self,
+        along_line: &LineString,
+        candidate_roads: &BTreeSet<RoadID>,
+    ) -> Vec<(RoadID, bool)> {
+        // Lines drawn by hand don't exactly match the road geometry
+        let threshold_meters = 10.0;
+        candidate_roads
+            .iter()
+            .filter_map(|r| {
+                linestring_follows(&self.get_r(*r).linestring, along_line, threshold_meters)
+                    .map(|same_direction| (*r, same_direction))
+            })
+            .collect()
+    }
+
     pub fn erase_all_main_roads(&mut self, neighbourhood: &Neighbourhood) {
         let cmds = neighbourhood
             .main_roads
@@ -591,24 +746,26 @@ impl MapModel {
             return;
         }
         let undo_cmd = self.do_edit(Command::Multiple(cmds));
-        self.undo_stack.push(undo_cmd);
-        self.redo_stack.clear();
+        self.push_undo(undo_cmd);
         self.after_edited();
     }
 
     // Returns the command to undo this one
     fn do_edit(&mut self, cmd: Command) -> Command {
         match cmd {
-            Command::SetModalFilter(r, filter) => {
-                let prev = self.modal_filters.get(&r).cloned();
-                if let Some(filter) = filter {
-                    info!("added a filter to {r} at {:.1}%", filter.percent_along);
-                    self.modal_filters.insert(r, filter);
+            Command::SetModalFilters(r, mut filters) => {
+                let prev = self.modal_filters.remove(&r).unwrap_or_default();
+                if filters.is_empty() {
+                    info!("deleted all filters from {r}");
                 } else {
-                    info!("deleted a filter from {r}");
-                    self.modal_filters.remove(&r);
+                    filters.sort_by(|a, b| a.percent_along.total_cmp(&b.percent_along));
+                    info!(
+                        "set filters on {r} at {:?}",
+                        filters.iter().map(|f| f.percent_along).collect::<Vec<_>>()
+                    );
+                    self.modal_filters.insert(r, filters);
                 }
-                Command::SetModalFilter(r, prev)
+                Command::SetModalFilters(r, prev)
             }
             Command::SetDiagonalFilter(i, filter) => {
                 let prev = self.diagonal_filters.get(&i).cloned();
@@ -627,6 +784,11 @@ impl MapModel {
                 self.travel_flows.insert(r, dir);
                 Command::SetTravelFlow(r, prev)
             }
+            Command::SetSpeedLimit(r, speed_mph) => {
+                info!("changed speed limit of {r} to {speed_mph} mph");
+                let prev = self.speed_limits.insert(r, speed_mph).unwrap();
+                Command::SetSpeedLimit(r, prev)
+            }
             Command::SetMainRoad(r, is_main_road) => {
                 info!("changed {r} to now be a main road = {is_main_road}");
                 self.is_main_road.insert(r, is_main_road);
@@ -636,6 +798,14 @@ impl MapModel {
                 std::mem::swap(&mut self.turn_restrictions[i.0], &mut restrictions);
                 Command::SetTurnRestrictions(i, restrictions)
             }
+            Command::SetAnnotation(intervention, annotation) => {
+                let prev = if let Some(annotation) = annotation {
+                    self.annotations.insert(intervention, annotation)
+                } else {
+                    self.annotations.remove(&intervention)
+                };
+                Command::SetAnnotation(intervention, prev)
+            }
             Command::Multiple(list) => {
                 let undo_list = list.into_iter().map(|cmd| self.do_edit(cmd)).collect();
                 Command::Multiple(undo_list)
@@ -643,24 +813,258 @@ impl MapModel {
         }
     }
 
-    /// Returns the command that was reverted.
-    pub fn undo(&mut self) -> Option<Command> {
+    /// Apply an externally built command as one undoable edit.
+    pub fn apply_command(&mut self, cmd: Command) {
+        let undo_cmd = self.do_edit(cmd);
+        self.push_undo(undo_cmd);
+        self.after_edited();
+    }
+
+    /// Records a new edit, given the command to undo it. Nothing can be redone afterwards.
+    fn push_undo(&mut self, undo_cmd: Command) {
+        let entry = self.history_entry(undo_cmd);
+        self.undo_stack.push(entry);
+        self.redo_stack.clear();
+    }
+
+    fn history_entry(&self, cmd: Command) -> HistoryEntry {
+        let expected = self.current_state_of(&cmd);
+        HistoryEntry { cmd, expected }
+    }
+
+    /// Returns the command that was reverted. Fails without changing anything if a later edit
+    /// from another neighbourhood's history changed the same roads or intersections, because
+    /// undoing would silently revert that too.
+    pub fn undo(&mut self) -> Result<Option<Command>> {
         // The UI shouldn't call this when the stack is empty, but when holding down the redo key,
         // it doesn't update fast enough
-        let cmd = self.undo_stack.pop()?;
+        let Some(entry) = self.undo_stack.last() else {
+            return Ok(None);
+        };
+        if !self.still_expected(entry) {
+            bail!("Can't undo, because another neighbourhood has since edited the same place");
+        }
+        let cmd = self.undo_stack.pop().unwrap().cmd;
         let redo_cmd = self.do_edit(cmd.clone());
-        self.redo_stack.push(redo_cmd);
+        let entry = self.history_entry(redo_cmd);
+        self.redo_stack.push(entry);
         self.after_edited();
-        Some(cmd)
+        Ok(Some(cmd))
     }
 
-    /// Returns the command that was applied.
-    pub fn redo(&mut self) -> Option<Command> {
-        let cmd = self.redo_stack.pop()?;
+    /// Returns the command that was applied. Fails like `undo`.
+    pub fn redo(&mut self) -> Result<Option<Command>> {
+        let Some(entry) = self.redo_stack.last() else {
+            return Ok(None);
+        };
+        if !self.still_expected(entry) {
+            bail!("Can't redo, because another neighbourhood has since edited the same place");
+        }
+        let cmd = self.redo_stack.pop().unwrap().cmd;
         let undo_cmd = self.do_edit(cmd.clone());
-        self.undo_stack.push(undo_cmd);
+        let entry = self.history_entry(undo_cmd);
+        self.undo_stack.push(entry);
         self.after_edited();
-        Some(cmd)
+        Ok(Some(cmd))
+    }
+
+    fn still_expected(&self, entry: &HistoryEntry) -> bool {
+        self.current_state_of(&entry.cmd) == entry.expected
+    }
+
+    /// Returns a command that would set everything `cmd` changes to its current value
+    fn current_state_of(&self, cmd: &Command) -> Command {
+        match cmd {
+            Command::SetModalFilters(r, _) => {
+                Command::SetModalFilters(*r, self.modal_filters.get(r).cloned().unwrap_or_default())
+            }
+            Command::SetDiagonalFilter(i, _) => {
+                Command::SetDiagonalFilter(*i, self.diagonal_filters.get(i).cloned())
+            }
+            Command::SetTravelFlow(r, _) => Command::SetTravelFlow(*r, self.travel_flows[r]),
+            Command::SetMainRoad(r, _) => Command::SetMainRoad(*r, self.is_main_road[r]),
+            Command::SetSpeedLimit(r, _) => Command::SetSpeedLimit(*r, self.speed_limits[r]),
+            Command::SetTurnRestrictions(i, _) => {
+                Command::SetTurnRestrictions(*i, self.turn_restrictions[i.0].clone())
+            }
+            Command::SetAnnotation(intervention, _) => {
+                Command::SetAnnotation(*intervention, self.annotations.get(intervention).cloned())
+            }
+            Command::Multiple(list) => {
+                Command::Multiple(list.iter().map(|cmd| self.current_state_of(cmd)).collect())
+            }
+        }
+    }
+
+    /// Does a command from a saved edit history refer to roads and intersections that exist and
+    /// fit together?
+    fn command_fits_map(&self, cmd: &Command) -> bool {
+        let road = |r: &RoadID| r.0 < self.roads.len();
+        let intersection = |i: &IntersectionID| i.0 < self.intersections.len();
+        let at =
+            |i: &IntersectionID, r: &RoadID| intersection(i) && self.get_i(*i).roads.contains(r);
+        match cmd {
+            Command::SetModalFilters(r, _)
+            | Command::SetTravelFlow(r, _)
+            | Command::SetMainRoad(r, _)
+            | Command::SetSpeedLimit(r, _) => road(r),
+            Command::SetDiagonalFilter(i, filter) => {
+                intersection(i)
+                    && filter.as_ref().is_none_or(|filter| {
+                        filter
+                            .group_a
+                            .iter()
+                            .chain(&filter.group_b)
+                            .all(|r| at(i, r))
+                    })
+            }
+            Command::SetTurnRestrictions(i, list) => {
+                intersection(i) && list.iter().all(|(from, to)| at(i, from) && at(i, to))
+            }
+            Command::SetAnnotation(intervention, _) => match intervention {
+                Intervention::ModalFilter { road: r } | Intervention::TravelFlow { road: r } => {
+                    road(r)
+                }
+                Intervention::DiagonalFilter { intersection: i } => intersection(i),
+                Intervention::TurnRestriction {
+                    intersection: i,
+                    from_road,
+                    to_road,
+                } => at(i, from_road) && at(i, to_road),
+            },
+            Command::Multiple(list) => list.iter().all(|cmd| self.command_fits_map(cmd)),
+        }
+    }
+
+    /// Saved edit histories are only kept if they're for the same OSM data and every command
+    /// fits this map. Problems are logged, not errors, because the edits themselves still load.
+    fn load_edit_histories(
+        &self,
+        json: &serde_json::Map<String, serde_json::Value>,
+    ) -> BTreeMap<String, BTreeMap<String, EditHistory>> {
+        let Some(histories) = json.get("edit_histories") else {
+            return BTreeMap::new();
+        };
+        let timestamp = json
+            .get("edit_histories_osm_timestamp")
+            .and_then(|x| x.as_i64());
+        // Without a timestamp, there's no way to tell if the IDs refer to the same roads
+        if timestamp.is_none() || timestamp != self.osm_timestamp {
+            warn!("Savefile edit histories may be for different OSM data; ignoring them");
+            return BTreeMap::new();
+        }
+        // Keyed by scenario, then neighbourhood
+        let mut histories: BTreeMap<String, BTreeMap<String, EditHistory>> =
+            match serde_json::from_value(histories.clone()) {
+                Ok(x) => x,
+                Err(err) => {
+                    warn!("Savefile edit histories are invalid; ignoring them: {err}");
+                    return BTreeMap::new();
+                }
+            };
+        for (scenario, per_scenario) in &mut histories {
+            per_scenario.retain(|name, history| {
+                let fits = history
+                    .undo_stack
+                    .iter()
+                    .chain(&history.redo_stack)
+                    .all(|entry| {
+                        self.command_fits_map(&entry.cmd) && self.command_fits_map(&entry.expected)
+                    });
+                if !fits {
+                    warn!(
+                        "Savefile edit history for {name} in {scenario} doesn't fit this map; \
+                         ignoring it"
+                    );
+                }
+                fits
+            });
+        }
+        histories
+    }
+
+    /// Keep the current undo/redo history for its neighbourhood, and restore the history for
+    /// another neighbourhood.
+    pub fn switch_history(&mut self, name: &str) {
+        if self.history_owner.as_deref() == Some(name) {
+            return;
+        }
+        let current = EditHistory {
+            undo_stack: std::mem::take(&mut self.undo_stack),
+            redo_stack: std::mem::take(&mut self.redo_stack),
+        };
+        if let Some(owner) = self.history_owner.take() {
+            self.other_histories.insert(owner, current);
+        }
+        let restored = self.other_histories.remove(name).unwrap_or_default();
+        self.undo_stack = restored.undo_stack;
+        self.redo_stack = restored.redo_stack;
+        self.history_owner = Some(name.to_string());
+    }
+
+    /// Renames a neighbourhood's history in every scenario
+    pub fn rename_history(&mut self, old_name: &str, new_name: &str) {
+        if self.history_owner.as_deref() == Some(old_name) {
+            self.history_owner = Some(new_name.to_string());
+        } else if let Some(history) = self.other_histories.remove(old_name) {
+            self.other_histories.insert(new_name.to_string(), history);
+        }
+        for state in self.scenarios.values_mut() {
+            if let Some(history) = state.histories.remove(old_name) {
+                state.histories.insert(new_name.to_string(), history);
+            }
+        }
+    }
+
+    /// Deletes a neighbourhood's history in every scenario
+    pub fn delete_history(&mut self, name: &str) {
+        if self.history_owner.as_deref() == Some(name) {
+            self.history_owner = None;
+            self.undo_stack.clear();
+            self.redo_stack.clear();
+        } else {
+            self.other_histories.remove(name);
+        }
+        for state in self.scenarios.values_mut() {
+            state.histories.remove(name);
+        }
+    }
+
+    /// Every neighbourhood's history in the current scenario, including the current one
+    pub fn all_histories(&self) -> BTreeMap<String, EditHistory> {
+        let mut histories = self.other_histories.clone();
+        if let Some(ref owner) = self.history_owner {
+            histories.insert(
+                owner.clone(),
+                EditHistory {
+                    undo_stack: self.undo_stack.clone(),
+                    redo_stack: self.redo_stack.clone(),
+                },
+            );
+        }
+        histories
+    }
+
+    /// Replaces every neighbourhood's history, keeping the same neighbourhood current. Returns
+    /// the previous histories, in the form `all_histories` would. A history without an owner is
+    /// lost.
+    pub fn swap_all_histories(
+        &mut self,
+        mut histories: BTreeMap<String, EditHistory>,
+    ) -> BTreeMap<String, EditHistory> {
+        let mut previous = std::mem::take(&mut self.other_histories);
+        let current = EditHistory {
+            undo_stack: std::mem::take(&mut self.undo_stack),
+            redo_stack: std::mem::take(&mut self.redo_stack),
+        };
+        if let Some(ref owner) = self.history_owner {
+            previous.insert(owner.clone(), current);
+            let restored = histories.remove(owner).unwrap_or_default();
+            self.undo_stack = restored.undo_stack;
+            self.redo_stack = restored.redo_stack;
+        }
+        self.other_histories = histories;
+        previous
     }
 
     // NOTE: this method is used both for saving and for serializing to the frontend,
@@ -668,19 +1072,34 @@ impl MapModel {
     // to split up this functionality
     pub fn filters_to_gj(&self) -> FeatureCollection {
         let mut features = Vec::new();
-        for (r, filter) in &self.modal_filters {
+        for (r, filters) in &self.modal_filters {
             let road = self.get_r(*r);
-            let pt = road
-                .linestring
-                .point_at_ratio_from_start(&Euclidean, filter.percent_along)
-                .unwrap();
-            let angle = limit_angle(angle_of_pt_on_line(&road.linestring, pt.into()) + 90.0);
-            let mut f = self.mercator.to_wgs84_gj(&pt);
-            f.set_property("filter_kind", filter.kind.to_string());
-            f.set_property("road", r.0);
-            f.set_property("angle", angle);
-            f.set_property("edited", Some(filter) != self.original_modal_filters.get(r));
-            features.push(f);
+            let original = self.original_modal_filters.get(r);
+            for (idx, filter) in filters.iter().enumerate() {
+                let pt = road
+                    .linestring
+                    .point_at_ratio_from_start(&Euclidean, filter.percent_along)
+                    .unwrap();
+                let angle = limit_angle(angle_of_pt_on_line(&road.linestring, pt.into()) + 90.0);
+                let mut f = self.mercator.to_wgs84_gj(&pt);
+                f.set_property("filter_kind", filter.kind.to_string());
+                f.set_property("exempt_vehicles", filter.kind.exempt_profiles());
+                f.set_property("road", r.0);
+                f.set_property("filter_idx", idx);
+                f.set_property("angle", angle);
+                f.set_property(
+                    "edited",
+                    !original.is_some_and(|list| list.contains(filter)),
+                );
+                // Annotations belong to all the filters on a road
+                if let Some(annotation) = self
+                    .annotations
+                    .get(&Intervention::ModalFilter { road: *r })
+                {
+                    f.set_property("annotation", serde_json::to_value(annotation).unwrap());
+                }
+                features.push(f);
+            }
         }
         for (i, filter) in &self.diagonal_filters {
             let intersection = self.get_i(*i);
@@ -688,8 +1107,24 @@ impl MapModel {
             f.set_property("filter_kind", FilterKind::DiagonalFilter.to_string());
             f.set_property("intersection_id", i.0);
             f.set_property("filter", filter);
+            // Road IDs aren't stable across basemap updates, so savefiles match the groups by
+            // bearing
+            f.set_property(
+                "group_a_bearings",
+                filter
+                    .group_a
+                    .iter()
+                    .map(|r| intersection.bearing_of_road(self.get_r(*r)))
+                    .collect::<Vec<_>>(),
+            );
             // part of being a "filter"
             f.set_property("edited", true);
+            if let Some(annotation) = self
+                .annotations
+                .get(&Intervention::DiagonalFilter { intersection: *i })
+            {
+                f.set_property("annotation", serde_json::to_value(annotation).unwrap());
+            }
             features.push(f);
         }
         FeatureCollection {
@@ -699,27 +1134,41 @@ impl MapModel {
         }
     }
 
-    /// Because ids like RoadID and IntersectionID aren't guaranteed to be stable across loads,
-    /// we use more permanent markers like GPS points to map to features.
-    pub fn to_savefile(&self) -> FeatureCollection {
-        // Edited filters only
+    /// Edited filters, travel flows, main roads, and turn restrictions, relative to the basemap
+    fn edits_to_gj(&self) -> Vec<Feature> {
+        // Edited filters only. When any filter on a road changes, all of the road's filters are
+        // saved, because loading the first one replaces the basemap filters.
         let mut gj = self.filters_to_gj();
-        gj.features
-            .retain(|f| f.property("edited").unwrap().as_bool().unwrap());
+        gj.features.retain(|f| match f.property("road") {
+            Some(r) => {
+                let r = RoadID(r.as_u64().unwrap() as usize);
+                self.modal_filters.get(&r) != self.original_modal_filters.get(&r)
+            }
+            None => f.property("edited").unwrap().as_bool().unwrap(),
+        });
         for f in &mut gj.features {
             f.set_property("kind", "modal_filter");
             f.remove_property("road");
+            f.remove_property("filter_idx");
+            // Saved separately, because unedited filters can have annotations too
+            f.remove_property("annotation");
+            // The internal diagonal filter refers to roads by unstable IDs. The groups are saved
+            // by bearing instead.
+            f.remove_property("intersection_id");
+            if let Some(filter) = f.remove_property("filter") {
+                f.set_property("is_rotated", filter["is_rotated"].clone());
+            }
         }
 
-        // Look for any basemap filters that were deleted entirely
-        for (r, filter) in &self.original_modal_filters {
+        // Look for any roads with basemap filters that were deleted entirely
+        for (r, filters) in &self.original_modal_filters {
             if self.modal_filters.contains_key(r) {
                 continue;
             }
             let pt = self
                 .get_r(*r)
                 .linestring
-                .point_at_ratio_from_start(&Euclidean, filter.percent_along)
+                .point_at_ratio_from_start(&Euclidean, filters[0].percent_along)
                 .unwrap();
             let mut f = self.mercator.to_wgs84_gj(&pt);
             f.set_property("kind", "deleted_existing_modal_filter");
@@ -741,6 +1190,13 @@ impl MapModel {
                 f.set_property("is_main_road", self.is_main_road[&r.id]);
                 gj.features.push(f);
             }
+
+            if self.speed_limits[&r.id] != r.speed_mph {
+                let mut f = self.mercator.to_wgs84_gj(&r.linestring);
+                f.set_property("kind", "speed_limit");
+                f.set_property("speed_mph", self.speed_limits[&r.id]);
+                gj.features.push(f);
+            }
         }
 
         // Edited turn restrictions only
@@ -780,6 +1236,72 @@ impl MapModel {
             }
         }
 
+        // Annotations are saved separately from the interventions, since unedited ones can have
+        // annotations too
+        for (intervention, annotation) in self.current_annotations() {
+            let mut f = match *intervention {
+                Intervention::ModalFilter { road } => {
+                    let pt = self
+                        .get_r(road)
+                        .linestring
+                        .point_at_ratio_from_start(
+                            &Euclidean,
+                            self.modal_filters[&road][0].percent_along,
+                        )
+                        .unwrap();
+                    self.mercator.to_wgs84_gj(&pt)
+                }
+                Intervention::DiagonalFilter { intersection } => {
+                    self.mercator.to_wgs84_gj(&self.get_i(intersection).point)
+                }
+                Intervention::TurnRestriction {
+                    intersection,
+                    from_road,
+                    to_road,
+                } => {
+                    let intersection = self.get_i(intersection);
+                    let mut f = self.mercator.to_wgs84_gj(&intersection.point);
+                    let (abs_bearing_1, abs_bearing_2) =
+                        intersection.bearing_of_roads(self.get_r(from_road), self.get_r(to_road));
+                    f.set_property("bearing1", abs_bearing_1.round());
+                    f.set_property("bearing2", abs_bearing_2.round());
+                    f
+                }
+                Intervention::TravelFlow { road } => {
+                    self.mercator.to_wgs84_gj(&self.get_r(road).linestring)
+                }
+            };
+            f.set_property("kind", "annotation");
+            f.set_property("intervention", intervention.kind());
+            f.set_property("annotation", serde_json::to_value(annotation).unwrap());
+            gj.features.push(f);
+        }
+
+        gj.features
+    }
+
+    /// Because ids like RoadID and IntersectionID aren't guaranteed to be stable across loads,
+    /// we use more permanent markers like GPS points to map to features.
+    pub fn to_savefile(&mut self) -> FeatureCollection {
+        let mut gj = FeatureCollection {
+            features: self.edits_to_gj(),
+            bbox: None,
+            foreign_members: None,
+        };
+
+        // Edits in other scenarios are tagged with the scenario name
+        let names: Vec<String> = self.scenarios.keys().cloned().collect();
+        for name in names {
+            let mut state = self.scenarios.remove(&name).unwrap();
+            self.swap_edit_state(&mut state);
+            for mut f in self.edits_to_gj() {
+                f.set_property("scenario", name.clone());
+                gj.features.push(f);
+            }
+            self.swap_edit_state(&mut state);
+            self.scenarios.insert(name, state);
+        }
+
         for neighbourhood_boundary in self.boundaries.values() {
             // we don't save the derived "stats" just the boundary definition
             gj.features
@@ -790,153 +1312,420 @@ impl MapModel {
         f.set_property("kind", "study_area_boundary");
         gj.features.push(f);
 
-        gj.foreign_members = Some(
-            // The features are elements within the study area, we store properties of the
-            // project itself as foreign members.
-            serde_json::json!(self
-                .project_details
-                .as_ref()
-                .expect("finish_loading never called"))
-            .as_object()
-            .unwrap()
-            .to_owned(),
+        let mut foreign_members = serde_json::json!(self
+            .project_details
+            .as_ref()
+            .expect("finish_loading never called"))
+        .as_object()
+        .unwrap()
+        .to_owned();
+        foreign_members.insert(
+            "savefile_version".to_string(),
+            serde_json::json!(SAVEFILE_VERSION),
+        );
+        foreign_members.insert(
+            "current_scenario".to_string(),
+            serde_json::json!(self.current_scenario),
         );
+        foreign_members.insert(
+            "scenarios".to_string(),
+            serde_json::json!(self.list_scenarios()),
+        );
+
+        // Edit histories refer to road and intersection IDs, so they're only valid for the same
+        // OSM data. Without a timestamp, there's no way to check that when loading.
+        let mut histories = BTreeMap::new();
+        histories.insert(self.current_scenario.clone(), self.all_histories());
+        for (name, state) in &self.scenarios {
+            histories.insert(name.clone(), state.histories.clone());
+        }
+        for per_scenario in histories.values_mut() {
+            per_scenario.retain(|_, h| !h.undo_stack.is_empty() || !h.redo_stack.is_empty());
+        }
+        histories.retain(|_, per_scenario| !per_scenario.is_empty());
+        if !histories.is_empty() && self.osm_timestamp.is_some() {
+            foreign_members.insert("edit_histories".to_string(), serde_json::json!(histories));
+            foreign_members.insert(
+                "edit_histories_osm_timestamp".to_string(),
+                serde_json::json!(self.osm_timestamp),
+            );
+        }
+
+        // The features are elements within the study area, we store properties of the
+        // project itself as foreign members.
+        gj.foreign_members = Some(foreign_members);
         gj
     }
 
-    pub fn load_savefile(&mut self, gj: FeatureCollection) -> Result<()> {
+    /// Replaces all edits with the ones in a savefile. Saved edits are matched to the current map
+    /// by geometry; returns a report of the ones that matched poorly or were dropped.
+    ///
+    /// A malformed feature fails the load with an `InvalidFeature` error and leaves the current
+    /// edits alone, unless `skip_invalid` is set. Then the feature is left out and listed in the
+    /// report.
+    pub fn load_savefile(
+        &mut self,
+        mut gj: FeatureCollection,
+        skip_invalid: bool,
+    ) -> Result<FeatureCollection> {
+        migrate_savefile(&mut gj)?;
+
+        // finish_loading sets the project_details for the initial load, but if the user switches
+        // projects in the same study area, we need to overwrite these.
+        let Some(json) = gj.foreign_members.as_ref() else {
+            bail!("Savefile is missing project details");
+        };
+        let details: ProjectDetails =
+            serde_json::from_value(serde_json::Value::Object(json.clone()))?;
+        let Some(current_scenario) = json.get("current_scenario").and_then(|x| x.as_str()) else {
+            bail!("Savefile is missing current_scenario");
+        };
+
+        let mut histories = self.load_edit_histories(json);
+
+        // Check and match every feature before changing anything
+        let mut report = MatchReport::default();
+        let mut boundaries = BTreeMap::new();
+        // Filters could be defined for multiple neighbourhoods, not just the one
+        // in the savefile
+        let mut edits = Vec::new();
+        // Edits belonging to scenarios besides the current one
+        let mut other_scenarios: BTreeMap<String, Vec<SavefileEdit>> = BTreeMap::new();
+
+        for (index, f) in gj.features.iter().enumerate() {
+            let parsed = match self.parse_savefile_feature(f.clone(), &boundaries) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    let invalid = InvalidFeature::new(index, f, err);
+                    if !skip_invalid {
+                        return Err(invalid.into());
+                    }
+                    report.skip_invalid(f, invalid);
+                    continue;
+                }
+            };
+            match parsed {
+                SavefileFeature::Edit(scenario, result) => {
+                    let list = match scenario {
+                        Some(name) => other_scenarios.entry(name).or_default(),
+                        None => &mut edits,
+                    };
+                    if let Some(edit) = report.check(index, f, result) {
+                        list.push(edit);
+                    }
+                }
+                SavefileFeature::Boundary(boundary) => {
+                    boundaries.insert(boundary.name().to_string(), boundary);
+                }
+                SavefileFeature::StudyAreaBoundary => {}
+            }
+        }
+
         // Clear previous state
-        self.boundaries.clear();
+        self.boundaries = boundaries;
         self.modal_filters = self.original_modal_filters.clone();
         self.diagonal_filters.clear();
         self.turn_restrictions = self.original_turn_restrictions.clone();
+        self.annotations.clear();
         for (r, dir) in &mut self.travel_flows {
             *dir = TravelFlow::from_osm(&self.roads[r.0].tags);
         }
         for (r, is_main_road) in &mut self.is_main_road {
             *is_main_road = self.roads[r.0].is_severance();
         }
+        for (r, speed_mph) in &mut self.speed_limits {
+            *speed_mph = self.roads[r.0].speed_mph;
+        }
         self.undo_stack.clear();
         self.redo_stack.clear();
+        self.history_owner = None;
+        self.other_histories = histories.remove(current_scenario).unwrap_or_default();
+        self.scenarios.clear();
 
-        // Filters could be defined for multiple neighbourhoods, not just the one
-        // in the savefile
         let mut cmds = Vec::new();
+        for edit in edits {
+            self.apply_savefile_edit(edit, &mut cmds);
+        }
+        // Keep the undo stack empty. A user shouldn't be able to undo and clear the whole
+        // savefile.
+        self.do_edit(Command::Multiple(cmds));
 
-        for f in gj.features {
-            match f
-                .property("kind")
-                .expect("savefile feature missing `kind`")
-                .as_str()
-                .unwrap()
-            {
-                "modal_filter" => {
-                    let kind = FilterKind::from_string(get_str_prop(&f, "filter_kind")?)?;
-                    let gj_pt: Point = f.geometry.as_ref().unwrap().try_into()?;
-                    match kind {
-                        FilterKind::DiagonalFilter => {
-                            let i = {
-                                let pt = self.mercator.pt_to_mercator(gj_pt.into());
-                                self.closest_intersection
-                                    .nearest_neighbor(&Point(pt))
-                                    .expect("intersection near saved editable intersection")
-                                    .data
-                            };
-                            let intersection = self.get_i(i);
-                            let filter = f.property("filter").unwrap().as_object().unwrap();
-                            let is_rotated = filter
-                                .get("is_rotated")
-                                .expect("missing is_rotated")
-                                .as_bool()
-                                .expect("expected a bool");
+        for (name, edits) in other_scenarios {
+            let mut state = self.original_edit_state();
+            self.swap_edit_state(&mut state);
+            let mut cmds = Vec::new();
+            for edit in edits {
+                self.apply_savefile_edit(edit, &mut cmds);
+            }
+            self.do_edit(Command::Multiple(cmds));
+            self.swap_edit_state(&mut state);
+            state.histories = histories.remove(&name).unwrap_or_default();
+            self.scenarios.insert(name, state);
+        }
+        self.after_edited();
 
-                            let diagonal_filter =
-                                DiagonalFilter::new(intersection, is_rotated, self);
-                            self.diagonal_filters
-                                .insert(intersection.id, diagonal_filter);
-                        }
-                        _ => {
-                            cmds.push(self.add_modal_filter_cmd(
-                                self.mercator.pt_to_mercator(gj_pt.into()),
-                                None,
-                                kind,
-                            ));
-                        }
-                    }
-                }
-                "deleted_existing_modal_filter" => {
-                    let gj_pt: Point = f.geometry.unwrap().try_into()?;
-                    let pt = self.mercator.pt_to_mercator(gj_pt.into());
-                    // TODO Better error handling if we don't match
-                    let (r, _) = self.closest_point_on_road(pt, None).unwrap();
-                    cmds.push(Command::SetModalFilter(r, None));
-                }
-                "travel_flow" => {
-                    let dir = TravelFlow::from_string(get_str_prop(&f, "travel_flow")?)?;
-                    let mut linestring: LineString = f.geometry.unwrap().try_into()?;
-                    self.mercator.to_mercator_in_place(&mut linestring);
-                    let r = self.most_similar_linestring(&linestring);
-                    cmds.push(Command::SetTravelFlow(r, dir));
-                }
-                "main_road" => {
-                    let is_main_road = get_bool_prop(&f, "is_main_road")?;
-                    let mut linestring: LineString = f.geometry.unwrap().try_into()?;
-                    self.mercator.to_mercator_in_place(&mut linestring);
-                    let r = self.most_similar_linestring(&linestring);
-                    cmds.push(Command::SetMainRoad(r, is_main_road));
-                }
-                "turn_restriction" => {
-                    let bearing1 = get_f64_prop(&f, "bearing1")?;
-                    let bearing2 = get_f64_prop(&f, "bearing2")?;
-                    let gj_pt: Point = f.geometry.unwrap().try_into()?;
-                    let pt = self.mercator.pt_to_mercator(gj_pt.into());
-                    let (i, from, to) =
-                        self.find_turn_restriction(pt.into(), bearing1, bearing2)?;
-                    self.turn_restrictions[i.0].push((from, to));
+        self.project_details = Some(details);
+
+        // Scenarios without any edits don't have any features, so create them from the list of
+        // names.
+        self.current_scenario = current_scenario.to_string();
+        if let Some(names) = json.get("scenarios").and_then(|x| x.as_array()) {
+            for name in names.iter().filter_map(|x| x.as_str()) {
+                if !self.has_scenario(name) {
+                    let mut state = self.original_edit_state();
+                    state.histories = histories.remove(name).unwrap_or_default();
+                    self.scenarios.insert(name.to_string(), state);
                 }
-                "deleted_existing_turn_restriction" => {
-                    let bearing1 = get_f64_prop(&f, "bearing1")?;
-                    let bearing2 = get_f64_prop(&f, "bearing2")?;
-                    let gj_pt: Point = f.geometry.unwrap().try_into()?;
-                    let pt = self.mercator.pt_to_mercator(gj_pt.into());
-                    let (i, from, to) =
-                        self.find_turn_restriction(pt.into(), bearing1, bearing2)?;
-                    self.turn_restrictions[i.0].retain(|(a, b)| (*a, *b) != (from, to));
+            }
+        }
+
+        Ok(report.to_gj())
+    }
+
+    /// Checks one savefile feature and matches it to the current map, without changing anything.
+    /// `boundaries` are the ones already in the savefile.
+    fn parse_savefile_feature(
+        &self,
+        f: Feature,
+        boundaries: &BTreeMap<String, NeighbourhoodBoundary>,
+    ) -> Result<SavefileFeature> {
+        match get_str_prop(&f, "kind")? {
+            "boundary" => {
+                let name = get_str_prop(&f, "name")?;
+                if boundaries.contains_key(name) {
+                    bail!("Multiple boundaries named {name} in savefile");
                 }
-                "boundary" => {
-                    let name = get_str_prop(&f, "name")?.to_string();
-                    if self.boundaries.contains_key(&name) {
-                        bail!("Multiple boundaries named {name} in savefile");
+                let neighbourhood_definition = NeighbourhoodDefinition::from_feature(f, self)?;
+                Ok(SavefileFeature::Boundary(NeighbourhoodBoundary::new(
+                    neighbourhood_definition,
+                    self.context_data.as_ref(),
+                )))
+            }
+            "study_area_boundary" => {
+                // TODO Detect if it's close enough to boundary_polygon? Overwrite?
+                Ok(SavefileFeature::StudyAreaBoundary)
+            }
+            _ => {
+                let scenario = match f.property("scenario") {
+                    Some(x) => {
+                        let Some(name) = x.as_str() else {
+                            bail!("Feature's scenario property isn't a string");
+                        };
+                        Some(name.to_string())
+                    }
+                    None => None,
+                };
+                Ok(SavefileFeature::Edit(
+                    scenario,
+                    self.resolve_savefile_edit(f)?,
+                ))
+            }
+        }
+    }
+
+    /// Applies one matched edit from a savefile to the current edits. Some edits are expressed as
+    /// commands to apply later.
+    fn apply_savefile_edit(&mut self, edit: SavefileEdit, cmds: &mut Vec<Command>) {
+        match edit {
+            SavefileEdit::Command(cmd) => cmds.push(cmd),
+            SavefileEdit::AddTurnRestriction(i, from, to) => {
+                self.turn_restrictions[i.0].push((from, to));
+            }
+            SavefileEdit::DeleteTurnRestriction(i, from, to) => {
+                self.turn_restrictions[i.0].retain(|(a, b)| (*a, *b) != (from, to));
+            }
+            SavefileEdit::AddModalFilter(r, filter) => {
+                for cmd in cmds.iter_mut() {
+                    if let Command::SetModalFilters(existing_r, filters) = cmd {
+                        if *existing_r == r {
+                            filters.push(filter);
+                            return;
+                        }
                     }
-                    let neighbourhood_definition = NeighbourhoodDefinition::from_feature(f, self)?;
-                    let neighbourhood_boundary = NeighbourhoodBoundary::new(
-                        neighbourhood_definition,
-                        self.context_data.as_ref(),
-                    );
-                    self.boundaries.insert(name, neighbourhood_boundary);
-                }
-                "study_area_boundary" => {
-                    // TODO Detect if it's close enough to boundary_polygon? Overwrite?
                 }
-                x => bail!("Unknown kind in savefile: {x}"),
+                cmds.push(Command::SetModalFilters(r, vec![filter]));
             }
         }
+    }
 
-        // Keep the undo stack empty. A user shouldn't be able to undo and clear the whole
-        // savefile.
-        self.do_edit(Command::Multiple(cmds));
-        self.after_edited();
+    /// Matches one edit from a savefile to the current map, without applying it. Fails if the
+    /// feature is malformed.
+    pub(crate) fn resolve_savefile_edit(&self, f: Feature) -> Result<Match<SavefileEdit>> {
+        let edit = match get_str_prop(&f, "kind")? {
+            "modal_filter" => {
+                let kind = FilterKind::from_string(get_str_prop(&f, "filter_kind")?)?;
+                let pt = self.saved_point(&f)?;
+                match kind {
+                    FilterKind::DiagonalFilter => {
+                        let is_rotated = get_bool_prop(&f, "is_rotated")?;
+                        let intersection = self.match_intersection(pt).and_then(|i| {
+                            if self.get_i(i).roads.len() < 3 {
+                                Match::NotFound(format!("{i} has fewer than 3 roads"))
+                            } else {
+                                Match::Good(i)
+                            }
+                        });
+                        intersection.try_map(|i| {
+                            let diagonal_filter =
+                                self.saved_diagonal_filter(&f, self.get_i(i), is_rotated)?;
+                            Ok(SavefileEdit::Command(Command::SetDiagonalFilter(
+                                i,
+                                Some(diagonal_filter),
+                            )))
+                        })?
+                    }
+                    _ => self.match_point_on_road(pt).map(|(r, percent_along)| {
+                        SavefileEdit::AddModalFilter(
+                            r,
+                            ModalFilter {
+                                percent_along,
+                                kind: self.filter_kind_for_road(r, kind),
+                            },
+                        )
+                    }),
+                }
+            }
+            "deleted_existing_modal_filter" => {
+                let pt = self.saved_point(&f)?;
+                self.match_point_on_road(pt)
+                    .map(|(r, _)| SavefileEdit::Command(Command::SetModalFilters(r, Vec::new())))
+            }
+            "travel_flow" => {
+                let dir = TravelFlow::from_string(get_str_prop(&f, "travel_flow")?)?;
+                let linestring = self.saved_linestring(&f)?;
+                self.match_road(&linestring).map(|roads| {
+                    per_road_command(roads, |r, same_direction| {
+                        // The direction is relative to the saved line
+                        Command::SetTravelFlow(r, if same_direction { dir } else { dir.reversed() })
+                    })
+                })
+            }
+            "main_road" => {
+                let is_main_road = get_bool_prop(&f, "is_main_road")?;
+                let linestring = self.saved_linestring(&f)?;
+                self.match_road(&linestring).map(|roads| {
+                    per_road_command(roads, |r, _| Command::SetMainRoad(r, is_main_road))
+                })
+            }
+            "speed_limit" => {
+                let speed_mph = get_f64_prop(&f, "speed_mph")?;
+                check_speed_limit(speed_mph)?;
+                let linestring = self.saved_linestring(&f)?;
+                self.match_road(&linestring).map(|roads| {
+                    per_road_command(roads, |r, _| Command::SetSpeedLimit(r, speed_mph))
+                })
+            }
+            "turn_restriction" => {
+                let bearing1 = get_f64_prop(&f, "bearing1")?;
+                let bearing2 = get_f64_prop(&f, "bearing2")?;
+                let pt = self.saved_point(&f)?;
+                self.find_turn_restriction(pt.into(), bearing1, bearing2)
+                    .map(|(i, from, to)| SavefileEdit::AddTurnRestriction(i, from, to))
+            }
+            "deleted_existing_turn_restriction" => {
+                let bearing1 = get_f64_prop(&f, "bearing1")?;
+                let bearing2 = get_f64_prop(&f, "bearing2")?;
+                let pt = self.saved_point(&f)?;
+                self.find_turn_restriction(pt.into(), bearing1, bearing2)
+                    .map(|(i, from, to)| SavefileEdit::DeleteTurnRestriction(i, from, to))
+            }
+            "annotation" => {
+                let Some(annotation) = f.property("annotation") else {
+                    bail!("Feature doesn't have an annotation property");
+                };
+                let annotation: Annotation = serde_json::from_value(annotation.clone())?;
+                let intervention_kind = get_str_prop(&f, "intervention")?.to_string();
+                let intervention = match intervention_kind.as_str() {
+                    "modal_filter" => {
+                        let pt = self.saved_point(&f)?;
+                        self.match_point_on_road(pt)
+                            .map(|(road, _)| Intervention::ModalFilter { road })
+                    }
+                    "diagonal_filter" => {
+                        let pt = self.saved_point(&f)?;
+                        self.match_intersection(pt)
+                            .map(|intersection| Intervention::DiagonalFilter { intersection })
+                    }
+                    "turn_restriction" => {
+                        let bearing1 = get_f64_prop(&f, "bearing1")?;
+                        let bearing2 = get_f64_prop(&f, "bearing2")?;
+                        let pt = self.saved_point(&f)?;
+                        self.find_turn_restriction(pt.into(), bearing1, bearing2)
+                            .map(|(intersection, from_road, to_road)| {
+                                Intervention::TurnRestriction {
+                                    intersection,
+                                    from_road,
+                                    to_road,
+                                }
+                            })
+                    }
+                    "travel_flow" => {
+                        let linestring = self.saved_linestring(&f)?;
+                        // Annotations belong to one road; a split road keeps it on the first piece
+                        self.match_road(&linestring)
+                            .map(|roads| Intervention::TravelFlow { road: roads[0].0 })
+                    }
+                    x => bail!("Unknown intervention in savefile annotation: {x}"),
+                };
+                intervention.map(|intervention| {
+                    SavefileEdit::Command(Command::SetAnnotation(intervention, Some(annotation)))
+                })
+            }
+            x => bail!("Unknown kind in savefile: {x}"),
+        };
+        Ok(edit)
+    }
 
-        // finish_loading sets the project_details for the initial load, but if the user switches
-        // projects in the same study area, we need to overwrite these.
-        let Some(json) = gj.foreign_members.as_ref() else {
-            bail!("Savefile is missing project details");
+    /// The point geometry of a savefile feature, in Mercator
+    fn saved_point(&self, f: &Feature) -> Result<Coord> {
+        let Some(geometry) = &f.geometry else {
+            bail!("Feature doesn't have a geometry");
         };
-        let details: ProjectDetails =
-            serde_json::from_value(serde_json::Value::Object(json.clone()))?;
-        self.project_details = Some(details);
+        let pt: Point = (&geometry.value).try_into()?;
+        Ok(self.mercator.pt_to_mercator(pt.into()))
+    }
 
-        Ok(())
+    /// The LineString geometry of a savefile feature, in Mercator
+    fn saved_linestring(&self, f: &Feature) -> Result<LineString> {
+        let Some(geometry) = &f.geometry else {
+            bail!("Feature doesn't have a geometry");
+        };
+        let mut linestring: LineString = (&geometry.value).try_into()?;
+        self.mercator.to_mercator_in_place(&mut linestring);
+        Ok(linestring)
+    }
+
+    fn saved_diagonal_filter(
+        &self,
+        f: &Feature,
+        intersection: &Intersection,
+        is_rotated: bool,
+    ) -> Result<DiagonalFilter> {
+        let diagonal_filter = DiagonalFilter::new(intersection, is_rotated, self);
+        // Older savefiles only have the default groups
+        let Some(bearings) = f.property("group_a_bearings") else {
+            return Ok(diagonal_filter);
+        };
+        let Some(bearings) = bearings.as_array() else {
+            bail!("group_a_bearings isn't a list");
+        };
+        let mut group_a = Vec::new();
+        for bearing in bearings {
+            let Some(bearing) = bearing.as_f64() else {
+                bail!("group_a_bearings has a non-number");
+            };
+            let r = intersection.road_nearest_bearing(self, bearing);
+            if !group_a.contains(&r) {
+                group_a.push(r);
+            }
+        }
+        group_a.sort();
+        let mut default_group_a = diagonal_filter.group_a.clone();
+        default_group_a.sort();
+        if group_a == default_group_a {
+            return Ok(diagonal_filter);
+        }
+        DiagonalFilter::from_group(intersection, group_a, self)
     }
 
     pub fn router_input_before(&self) -> impl RouterInput + use<'_> {
@@ -959,8 +1748,12 @@ impl MapModel {
                 self.map.get_i(i)
             }
 
-            fn modal_filter(&self, r: RoadID) -> Option<&ModalFilter> {
-                self.map.original_modal_filters.get(&r)
+            fn modal_filters(&self, r: RoadID) -> &[ModalFilter] {
+                self.map
+                    .original_modal_filters
+                    .get(&r)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
             }
 
             fn travel_flow(&self, r: RoadID) -> TravelFlow {
@@ -977,6 +1770,10 @@ impl MapModel {
             fn turn_restrictions(&self, i: IntersectionID) -> &Vec<(RoadID, RoadID)> {
                 &self.map.original_turn_restrictions[i.0]
             }
+
+            fn via_way_restrictions(&self) -> &Vec<ViaWayRestriction> {
+                &self.map.via_way_restrictions
+            }
         }
 
         RouterInputBefore { map: self }
@@ -1002,14 +1799,22 @@ impl MapModel {
                 self.map.get_i(i)
             }
 
-            fn modal_filter(&self, r: RoadID) -> Option<&ModalFilter> {
-                self.map.modal_filters.get(&r)
+            fn modal_filters(&self, r: RoadID) -> &[ModalFilter] {
+                self.map
+                    .modal_filters
+                    .get(&r)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
             }
 
             fn travel_flow(&self, r: RoadID) -> TravelFlow {
                 self.map.travel_flows[&r]
             }
 
+            fn speed_mph(&self, r: RoadID) -> f64 {
+                self.map.speed_limits[&r]
+            }
+
             fn diagonal_filter(&self, i: IntersectionID) -> Option<&DiagonalFilter> {
                 self.map.diagonal_filters.get(&i)
             }
@@ -1017,6 +1822,10 @@ impl MapModel {
             fn turn_restrictions(&self, i: IntersectionID) -> &Vec<(RoadID, RoadID)> {
                 &self.map.turn_restrictions[i.0]
             }
+
+            fn via_way_restrictions(&self) -> &Vec<ViaWayRestriction> {
+                &self.map.via_way_restrictions
+            }
         }
         RouterInputAfter { map: self }
     }
@@ -1059,7 +1868,7 @@ impl MapModel {
             .unwrap()
             .route_from_points(&self.router_input_before(), pt1, pt2)
         {
-            let (distance, time) = route.get_distance_and_time(self);
+            let (distance, time) = route.get_distance_and_time(&self.router_input_before());
             let mut f = self.mercator.to_wgs84_gj(&route.to_linestring(self));
             f.set_property("kind", "before");
             f.set_property("distance", distance);
@@ -1071,7 +1880,75 @@ impl MapModel {
             pt1,
             pt2,
         ) {
-            let (distance, time) = route.get_distance_and_time(self);
+            let (distance, time) = route.get_distance_and_time(&self.router_input_after());
+            let mut f = self.mercator.to_wgs84_gj(&route.to_linestring(self));
+            f.set_property("kind", "after");
+            f.set_property("distance", distance);
+            f.set_property("time", time);
+            features.push(f);
+        }
+        GeoJson::from(features)
+    }
+
+    /// Like `rebuild_router`, but for a different kind of vehicle
+    fn rebuild_profile_routers(&mut self, profile: VehicleProfile, main_road_penalty: f64) {
+        let stale = |router: Option<&Router>| {
+            router.is_none_or(|r| r.main_road_penalty != main_road_penalty)
+        };
+        if stale(self.profile_routers_before.get(&profile)) {
+            let router_input = ProfileRouterInput {
+                inner: &self.router_input_before(),
+                profile,
+            };
+            let router = Router::new(&router_input, main_road_penalty);
+            self.profile_routers_before.insert(profile, router);
+        }
+        if stale(self.profile_routers_after.get(&profile)) {
+            let router_input = ProfileRouterInput {
+                inner: &self.router_input_after(),
+                profile,
+            };
+            let router = Router::new(&router_input, main_road_penalty);
+            self.profile_routers_after.insert(profile, router);
+        }
+    }
+
+    /// Like `compare_route`, but for a different kind of vehicle
+    pub fn compare_route_for_profile(
+        &mut self,
+        pt1: Coord,
+        pt2: Coord,
+        main_road_penalty: f64,
+        profile: VehicleProfile,
+    ) -> GeoJson {
+        if profile == VehicleProfile::ThroughTraffic {
+            return self.compare_route(pt1, pt2, main_road_penalty);
+        }
+        self.rebuild_profile_routers(profile, main_road_penalty);
+
+        let mut features = Vec::new();
+        let router_input_before = ProfileRouterInput {
+            inner: &self.router_input_before(),
+            profile,
+        };
+        if let Some(route) =
+            self.profile_routers_before[&profile].route_from_points(&router_input_before, pt1, pt2)
+        {
+            let (distance, time) = route.get_distance_and_time(&router_input_before);
+            let mut f = self.mercator.to_wgs84_gj(&route.to_linestring(self));
+            f.set_property("kind", "before");
+            f.set_property("distance", distance);
+            f.set_property("time", time);
+            features.push(f);
+        }
+        let router_input_after = ProfileRouterInput {
+            inner: &self.router_input_after(),
+            profile,
+        };
+        if let Some(route) =
+            self.profile_routers_after[&profile].route_from_points(&router_input_after, pt1, pt2)
+        {
+            let (distance, time) = route.get_distance_and_time(&router_input_after);
             let mut f = self.mercator.to_wgs84_gj(&route.to_linestring(self));
             f.set_property("kind", "after");
             f.set_property("distance", distance);
@@ -1116,8 +1993,9 @@ impl MapModel {
                 ),
             ) {
                 let from_pt = self.mercator.pt_to_wgs84(pt1);
-                let (distance_before, time_before) = before.get_distance_and_time(self);
-                let (distance_after, time_after) = after.get_distance_and_time(self);
+                let (distance_before, time_before) =
+                    before.get_distance_and_time(&router_input_before);
+                let (distance_after, time_after) = after.get_distance_and_time(&router_input_after);
 
                 let mut f = self.mercator.to_wgs84_gj(&road.linestring);
                 f.set_property("distance_before", distance_before);
@@ -1178,10 +2056,11 @@ impl MapModel {
 }
 
 impl Road {
-    // How long does it take for a car following the speed limit to cross this road?
-    pub fn cost_seconds(&self) -> f64 {
+    // How long does it take for a car following the speed limit to cross this road? The speed
+    // limit may be edited, so it's passed in.
+    pub fn cost_seconds(&self, speed_mph: f64) -> f64 {
         let meters = Euclidean.length(&self.linestring);
-        let meters_per_second = self.speed_mph * 0.44704;
+        let meters_per_second = speed_mph * 0.44704;
         meters / meters_per_second
     }
 
@@ -1204,11 +2083,16 @@ pub struct ModalFilter {
     pub percent_along: f64,
 }
 
-/// A DiagonalFilter is placed at a 4-way intersection, and prevents traffic from going "straight"
-/// through the intersection. Traffic must turn.
+/// A DiagonalFilter is placed at an intersection with 3 or more roads. It splits the roads into two
+/// groups, and prevents traffic from going between the groups. At a 4-way intersection, traffic
+/// can't go "straight" through and must turn.
 ///
-/// The DiagonalFilter can be placed in one of two rotations to determine which way traffic is forced
-/// to turn.
+/// By default, the roads are split into two halves, and the DiagonalFilter can be placed in one of
+/// two rotations to determine which way traffic is forced to turn. Users can also choose the
+/// groups, such as to close off one arm of a 5-way junction.
+///
+/// At a T-junction, the two rotations separate the first or second road clockwise from North.
+/// Separating the third road needs the groups chosen with `from_group`.
 ///
 /// Note: When all the roads at the intersection are 1-way roads, there is only one reasonable
 /// orientation for the diagonal filter, the other orientation would effectively block the intersection.
@@ -1221,32 +2105,37 @@ pub struct DiagonalFilter {
     /// Travel within these roads are allowed, but not to the other group.
     pub group_b: Vec<RoadID>,
     /// The topological orientation of the filter - it determines how `intersection.roads` are split
-    /// into `group_a` and `group_b`.
+    /// into `group_a` and `group_b`. Meaningless when the groups were chosen by the user.
     pub is_rotated: bool,
     /// How many degrees to rotate a vertical line to split `group_a` from `group_b`
     pub angle: f32,
 }
 
 impl DiagonalFilter {
-    /// Precondition: Intersection must be a 4-way intersection
-    fn new(intersection: &Intersection, is_rotated: bool, map_model: &MapModel) -> DiagonalFilter {
-        debug_assert_eq!(
-            intersection.roads.len(),
-            4,
-            "diagonal filters only support 4-way intersections"
+    /// Precondition: Intersection must have at least 3 roads
+    pub(crate) fn new(
+        intersection: &Intersection,
+        is_rotated: bool,
+        map_model: &MapModel,
+    ) -> DiagonalFilter {
+        debug_assert!(
+            intersection.roads.len() >= 3,
+            "diagonal filters need at least 3 roads"
         );
 
+        let num_roads = intersection.roads.len();
         let split_offset = if is_rotated { 1 } else { 0 };
+        let half = num_roads / 2;
 
-        let group_a: Vec<RoadID> = (0..2)
-            .map(|offset| intersection.roads[(offset + split_offset) % intersection.roads.len()])
+        let group_a: Vec<RoadID> = (0..half)
+            .map(|offset| intersection.roads[(offset + split_offset) % num_roads])
             .collect();
 
-        let group_b: Vec<RoadID> = (2..4)
-            .map(|offset| intersection.roads[(offset + split_offset) % intersection.roads.len()])
+        let group_b: Vec<RoadID> = (half..num_roads)
+            .map(|offset| intersection.roads[(offset + split_offset) % num_roads])
             .collect();
 
-        let angle = {
+        let angle = if num_roads == 4 {
             let road_a = if is_rotated {
                 map_model.get_r(group_a[0])
             } else {
@@ -1255,21 +2144,21 @@ impl DiagonalFilter {
             let road_b0 = map_model.get_r(group_b[0]);
             let road_b1 = map_model.get_r(group_b[1]);
 
-            let bearing_a0 = bearing_from_endpoint(intersection.point, &road_a.linestring);
-            let bearing_b0 = bearing_from_endpoint(intersection.point, &road_b0.linestring);
-            let bearing_b1 = bearing_from_endpoint(intersection.point, &road_b1.linestring);
+            let bearing_a0 = intersection.bearing_of_road(road_a);
+            let bearing_b0 = intersection.bearing_of_road(road_b0);
+            let bearing_b1 = intersection.bearing_of_road(road_b1);
 
             let angle_b0 = angle_between_bearings(bearing_a0, bearing_b0);
             let angle_b1 = angle_between_bearings(bearing_a0, bearing_b1);
 
             // Split the acute angle, since the obtuse angle has more tolerance
-            let split = if angle_b0 < angle_b1 {
+            if angle_b0 < angle_b1 {
                 split_bearing(bearing_a0, bearing_b0) as f32
             } else {
                 split_bearing(bearing_a0, bearing_b1) as f32
-            };
-
-            split
+            }
+        } else {
+            split_groups_angle(intersection, &group_a, map_model)
         };
         DiagonalFilter {
             group_a,
@@ -1279,6 +2168,30 @@ impl DiagonalFilter {
         }
     }
 
+    /// Creates a filter splitting `group_a` from all other roads at the intersection
+    pub(crate) fn from_group(
+        intersection: &Intersection,
+        group_a: Vec<RoadID>,
+        map_model: &MapModel,
+    ) -> Result<DiagonalFilter> {
+        if let Some(r) = group_a.iter().find(|r| !intersection.roads.contains(r)) {
+            bail!("{r} isn't connected to {}", intersection.id);
+        }
+        // Keep the roads in the intersection's order
+        let (group_a, group_b): (Vec<RoadID>, Vec<RoadID>) =
+            intersection.roads.iter().partition(|r| group_a.contains(r));
+        if group_a.is_empty() || group_b.is_empty() {
+            bail!("Both groups of a diagonal filter need at least one road");
+        }
+        let angle = split_groups_angle(intersection, &group_a, map_model);
+        Ok(DiagonalFilter {
+            group_a,
+            group_b,
+            is_rotated: false,
+            angle,
+        })
+    }
+
     // `movement`: (from, to)
     pub fn allows_movement(&self, movement: &(RoadID, RoadID)) -> bool {
         let (from, to) = movement;
@@ -1291,6 +2204,61 @@ impl DiagonalFilter {
     }
 }
 
+/// Finds the narrowest gap between neighbouring roads in different groups, and splits it
+fn split_groups_angle(
+    intersection: &Intersection,
+    group_a: &[RoadID],
+    map_model: &MapModel,
+) -> f32 {
+    let bearings: Vec<(f64, bool)> = intersection
+        .roads
+        .iter()
+        .map(|r| {
+            (
+                intersection.bearing_of_road(map_model.get_r(*r)),
+                group_a.contains(r),
+            )
+        })
+        .collect();
+    let mut best: Option<(f64, f64)> = None;
+    for idx in 0..bearings.len() {
+        let (bearing1, in_a1) = bearings[idx];
+        let (bearing2, in_a2) = bearings[(idx + 1) % bearings.len()];
+        if in_a1 == in_a2 {
+            continue;
+        }
+        let angle = angle_between_bearings(bearing1, bearing2);
+        if best
+            .map(|(best_angle, _)| angle < best_angle)
+            .unwrap_or(true)
+        {
+            best = Some((angle, split_bearing(bearing1, bearing2)));
+        }
+    }
+    best.map(|(_, split)| split as f32).unwrap_or(0.0)
+}
+
+/// Speed limits from the user or a savefile become routing costs, so they must be finite and
+/// positive
+pub(crate) fn check_speed_limit(speed_mph: f64) -> Result<()> {
+    if !(speed_mph.is_finite() && speed_mph > 0.0) {
+        bail!("Speed limit must be positive, not {speed_mph}");
+    }
+    Ok(())
+}
+
+/// A banned sequence of movements through multiple roads, like a U-turn through the short road
+/// between two carriageways
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ViaWayRestriction {
+    /// Where the banned sequence starts, travelling towards the first `via` road
+    pub from: (RoadID, Direction),
+    /// The roads crossed, in order
+    pub via: Vec<(RoadID, Direction)>,
+    /// Vehicles can't continue onto this road after the last `via` road
+    pub to: RoadID,
+}
+
 impl From<&DiagonalFilter> for JsonValue {
     fn from(value: &DiagonalFilter) -> Self {
         serde_json::to_value(value).expect("valid JSON fields")
@@ -1304,6 +2272,14 @@ pub enum FilterKind {
     BusGate,
     SchoolStreet,
     DiagonalFilter,
+    /// Enforced by a camera, with residents' vehicles registered as exempt
+    CameraResidentExemption,
+    /// No motor vehicles except for access
+    ExceptAccess,
+    /// Closed to all traffic during some hours. Routing treats the closure as active.
+    TimedClosure,
+    /// A bollard that lowers for buses and emergency vehicles
+    RisingBollard,
 }
 
 // TODO strum?
@@ -1315,6 +2291,10 @@ impl FilterKind {
             Self::BusGate => "bus_gate",
             Self::SchoolStreet => "school_street",
             Self::DiagonalFilter => "diagonal_filter",
+            Self::CameraResidentExemption => "camera_resident_exemption",
+            Self::ExceptAccess => "except_access",
+            Self::TimedClosure => "timed_closure",
+            Self::RisingBollard => "rising_bollard",
         }
     }
 
@@ -1325,9 +2305,40 @@ impl FilterKind {
             "bus_gate" => Ok(Self::BusGate),
             "school_street" => Ok(Self::SchoolStreet),
             "diagonal_filter" => Ok(Self::DiagonalFilter),
+            "camera_resident_exemption" => Ok(Self::CameraResidentExemption),
+            "except_access" => Ok(Self::ExceptAccess),
+            "timed_closure" => Ok(Self::TimedClosure),
+            "rising_bollard" => Ok(Self::RisingBollard),
             _ => bail!("Invalid FilterKind: {x}"),
         }
     }
+
+    /// Does this filter stop vehicles of this profile?
+    pub fn blocks(self, profile: VehicleProfile) -> bool {
+        let exempt: &[VehicleProfile] = match self {
+            Self::WalkCycleOnly | Self::NoEntry | Self::SchoolStreet | Self::DiagonalFilter => &[],
+            Self::BusGate | Self::RisingBollard => {
+                &[VehicleProfile::Bus, VehicleProfile::Emergency]
+            }
+            Self::CameraResidentExemption => &[
+                VehicleProfile::Resident,
+                VehicleProfile::Bus,
+                VehicleProfile::Emergency,
+            ],
+            Self::ExceptAccess => &[VehicleProfile::Resident, VehicleProfile::Emergency],
+            Self::TimedClosure => &[VehicleProfile::Emergency],
+        };
+        !exempt.contains(&profile)
+    }
+
+    /// The vehicle profiles that can pass through the filter
+    pub fn exempt_profiles(self) -> Vec<&'static str> {
+        VehicleProfile::ALL
+            .into_iter()
+            .filter(|profile| !self.blocks(*profile))
+            .map(|profile| profile.to_string())
+            .collect()
+    }
 }
 
 #[derive(Clone, Debug, Copy, PartialEq, PartialOrd, Ord, Eq, Serialize, Deserialize)]
@@ -1345,6 +2356,14 @@ impl TravelFlow {
     pub fn flows_backwards(self) -> bool {
         matches!(self, TravelFlow::BACKWARDS | TravelFlow::BothWays)
     }
+    /// The same flow, relative to a line pointing the opposite way
+    pub fn reversed(self) -> Self {
+        match self {
+            TravelFlow::FORWARDS => TravelFlow::BACKWARDS,
+            TravelFlow::BACKWARDS => TravelFlow::FORWARDS,
+            TravelFlow::BothWays => TravelFlow::BothWays,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Copy, PartialEq, PartialOrd, Ord, Eq, Serialize, Deserialize)]
@@ -1398,16 +2417,70 @@ impl TravelFlow {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Command {
-    SetModalFilter(RoadID, Option<ModalFilter>),
+    /// All of the filters on a road, in any order
+    SetModalFilters(RoadID, Vec<ModalFilter>),
     SetDiagonalFilter(IntersectionID, Option<DiagonalFilter>),
     SetTravelFlow(RoadID, TravelFlow),
     SetMainRoad(RoadID, bool),
+    /// In mph
+    SetSpeedLimit(RoadID, f64),
     SetTurnRestrictions(IntersectionID, Vec<(RoadID, RoadID)>),
+    SetAnnotation(Intervention, Option<Annotation>),
     Multiple(Vec<Command>),
 }
 
+impl Command {
+    /// Does this change any main roads, possibly nested in a `Multiple`?
+    pub fn touches_main_roads(&self) -> bool {
+        match self {
+            Command::SetMainRoad(_, _) => true,
+            Command::Multiple(list) => list.iter().any(|cmd| cmd.touches_main_roads()),
+            _ => false,
+        }
+    }
+}
+
+/// One edit from a savefile, matched to the current map
+pub enum SavefileEdit {
+    Command(Command),
+    // Turn restrictions are edited as a whole list per intersection, so adding or deleting one
+    // isn't a Command until it's combined with the current list
+    AddTurnRestriction(IntersectionID, RoadID, RoadID),
+    DeleteTurnRestriction(IntersectionID, RoadID, RoadID),
+    // A road with multiple filters is saved as one feature per filter. The first one found on a
+    // road replaces its existing filters, and the rest are added.
+    AddModalFilter(RoadID, ModalFilter),
+}
+
+/// One feature from a savefile, checked before anything is loaded
+enum SavefileFeature {
+    /// An edit for the named scenario, or the current one
+    Edit(Option<String>, Match<SavefileEdit>),
+    Boundary(NeighbourhoodBoundary),
+    StudyAreaBoundary,
+}
+
+/// The undo and redo stacks for one neighbourhood.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EditHistory {
+    pub undo_stack: Vec<HistoryEntry>,
+    pub redo_stack: Vec<HistoryEntry>,
+}
+
+/// One edit that can be undone or redone.
+///
+/// Commands record the previous state of roads and intersections, so undoing an old edit in one
+/// neighbourhood could revert a later edit made from another neighbourhood to a shared main road.
+/// `expected` detects that.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub cmd: Command,
+    /// Everything `cmd` changes, set to the values they had when this entry was recorded
+    pub expected: Command,
+}
+
 fn get_str_prop<'a>(f: &'a Feature, key: &str) -> Result<&'a str> {
     let Some(value) = f.property(key) else {
         bail!("Feature doesn't have a {key} property");
@@ -1438,9 +2511,258 @@ fn get_bool_prop<'a>(f: &'a Feature, key: &str) -> Result<bool> {
     Ok(x)
 }
 
+/// One saved edit can match multiple roads when the road was split
+fn per_road_command(
+    roads: Vec<(RoadID, bool)>,
+    cmd: impl Fn(RoadID, bool) -> Command,
+) -> SavefileEdit {
+    let mut cmds: Vec<Command> = roads
+        .into_iter()
+        .map(|(r, same_direction)| cmd(r, same_direction))
+        .collect();
+    SavefileEdit::Command(if cmds.len() == 1 {
+        cmds.pop().unwrap()
+    } else {
+        Command::Multiple(cmds)
+    })
+}
+
 /// A position along a road
 #[derive(Clone, Debug, Copy, PartialEq)]
 pub struct Position {
     pub road: RoadID,
     pub percent_along: f64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_tests::{get_road_by_name, load_osm_xml};
+
+    #[test]
+    fn undo_refuses_to_revert_another_history() {
+        let mut map = load_osm_xml("simple_four_way_intersection");
+        let r = RoadID(3);
+        let original_speed = map.speed_limits[&r];
+
+        map.switch_history("west");
+        map.set_speed_limit(r, 10.0).unwrap();
+        map.switch_history("east");
+        map.set_speed_limit(r, 20.0).unwrap();
+
+        // Undoing west's edit would silently revert east's
+        map.switch_history("west");
+        assert!(map.undo().is_err());
+        assert_eq!(map.speed_limits[&r], 20.0);
+        assert_eq!(map.undo_stack.len(), 1);
+
+        // Once east's edit is undone, west's can be too
+        map.switch_history("east");
+        map.undo().unwrap();
+        assert_eq!(map.speed_limits[&r], 10.0);
+        map.switch_history("west");
+        map.undo().unwrap();
+        assert_eq!(map.speed_limits[&r], original_speed);
+
+        // Redo checks the same way
+        map.switch_history("east");
+        assert!(map.redo().is_err());
+        map.switch_history("west");
+        map.redo().unwrap();
+        map.switch_history("east");
+        map.redo().unwrap();
+        assert_eq!(map.speed_limits[&r], 20.0);
+    }
+
+    #[test]
+    fn saved_histories_are_validated() {
+        let mut map = load_osm_xml("simple_four_way_intersection");
+        map.switch_history("west");
+        map.set_speed_limit(RoadID(3), 10.0).unwrap();
+        let bad_cmd = Command::SetSpeedLimit(RoadID(9999), 10.0);
+        map.other_histories.insert(
+            "bad".to_string(),
+            EditHistory {
+                undo_stack: vec![HistoryEntry {
+                    cmd: bad_cmd.clone(),
+                    expected: bad_cmd,
+                }],
+                redo_stack: Vec::new(),
+            },
+        );
+
+        // Without a timestamp, histories aren't saved at all
+        map.osm_timestamp = None;
+        let savefile = map.to_savefile();
+        assert!(!savefile
+            .foreign_members
+            .as_ref()
+            .unwrap()
+            .contains_key("edit_histories"));
+
+        // A matching timestamp keeps histories, except ones that don't fit the map
+        map.osm_timestamp = Some(123);
+        let savefile = map.to_savefile();
+        map.load_savefile(savefile.clone(), false).unwrap();
+        assert_eq!(map.other_histories.keys().collect::<Vec<_>>(), vec!["west"]);
+        map.switch_history("west");
+        map.undo().unwrap();
+        assert_eq!(map.speed_limits[&RoadID(3)], map.get_r(RoadID(3)).speed_mph);
+
+        // A different or missing timestamp drops all of them
+        map.osm_timestamp = Some(456);
+        map.load_savefile(savefile.clone(), false).unwrap();
+        assert!(map.other_histories.is_empty());
+
+        let mut savefile = savefile;
+        savefile
+            .foreign_members
+            .as_mut()
+            .unwrap()
+            .remove("edit_histories_osm_timestamp");
+        map.osm_timestamp = Some(123);
+        map.load_savefile(savefile, false).unwrap();
+        assert!(map.other_histories.is_empty());
+    }
+
+    fn intersection_with(map: &MapModel, num_roads: usize) -> &Intersection {
+        map.intersections
+            .iter()
+            .find(|i| i.roads.len() == num_roads)
+            .unwrap()
+    }
+
+    fn names(map: &MapModel, roads: &[RoadID]) -> Vec<String> {
+        roads
+            .iter()
+            .map(|r| map.get_r(*r).tags.get("name").unwrap().clone())
+            .collect()
+    }
+
+    #[test]
+    fn diagonal_filter_at_t_junction() {
+        let map = load_osm_xml("diagonal_filters");
+        let intersection = intersection_with(&map, 3);
+        assert_eq!(
+            names(&map, &intersection.roads),
+            vec!["t east", "t south", "t west"]
+        );
+
+        // The two rotations separate the first or second road
+        let filter = DiagonalFilter::new(intersection, false, &map);
+        assert_eq!(names(&map, &filter.group_a), vec!["t east"]);
+        assert_eq!(names(&map, &filter.group_b), vec!["t south", "t west"]);
+        let filter = DiagonalFilter::new(intersection, true, &map);
+        assert_eq!(names(&map, &filter.group_a), vec!["t south"]);
+        assert_eq!(names(&map, &filter.group_b), vec!["t west", "t east"]);
+
+        // Only choosing the groups separates the third. The split is in the narrower gap, between
+        // south and west.
+        let west = get_road_by_name(&map, "t west");
+        let filter = DiagonalFilter::from_group(intersection, vec![west], &map).unwrap();
+        assert_eq!(filter.group_a, vec![west]);
+        assert_eq!(names(&map, &filter.group_b), vec!["t east", "t south"]);
+        approx::assert_abs_diff_eq!(filter.angle, 225.0, epsilon = 1.0);
+    }
+
+    #[test]
+    fn diagonal_filter_at_five_way_junction() {
+        let map = load_osm_xml("diagonal_filters");
+        let intersection = intersection_with(&map, 5);
+        assert_eq!(
+            names(&map, &intersection.roads),
+            vec![
+                "north",
+                "north east",
+                "south east",
+                "south west",
+                "north west"
+            ]
+        );
+
+        // Half of the roads, rounded down, go in group_a. The split is in the narrowest gap
+        // between the groups.
+        let filter = DiagonalFilter::new(intersection, false, &map);
+        assert_eq!(names(&map, &filter.group_a), vec!["north", "north east"]);
+        approx::assert_abs_diff_eq!(filter.angle, 325.0, epsilon = 1.0);
+        let filter = DiagonalFilter::new(intersection, true, &map);
+        assert_eq!(
+            names(&map, &filter.group_a),
+            vec!["north east", "south east"]
+        );
+        approx::assert_abs_diff_eq!(filter.angle, 180.0, epsilon = 1.0);
+
+        // Close off one arm. Groups keep the intersection's order.
+        let (north_west, south_west) = (
+            get_road_by_name(&map, "north west"),
+            get_road_by_name(&map, "south west"),
+        );
+        let filter =
+            DiagonalFilter::from_group(intersection, vec![north_west, south_west], &map).unwrap();
+        assert_eq!(filter.group_a, vec![south_west, north_west]);
+        assert_eq!(
+            names(&map, &filter.group_b),
+            vec!["north", "north east", "south east"]
+        );
+        approx::assert_abs_diff_eq!(filter.angle, 180.0, epsilon = 1.0);
+
+        // Both groups need a road, and every road must be at the intersection
+        assert!(DiagonalFilter::from_group(intersection, Vec::new(), &map).is_err());
+        assert!(
+            DiagonalFilter::from_group(intersection, intersection.roads.clone(), &map).is_err()
+        );
+        let elsewhere = get_road_by_name(&map, "t east");
+        assert!(DiagonalFilter::from_group(intersection, vec![elsewhere], &map).is_err());
+    }
+
+    #[test]
+    fn set_diagonal_filter_groups() {
+        let mut map = load_osm_xml("diagonal_filters");
+        let i = intersection_with(&map, 5).id;
+        let south_west = get_road_by_name(&map, "south west");
+
+        map.add_diagonal_filter(i);
+        map.set_diagonal_filter_groups(i, vec![south_west]).unwrap();
+        assert_eq!(map.diagonal_filters[&i].group_a, vec![south_west]);
+        // Vehicles can't turn into the closed arm
+        let north = get_road_by_name(&map, "north");
+        assert!(!map.diagonal_filters[&i].allows_movement(&(north, south_west)));
+
+        // A bad group doesn't change anything
+        let elsewhere = get_road_by_name(&map, "t east");
+        assert!(map.set_diagonal_filter_groups(i, vec![elsewhere]).is_err());
+        assert_eq!(map.undo_stack.len(), 2);
+
+        // Undo returns to the default groups
+        map.undo().unwrap();
+        assert_eq!(
+            map.diagonal_filters[&i],
+            DiagonalFilter::new(map.get_i(i), false, &map)
+        );
+    }
+
+    #[test]
+    fn invalid_speed_limits_are_rejected() {
+        let mut map = load_osm_xml("simple_four_way_intersection");
+        for speed_mph in [0.0, -10.0, f64::NAN, f64::INFINITY] {
+            assert!(map.set_speed_limit(RoadID(0), speed_mph).is_err());
+            assert!(map.set_speed_limits(Vec::new(), speed_mph).is_err());
+        }
+        assert!(map.set_speed_limit(RoadID(9999), 20.0).is_err());
+        assert!(map.undo_stack.is_empty());
+    }
+
+    #[test]
+    fn touches_main_roads_checks_nested_commands() {
+        let filter = Command::SetModalFilters(RoadID(0), Vec::new());
+        let main_road = Command::SetMainRoad(RoadID(1), true);
+        assert!(!filter.touches_main_roads());
+        assert!(main_road.touches_main_roads());
+        assert!(Command::Multiple(vec![
+            filter.clone(),
+            Command::Multiple(vec![filter.clone(), main_road])
+        ])
+        .touches_main_roads());
+        assert!(!Command::Multiple(vec![filter]).touches_main_roads());
+    }
+}
@@ -1,11 +1,12 @@
-use anyhow::Result;
 use geo::line_measures::InterpolatableLine;
 use geo::{Euclidean, Line, Point, Polygon};
 use geojson::GeoJson;
 use itertools::Itertools;
 
 use crate::{
+    annotations::Intervention,
     geo_helpers::{euclidean_bearing, make_arrow, thicken_line},
+    savefile_matching::Match,
     Intersection, IntersectionID, MapModel, Road, RoadID,
 };
 
@@ -84,6 +85,13 @@ impl MapModel {
                     f.set_property("intersection", i.id.0);
                     f.set_property("from_road", from.id.0);
                     f.set_property("to_road", to.id.0);
+                    if let Some(annotation) = self.get_annotation(Intervention::TurnRestriction {
+                        intersection: i.id,
+                        from_road: from.id,
+                        to_road: to.id,
+                    }) {
+                        f.set_property("annotation", serde_json::to_value(annotation).unwrap());
+                    }
                     features.push(f);
                 }
             }
@@ -124,49 +132,62 @@ impl MapModel {
         GeoJson::from(features)
     }
 
-    /// Find (intersection, from, to) to represent a turn restriction
+    /// Find (intersection, from, to) to represent a saved turn restriction
     pub fn find_turn_restriction(
         &self,
         pt: Point,
         bearing1: f64,
         bearing2: f64,
-    ) -> Result<(IntersectionID, RoadID, RoadID)> {
-        let intersection = match self.closest_intersection.nearest_neighbor(&pt) {
-            Some(obj) => self.get_i(obj.data),
-            None => bail!("No intersection near point"),
-        };
-        // For every road attached to this intersection, calculate its absolute bearing as a (from,
-        // to) road
-        let bearings: Vec<(RoadID, f64, f64)> = intersection
-            .roads
-            .iter()
-            .map(|r| {
-                let road = self.get_r(*r);
-                let road_pt = self.get_r(*r).pt_near_intersection(intersection.id);
-                (
-                    road.id,
-                    euclidean_bearing(road_pt.into(), intersection.point.into()),
-                    euclidean_bearing(intersection.point.into(), road_pt.into()),
-                )
-            })
-            .collect();
-
-        // Find the best match for each bearing
-        let (from, _, _) = bearings
-            .iter()
-            .min_by_key(|(_, b1, _)| smallest_rotation(bearing1, *b1) as usize)
-            .unwrap();
-        let (to, _, _) = bearings
-            .iter()
-            .min_by_key(|(_, _, b2)| smallest_rotation(bearing2, *b2) as usize)
-            .unwrap();
-        if from == to {
-            bail!("Bearings {bearing1} and {bearing2} both matched to the same road");
-        }
-        Ok((intersection.id, *from, *to))
+    ) -> Match<(IntersectionID, RoadID, RoadID)> {
+        self.match_intersection(pt.into()).and_then(|i| {
+            let intersection = self.get_i(i);
+            // For every road attached to this intersection, calculate its absolute bearing as a
+            // (from, to) road
+            let bearings: Vec<(RoadID, f64, f64)> = intersection
+                .roads
+                .iter()
+                .map(|r| {
+                    let road = self.get_r(*r);
+                    let road_pt = self.get_r(*r).pt_near_intersection(intersection.id);
+                    (
+                        road.id,
+                        euclidean_bearing(road_pt.into(), intersection.point.into()),
+                        euclidean_bearing(intersection.point.into(), road_pt.into()),
+                    )
+                })
+                .collect();
+
+            // Find the best match for each bearing
+            let (from, rotation1) = bearings
+                .iter()
+                .map(|(r, b1, _)| (*r, smallest_rotation(bearing1, *b1)))
+                .min_by_key(|(_, rotation)| *rotation as usize)
+                .unwrap();
+            let (to, rotation2) = bearings
+                .iter()
+                .map(|(r, _, b2)| (*r, smallest_rotation(bearing2, *b2)))
+                .min_by_key(|(_, rotation)| *rotation as usize)
+                .unwrap();
+            if from == to {
+                return Match::NotFound(format!(
+                    "Bearings {bearing1} and {bearing2} both matched to the same road"
+                ));
+            }
+            let rotation = rotation1.max(rotation2);
+            if rotation > MAX_BEARING_ROTATION {
+                return Match::LowConfidence(
+                    (i, from, to),
+                    format!("The closest roads are {rotation:.0} degrees from the saved bearings"),
+                );
+            }
+            Match::Good((i, from, to))
+        })
     }
 }
 
+/// Matching roads with bearings further apart than this are probably different roads
+const MAX_BEARING_ROTATION: f64 = 30.0;
+
 fn render_arrow(i: IntersectionID, offset1: usize, road1: &Road, road2: &Road) -> Polygon {
     let line = Line::new(
         road1.stacked_icon_position(offset1, i),
@@ -192,6 +213,23 @@ impl Intersection {
             ),
         )
     }
+
+    /// Returns the absolute bearing of the road pointing away from the intersection
+    pub fn bearing_of_road(&self, road: &Road) -> f64 {
+        euclidean_bearing(self.point.into(), road.pt_near_intersection(self.id).into())
+    }
+
+    /// Finds the road pointing away from the intersection closest to this absolute bearing
+    pub fn road_nearest_bearing(&self, map: &MapModel, bearing: f64) -> RoadID {
+        *self
+            .roads
+            .iter()
+            .min_by_key(|r| {
+                let rotation = smallest_rotation(self.bearing_of_road(map.get_r(**r)), bearing);
+                (rotation * 1000.0) as usize
+            })
+            .expect("intersection has no roads")
+    }
 }
 
 impl Road {
@@ -1,4 +1,4 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use crate::geo_helpers::{
     buffer_polygon, euclidean_bearing, invert_feature_geometry_in_place, make_polygon_valid,
@@ -11,13 +11,16 @@ use geo::{
 use geojson::{Feature, FeatureCollection};
 use rstar::{primitives::GeomWithData, RTree};
 use serde::{Deserialize, Serialize};
-use utils::{aabb, buffer_aabb, Mercator};
+use utils::{aabb, buffer_aabb, Mercator, Tags};
 use web_time::Instant;
 
+use crate::access::AccessPenalties;
+use crate::annotations::Intervention;
 use crate::boundary_stats::{BoundaryStats, PreparedContextData};
-use crate::map_model::DiagonalFilter;
+use crate::map_model::{DiagonalFilter, ViaWayRestriction};
 use crate::render_cells::Color;
 use crate::route::RouterInput;
+use crate::shortcuts::through_traffic_pairs;
 use crate::{
     Cell, Intersection, IntersectionID, MapModel, ModalFilter, RenderCells, Road, RoadID,
     Shortcuts, TravelFlow,
@@ -49,7 +52,7 @@ pub struct WayPoint {
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-pub struct WayPoints(Vec<WayPoint>);
+pub struct WayPoints(pub Vec<WayPoint>);
 impl MapCoordsInPlace<f64> for WayPoints {
     fn map_coords_in_place(&mut self, func: impl Fn(Coord<f64>) -> Coord<f64> + Copy)
     where
@@ -124,7 +127,13 @@ pub struct Neighbourhood {
     pub main_roads: BTreeSet<RoadID>,
     pub editable_intersections: BTreeSet<IntersectionID>,
     pub border_intersections: BTreeSet<IntersectionID>,
+    /// Bridges and tunnels inside the boundary that don't connect to the neighbourhood's streets,
+    /// with the reason they're considered grade-separated
+    pub excluded_roads: BTreeMap<RoadID, String>,
     pub boundary: NeighbourhoodBoundary,
+    /// Only calculated in demand-weighted mode. Edits don't change routes before edits, so this
+    /// is cached until the neighbourhood is rebuilt.
+    pub through_traffic_pairs: Option<HashMap<(RoadID, RoadID), usize>>,
     // Updated after mutations
     pub derived: Option<DerivedNeighbourhoodState>,
 }
@@ -132,6 +141,7 @@ pub struct Neighbourhood {
 pub struct DerivedNeighbourhoodState {
     render_cells: RenderCells,
     pub shortcuts: Shortcuts,
+    pub access: AccessPenalties,
 }
 
 impl NeighbourhoodDefinition {
@@ -203,16 +213,29 @@ impl Neighbourhood {
         // we'll inadvertenly include roads beyond the boundary.
         let bbox = buffer_aabb(aabb(boundary.geometry()), 1.0);
         let buffered_boundary = buffer_polygon(boundary.geometry(), 1.0)?;
+        let mut inside = BTreeSet::new();
         for obj in map.closest_road.locate_in_envelope_intersecting(&bbox) {
             let road = &map.roads[obj.data.0];
-            if !is_road_mostly_inside(&road.linestring, &buffered_boundary) {
-                continue;
+            if is_road_mostly_inside(&road.linestring, &buffered_boundary) {
+                inside.insert(road.id);
             }
+        }
 
-            if map.is_main_road[&road.id] {
-                main_roads.insert(road.id);
+        let excluded_roads = find_grade_separated_roads(map, &inside);
+        if !excluded_roads.is_empty() {
+            info!(
+                "Excluding {} bridges or tunnels from the neighbourhood",
+                excluded_roads.len()
+            );
+        }
+        for r in inside {
+            if excluded_roads.contains_key(&r) {
+                continue;
+            }
+            if map.is_main_road[&r] {
+                main_roads.insert(r);
             } else {
-                interior_roads.insert(road.id);
+                interior_roads.insert(r);
             }
         }
 
@@ -235,8 +258,8 @@ impl Neighbourhood {
                 }
             }
 
-            if interior_connections == 4 && main_road_connections == 0 {
-                // only 4-way interior intersections are eligible for diagonal intersections
+            if interior_connections >= 3 && main_road_connections == 0 {
+                // interior junctions of 3 or more roads are eligible for diagonal filters
                 editable_intersections.insert(intersection.id);
             } else if interior_connections > 0 && main_road_connections > 0 {
                 // border intersections represent an "input" of traffic into the neighbourhood.
@@ -261,6 +284,8 @@ impl Neighbourhood {
             boundary,
             editable_intersections,
             border_intersections,
+            excluded_roads,
+            through_traffic_pairs: None,
             derived: None,
         };
         n.after_edit(map);
@@ -268,6 +293,14 @@ impl Neighbourhood {
     }
 
     pub fn after_edit(&mut self, map: &MapModel) {
+        if map.demand_weighted_shortcuts && map.demand.is_some() {
+            if self.through_traffic_pairs.is_none() {
+                self.through_traffic_pairs = Some(through_traffic_pairs(map, self));
+            }
+        } else {
+            self.through_traffic_pairs = None;
+        }
+
         let t1 = Instant::now();
         let cells = Cell::find_all(map, self);
         let t2 = Instant::now();
@@ -275,12 +308,15 @@ impl Neighbourhood {
         let t3 = Instant::now();
         let shortcuts = Shortcuts::new(map, self, &cells);
         let t4 = Instant::now();
+        let access = AccessPenalties::new(map, self);
+        let t5 = Instant::now();
         self.derived = Some(DerivedNeighbourhoodState {
             render_cells,
             shortcuts,
+            access,
         });
         if true {
-            info!("Neighbourhood edited, total {:?}. Finding cells took {:?}, rendering cells took {:?}, finding shortcuts took {:?}", t4 - t1, t2 - t1, t3 - t2, t4 - t3);
+            info!("Neighbourhood edited, total {:?}. Finding cells took {:?}, rendering cells took {:?}, finding shortcuts took {:?}, finding access penalties took {:?}", t5 - t1, t2 - t1, t3 - t2, t4 - t3, t5 - t4);
         }
     }
 
@@ -336,6 +372,8 @@ impl Neighbourhood {
             let mut f = road.to_gj(&map.mercator);
             f.set_property("kind", "main_road");
             f.set_property("travel_flow", map.travel_flows[&r].to_string());
+            f.set_property("speed_mph", map.speed_limits[&r]);
+            f.set_property("speed_limit_edited", map.speed_limits[&r] != road.speed_mph);
             f.set_property(
                 "travel_flow_edited",
                 map.travel_flows[&r] != TravelFlow::from_osm(&road.tags),
@@ -345,6 +383,12 @@ impl Neighbourhood {
                 map.travel_flows[&r] != TravelFlow::from_osm(&road.tags)
                     || map.modal_filters.get(&r) != map.original_modal_filters.get(&r),
             );
+            if let Some(annotation) = map.get_annotation(Intervention::TravelFlow { road: *r }) {
+                f.set_property(
+                    "travel_flow_annotation",
+                    serde_json::to_value(annotation).unwrap(),
+                );
+            }
             f.set_property("road", r.0);
             if let Some(color) = derived.render_cells.colors_per_road.get(&r) {
                 f.set_property("cell_color", *color);
@@ -364,7 +408,17 @@ impl Neighbourhood {
                     .cloned()
                     .unwrap_or(0),
             );
+            if let Some(ref through_traffic) = derived.shortcuts.through_traffic_per_road {
+                f.set_property(
+                    "through_traffic",
+                    through_traffic.get(&r).cloned().unwrap_or(0),
+                );
+            }
+            // Extra meters to drive from the nearest main road, or null if unreachable
+            f.set_property("access_penalty", derived.access.penalty(*r));
             f.set_property("travel_flow", map.travel_flows[&r].to_string());
+            f.set_property("speed_mph", map.speed_limits[&r]);
+            f.set_property("speed_limit_edited", map.speed_limits[&r] != road.speed_mph);
             f.set_property(
                 "travel_flow_edited",
                 map.travel_flows[&r] != TravelFlow::from_osm(&road.tags),
@@ -374,6 +428,12 @@ impl Neighbourhood {
                 map.travel_flows[&r] != TravelFlow::from_osm(&road.tags)
                     || map.modal_filters.get(&r) != map.original_modal_filters.get(&r),
             );
+            if let Some(annotation) = map.get_annotation(Intervention::TravelFlow { road: *r }) {
+                f.set_property(
+                    "travel_flow_annotation",
+                    serde_json::to_value(annotation).unwrap(),
+                );
+            }
             f.set_property("road", r.0);
             if let Some(color) = derived.render_cells.colors_per_road.get(&r) {
                 f.set_property("cell_color", *color);
@@ -422,6 +482,8 @@ impl Neighbourhood {
             features.push(f);
         }
 
+        let (max_access_penalty, mean_access_penalty) = derived.access.summary();
+
         FeatureCollection {
             features,
             bbox: None,
@@ -431,6 +493,8 @@ impl Neighbourhood {
                 serde_json::json!({
                     "undo_length": map.undo_stack.len(),
                     "redo_length": map.redo_stack.len(),
+                    "max_access_penalty": max_access_penalty,
+                    "mean_access_penalty": mean_access_penalty,
                 })
                 .as_object()
                 .unwrap()
@@ -439,6 +503,14 @@ impl Neighbourhood {
         }
     }
 
+    /// How many arrows are drawn for traffic entering the neighbourhood
+    pub fn num_border_entries(&self, map: &MapModel) -> usize {
+        self.border_intersections
+            .iter()
+            .map(|i| self.border_entries(*i, map).count())
+            .sum()
+    }
+
     fn border_entries<'a>(
         &'a self,
         i: IntersectionID,
@@ -494,6 +566,79 @@ impl Neighbourhood {
     }
 }
 
+/// Bridges and tunnels may be inside the boundary without being part of the neighbourhood, like a
+/// main road passing overhead. Finds groups of connected grade-separated roads that never touch an
+/// at-grade interior road, and returns them with the reason they're grade-separated.
+fn find_grade_separated_roads(
+    map: &MapModel,
+    inside: &BTreeSet<RoadID>,
+) -> BTreeMap<RoadID, String> {
+    let mut grade_separated: BTreeMap<RoadID, String> = inside
+        .iter()
+        .filter_map(|r| grade_separation(&map.get_r(*r).tags).map(|reason| (*r, reason)))
+        .collect();
+
+    let at_grade_interior_intersections: BTreeSet<IntersectionID> = inside
+        .iter()
+        .filter(|r| !grade_separated.contains_key(r) && !map.is_main_road[r])
+        .flat_map(|r| {
+            let road = map.get_r(*r);
+            [road.src_i, road.dst_i]
+        })
+        .collect();
+
+    let mut visited = BTreeSet::new();
+    let starts: Vec<RoadID> = grade_separated.keys().cloned().collect();
+    for start in starts {
+        if visited.contains(&start) {
+            continue;
+        }
+        // Flood through connected grade-separated roads
+        let mut component = Vec::new();
+        let mut queue = vec![start];
+        visited.insert(start);
+        while let Some(r) = queue.pop() {
+            component.push(r);
+            let road = map.get_r(r);
+            for i in [road.src_i, road.dst_i] {
+                for next in &map.get_i(i).roads {
+                    if grade_separated.contains_key(next) && visited.insert(*next) {
+                        queue.push(*next);
+                    }
+                }
+            }
+        }
+
+        // A bridge or tunnel joining the neighbourhood's own streets belongs to it
+        let connected = component.iter().any(|r| {
+            let road = map.get_r(*r);
+            at_grade_interior_intersections.contains(&road.src_i)
+                || at_grade_interior_intersections.contains(&road.dst_i)
+        });
+        if connected {
+            for r in component {
+                grade_separated.remove(&r);
+            }
+        }
+    }
+
+    grade_separated
+}
+
+fn grade_separation(tags: &Tags) -> Option<String> {
+    if tags.has("bridge") && !tags.is("bridge", "no") {
+        return Some("bridge".to_string());
+    }
+    if tags.has("tunnel") && !tags.is("tunnel", "no") {
+        return Some("tunnel".to_string());
+    }
+    let layer = tags.get("layer")?.parse::<i32>().ok()?;
+    if layer != 0 {
+        return Some(format!("layer {layer}"));
+    }
+    None
+}
+
 fn is_road_mostly_inside(line_string: &LineString, polygon: &Polygon) -> bool {
     let invert = false;
     let clipped = polygon.clip(&MultiLineString(vec![line_string.clone()]), invert);
@@ -535,12 +680,13 @@ impl RouterInput for NeighbourhoodShortcutsRouterInput<'_> {
         self.map.get_i(i)
     }
 
-    fn modal_filter(&self, r: RoadID) -> Option<&ModalFilter> {
-        if self.before {
-            self.map.original_modal_filters.get(&r)
+    fn modal_filters(&self, r: RoadID) -> &[ModalFilter] {
+        let filters = if self.before {
+            &self.map.original_modal_filters
         } else {
-            self.map.modal_filters.get(&r)
-        }
+            &self.map.modal_filters
+        };
+        filters.get(&r).map(Vec::as_slice).unwrap_or_default()
     }
 
     fn travel_flow(&self, r: RoadID) -> TravelFlow {
@@ -551,6 +697,14 @@ impl RouterInput for NeighbourhoodShortcutsRouterInput<'_> {
         }
     }
 
+    fn speed_mph(&self, r: RoadID) -> f64 {
+        if self.before {
+            self.get_r(r).speed_mph
+        } else {
+            self.map.speed_limits[&r]
+        }
+    }
+
     fn diagonal_filter(&self, r: IntersectionID) -> Option<&DiagonalFilter> {
         if self.before {
             None
@@ -566,6 +720,10 @@ impl RouterInput for NeighbourhoodShortcutsRouterInput<'_> {
             &self.map.turn_restrictions[i.0]
         }
     }
+
+    fn via_way_restrictions(&self) -> &Vec<ViaWayRestriction> {
+        &self.map.via_way_restrictions
+    }
 }
 
 impl BorderEntry {
@@ -582,3 +740,33 @@ impl BorderEntry {
         feature
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::osm_tests::{get_road_by_name, load_osm_xml, rectangle_neighbourhood};
+
+    #[test]
+    fn grade_separated_roads() {
+        let map = load_osm_xml("grade_separated");
+        let neighbourhood =
+            rectangle_neighbourhood(&map, "test", [(-0.115, 55.703), (-0.111, 55.7054)]);
+
+        // A main road passing overhead and an isolated tunnel aren't part of the neighbourhood
+        let flyover = get_road_by_name(&map, "flyover");
+        let underpass = get_road_by_name(&map, "underpass");
+        assert_eq!(neighbourhood.excluded_roads.len(), 2);
+        assert_eq!(neighbourhood.excluded_roads[&flyover], "bridge");
+        assert_eq!(neighbourhood.excluded_roads[&underpass], "tunnel");
+        assert!(!neighbourhood.main_roads.contains(&flyover));
+        assert!(!neighbourhood.interior_roads.contains(&underpass));
+
+        // A bridge joining the neighbourhood's own streets belongs to it
+        let local_bridge = get_road_by_name(&map, "local bridge");
+        assert!(neighbourhood.interior_roads.contains(&local_bridge));
+        for name in ["street 1", "street 2", "street 3"] {
+            assert!(neighbourhood
+                .interior_roads
+                .contains(&get_road_by_name(&map, name)));
+        }
+    }
+}
@@ -1,7 +1,13 @@
 use crate::map_model::ProjectDetails;
+use crate::neighbourhood::{NeighbourhoodBoundary, NeighbourhoodDefinition};
 use crate::test_fixtures::TEST_DB_SCHEMA_VERSION;
-use crate::{FilterKind, Intersection, MapModel, RoadID};
-use geo::MultiPolygon;
+use crate::{FilterKind, Intersection, MapModel, Neighbourhood, RoadID};
+use geo::{Coord, LineString, MultiPolygon, Polygon};
+
+/// The halves of `two_neighbourhoods`, as (min lon, min lat) and (max lon, max lat). They share
+/// the `middle` main road.
+pub const WEST_NEIGHBOURHOOD: [(f64, f64); 2] = [(-0.115, 55.703), (-0.111, 55.7066)];
+pub const EAST_NEIGHBOURHOOD: [(f64, f64); 2] = [(-0.111, 55.703), (-0.107, 55.7066)];
 
 #[test]
 fn test_deadend_with_barrier() {
@@ -29,6 +35,55 @@ fn test_no_left_turn() {
     assert!(map.get_r(restrictions[0].1).tags.is("name", "west"));
 }
 
+#[test]
+fn test_only_straight_on() {
+    let map = load_osm_xml("only_straight_on");
+    let i = map
+        .intersections
+        .iter()
+        .find(|i| i.roads.len() > 1)
+        .unwrap()
+        .id;
+    // Every turn from the south except to the north is banned, including a U-turn
+    let mut banned: Vec<&String> = map.turn_restrictions[i.0]
+        .iter()
+        .map(|(from, to)| {
+            assert!(map.get_r(*from).tags.is("name", "south"));
+            map.get_r(*to).tags.get("name").unwrap()
+        })
+        .collect();
+    banned.sort();
+    assert_eq!(banned, vec!["east", "south", "west"]);
+}
+
+#[test]
+fn test_via_way_restriction() {
+    let map = load_osm_xml("via_way");
+    assert_eq!(map.via_way_restrictions.len(), 1);
+    let restriction = &map.via_way_restrictions[0];
+    assert_eq!(restriction.from.0, get_road_by_name(&map, "from"));
+    assert_eq!(
+        restriction.via.iter().map(|(r, _)| *r).collect::<Vec<_>>(),
+        vec![get_road_by_name(&map, "via")]
+    );
+    assert_eq!(restriction.to, get_road_by_name(&map, "to"));
+
+    // There's no other way to reach the banned road
+    let router_input = map.router_input_before();
+    let route = |from, to| {
+        map.router_before.route_from_roads(
+            &router_input,
+            get_road_by_name(&map, from),
+            get_road_by_name(&map, to),
+        )
+    };
+    assert!(route("from", "to").is_none());
+    assert!(route("from", "via").is_some());
+    assert!(route("from", "side 2").is_some());
+    assert!(route("side 1", "to").is_some());
+    assert!(route("to", "from").is_some());
+}
+
 #[test]
 fn test_dog_legs() {
     let map = load_osm_xml("dog_legs");
@@ -73,7 +128,7 @@ fn test_modal_filters() {
         .contains_key(&get_road_by_name(&map, "pedestrianized")));
     assert_eq!(
         FilterKind::BusGate,
-        map.modal_filters[&get_road_by_name(&map, "bus only")].kind
+        map.modal_filters[&get_road_by_name(&map, "bus only")][0].kind
     );
 
     assert!(!map
@@ -111,7 +166,35 @@ pub fn load_osm_xml(filename: &str) -> MapModel {
     map
 }
 
-fn get_road_by_name(map: &MapModel, name: &str) -> RoadID {
+/// Makes a neighbourhood from a WGS84 rectangle
+pub fn rectangle_neighbourhood(
+    map: &MapModel,
+    name: &str,
+    [(x1, y1), (x2, y2)]: [(f64, f64); 2],
+) -> Neighbourhood {
+    let mut ring = LineString::from(vec![(x1, y1), (x2, y1), (x2, y2), (x1, y2), (x1, y1)]);
+    ring.0 = ring
+        .0
+        .into_iter()
+        .map(|pt| map.mercator.pt_to_mercator(pt))
+        .collect::<Vec<Coord>>();
+    let definition = NeighbourhoodDefinition {
+        geometry: Polygon::new(ring, Vec::new()),
+        name: name.to_string(),
+        waypoints: None,
+    };
+    Neighbourhood::new(map, NeighbourhoodBoundary::new(definition, None)).unwrap()
+}
+
+pub fn get_roads_by_name(map: &MapModel, name: &str) -> Vec<RoadID> {
+    map.roads
+        .iter()
+        .filter(|r| r.tags.is("name", name))
+        .map(|r| r.id)
+        .collect()
+}
+
+pub fn get_road_by_name(map: &MapModel, name: &str) -> RoadID {
     map.roads
         .iter()
         .find(|r| r.tags.is("name", name))
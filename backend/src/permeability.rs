@@ -0,0 +1,348 @@
+use std::collections::BTreeSet;
+
+use geo::{Coord, Distance, Euclidean, Intersects, Length, LineString};
+use geojson::FeatureCollection;
+use petgraph::graphmap::DiGraphMap;
+use serde::{Deserialize, Serialize};
+use utils::Tags;
+
+use crate::{MapModel, Neighbourhood, RoadID, TravelFlow};
+
+// Limit how many pairs of points are checked; the number of routes grows quadratically
+const MAX_SAMPLES: usize = 30;
+// Pairs closer than this are ignored, because small differences dominate the ratio
+const MIN_STRAIGHT_LINE_METERS: f64 = 50.0;
+// Edits forcing a cyclist to go this many times further than before are flagged
+const MAX_CUT_DETOUR: f64 = 3.0;
+
+/// A footway, cycleway, or similar path that isn't a road. These aren't part of the driving
+/// network, but people walking and cycling use them.
+#[derive(Serialize, Deserialize)]
+pub struct ActiveTravelPath {
+    pub linestring: LineString,
+    pub walking: bool,
+    pub cycling: bool,
+}
+
+impl ActiveTravelPath {
+    /// Returns the (walking, cycling) modes allowed on a non-road path, or None if it's not a
+    /// usable path at all
+    pub fn modes_from_osm(tags: &Tags) -> Option<(bool, bool)> {
+        if !tags.is_any("highway", vec!["footway", "cycleway", "path", "steps"])
+            || tags.is("area", "yes")
+            || tags.is_any("access", vec!["private", "no"])
+        {
+            return None;
+        }
+        let walking = !tags.is("foot", "no");
+        let cycling = !tags.is("highway", "steps")
+            && !tags.is("bicycle", "no")
+            && (tags.is_any("highway", vec!["cycleway", "path"])
+                || tags.is_any("bicycle", vec!["yes", "designated", "permissive"]));
+        if walking || cycling {
+            Some((walking, cycling))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Walking,
+    Cycling,
+}
+
+/// How direct it is to walk or cycle between points within a neighbourhood
+#[derive(Clone, Serialize)]
+pub struct ModeScore {
+    /// The average ratio of network distance to straight-line distance over all reachable pairs.
+    /// 1.0 is perfectly direct.
+    pub mean_directness: f64,
+    pub num_pairs: usize,
+    pub num_unreachable_pairs: usize,
+}
+
+#[derive(Serialize)]
+pub struct PermeabilityScores {
+    pub walking: ModeScore,
+    pub cycling: ModeScore,
+}
+
+/// Walking and cycling permeability of a neighbourhood, using roads and paths inside it, before
+/// and after edits.
+///
+/// Every kind of modal filter lets people walk and cycle through, so filters themselves never
+/// change these scores. No edit can stop people walking along a road, so walking is the same
+/// before and after edits, and only cycling links can be cut. Changing a road to one-way does
+/// affect cycling, unless it's tagged with a contraflow exemption.
+pub struct Permeability {
+    pub before: PermeabilityScores,
+    pub after: PermeabilityScores,
+    /// Edited roads that can no longer be cycled in one direction, with the distance of the
+    /// shortest detour (or None if there's no way around) and the road's own length. Only roads
+    /// where the detour is much longer are included.
+    pub cut_links: Vec<(RoadID, Option<f64>, f64)>,
+}
+
+impl Permeability {
+    pub fn new(map: &MapModel, neighbourhood: &Neighbourhood) -> Self {
+        let samples = sample_points(map, neighbourhood);
+
+        let walking_graph = build_graph(map, neighbourhood, Mode::Walking, false);
+        let cycling_before = build_graph(map, neighbourhood, Mode::Cycling, false);
+        let cycling_after = build_graph(map, neighbourhood, Mode::Cycling, true);
+
+        let walking = score(&walking_graph, &samples);
+        let before = PermeabilityScores {
+            walking: walking.clone(),
+            cycling: score(&cycling_before, &samples),
+        };
+        let after = PermeabilityScores {
+            walking,
+            cycling: score(&cycling_after, &samples),
+        };
+
+        let mut cut_links = Vec::new();
+        for r in &neighbourhood.interior_roads {
+            let road = map.get_r(*r);
+            let flow_before = cycling_flow(map, *r, false);
+            let flow_after = cycling_flow(map, *r, true);
+            let length = Euclidean.length(&road.linestring);
+            for (lost, from, to) in [
+                (
+                    flow_before.flows_forwards() && !flow_after.flows_forwards(),
+                    road.linestring.0[0],
+                    *road.linestring.0.last().unwrap(),
+                ),
+                (
+                    flow_before.flows_backwards() && !flow_after.flows_backwards(),
+                    *road.linestring.0.last().unwrap(),
+                    road.linestring.0[0],
+                ),
+            ] {
+                if !lost {
+                    continue;
+                }
+                let detour =
+                    petgraph::algo::dijkstra(&cycling_after, key(from), Some(key(to)), |e| *e.2)
+                        .get(&key(to))
+                        .cloned();
+                if detour.map(|d| d / length > MAX_CUT_DETOUR).unwrap_or(true) {
+                    cut_links.push((*r, detour, length));
+                }
+            }
+        }
+
+        Self {
+            before,
+            after,
+            cut_links,
+        }
+    }
+
+    pub fn to_json(&self, map: &MapModel) -> serde_json::Value {
+        let mut features = Vec::new();
+        for (r, detour, length) in &self.cut_links {
+            let mut f = map.get_r(*r).to_gj(&map.mercator);
+            f.set_property("road", r.0);
+            f.set_property("detour_meters", *detour);
+            f.set_property("length_meters", *length);
+            features.push(f);
+        }
+        serde_json::json!({
+            "before": self.before,
+            "after": self.after,
+            "cut_links": FeatureCollection {
+                features,
+                bbox: None,
+                foreign_members: None,
+            },
+        })
+    }
+}
+
+/// Intersections inside the neighbourhood, evenly spread out by ID
+fn sample_points(map: &MapModel, neighbourhood: &Neighbourhood) -> Vec<Coord> {
+    let intersections: BTreeSet<_> = neighbourhood
+        .interior_roads
+        .iter()
+        .flat_map(|r| {
+            let road = map.get_r(*r);
+            [road.src_i, road.dst_i]
+        })
+        .collect();
+    let step = (intersections.len() / MAX_SAMPLES).max(1);
+    intersections
+        .into_iter()
+        .step_by(step)
+        .map(|i| map.get_i(i).point.into())
+        .collect()
+}
+
+fn score(graph: &DiGraphMap<(isize, isize), f64>, samples: &Vec<Coord>) -> ModeScore {
+    let mut total = 0.0;
+    let mut num_pairs = 0;
+    let mut num_unreachable_pairs = 0;
+    for from in samples {
+        if !graph.contains_node(key(*from)) {
+            continue;
+        }
+        let costs = petgraph::algo::dijkstra(graph, key(*from), None, |e| *e.2);
+        for to in samples {
+            let straight_line = Euclidean.distance(*from, *to);
+            if straight_line < MIN_STRAIGHT_LINE_METERS {
+                continue;
+            }
+            match costs.get(&key(*to)) {
+                Some(cost) => {
+                    total += cost / straight_line;
+                    num_pairs += 1;
+                }
+                None => {
+                    num_unreachable_pairs += 1;
+                }
+            }
+        }
+    }
+    ModeScore {
+        mean_directness: if num_pairs == 0 {
+            0.0
+        } else {
+            total / (num_pairs as f64)
+        },
+        num_pairs,
+        num_unreachable_pairs,
+    }
+}
+
+/// Nodes are every point along the roads and paths, so paths joining a road partway along are
+/// connected
+fn build_graph(
+    map: &MapModel,
+    neighbourhood: &Neighbourhood,
+    mode: Mode,
+    after_edits: bool,
+) -> DiGraphMap<(isize, isize), f64> {
+    let mut graph = DiGraphMap::new();
+    for r in neighbourhood.editable_roads() {
+        let flow = if mode == Mode::Walking {
+            TravelFlow::BothWays
+        } else {
+            cycling_flow(map, r, after_edits)
+        };
+        add_linestring(&mut graph, &map.get_r(r).linestring, flow);
+    }
+
+    let boundary = neighbourhood.boundary_polygon();
+    for path in &map.active_travel_paths {
+        let allowed = match mode {
+            Mode::Walking => path.walking,
+            Mode::Cycling => path.cycling,
+        };
+        if allowed && path.linestring.intersects(boundary) {
+            add_linestring(&mut graph, &path.linestring, TravelFlow::BothWays);
+        }
+    }
+    graph
+}
+
+fn add_linestring(
+    graph: &mut DiGraphMap<(isize, isize), f64>,
+    linestring: &LineString,
+    flow: TravelFlow,
+) {
+    for line in linestring.lines() {
+        let length = Euclidean.length(&line);
+        if flow.flows_forwards() {
+            graph.add_edge(key(line.start), key(line.end), length);
+        }
+        if flow.flows_backwards() {
+            graph.add_edge(key(line.end), key(line.start), length);
+        }
+    }
+}
+
+fn cycling_flow(map: &MapModel, r: RoadID, after_edits: bool) -> TravelFlow {
+    let road = map.get_r(r);
+    if road.tags.is("oneway:bicycle", "no")
+        || road
+            .tags
+            .is_any("cycleway", vec!["opposite", "opposite_lane"])
+    {
+        return TravelFlow::BothWays;
+    }
+    if after_edits {
+        map.travel_flows[&r]
+    } else {
+        TravelFlow::from_osm(&road.tags)
+    }
+}
+
+// Round to centimeters, so the same point from different roads and paths matches
+fn key(pt: Coord) -> (isize, isize) {
+    (
+        (pt.x * 100.0).round() as isize,
+        (pt.y * 100.0).round() as isize,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_tests::{
+        get_roads_by_name, load_osm_xml, rectangle_neighbourhood, WEST_NEIGHBOURHOOD,
+    };
+    use crate::{FilterKind, ModalFilter};
+
+    #[test]
+    fn one_way_cuts_cycling_but_not_walking() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let neighbourhood = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        // Roads between a main road and the middle of the neighbourhood
+        let entrance = |name: &str| {
+            get_roads_by_name(&map, name)
+                .into_iter()
+                .find(|r| {
+                    let road = map.get_r(*r);
+                    neighbourhood.border_intersections.contains(&road.src_i)
+                        || neighbourhood.border_intersections.contains(&road.dst_i)
+                })
+                .unwrap()
+        };
+        let (one_way, filtered) = (entrance("west 1"), entrance("west 2"));
+
+        // Filters never cut anything
+        map.modal_filters.insert(
+            filtered,
+            vec![ModalFilter {
+                kind: FilterKind::WalkCycleOnly,
+                percent_along: 0.5,
+            }],
+        );
+        let permeability = Permeability::new(&map, &neighbourhood);
+        assert!(permeability.cut_links.is_empty());
+        assert_eq!(
+            permeability.before.cycling.mean_directness,
+            permeability.after.cycling.mean_directness
+        );
+
+        // Cycling back against the one-way means going around the block, more than 3 times as far
+        map.travel_flows.insert(one_way, TravelFlow::FORWARDS);
+        let permeability = Permeability::new(&map, &neighbourhood);
+        assert_eq!(permeability.cut_links.len(), 1);
+        let (r, detour, length) = permeability.cut_links[0];
+        assert_eq!(r, one_way);
+        assert!(detour.unwrap() > MAX_CUT_DETOUR * length);
+        assert!(
+            permeability.after.cycling.mean_directness
+                > permeability.before.cycling.mean_directness
+        );
+
+        assert_eq!(
+            permeability.before.walking.mean_directness,
+            permeability.after.walking.mean_directness
+        );
+        assert_eq!(permeability.after.walking.num_unreachable_pairs, 0);
+    }
+}
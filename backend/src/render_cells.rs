@@ -1,6 +1,11 @@
 use std::collections::{HashMap, HashSet, VecDeque};
 
-use geo::{BooleanOps, BoundingRect, Coord, Densify, Euclidean, LineString, MultiPolygon, Rect};
+use anyhow::{bail, Result};
+use geo::{
+    unary_union, BooleanOps, BoundingRect, Coord, Densify, Euclidean, LineString, MultiPolygon,
+    Point, Polygon, Rect, TriangulateDelaunay,
+};
+use rstar::{primitives::GeomWithData, RTree};
 use serde::{Serialize, Serializer};
 use utils::{Grid, LineSplit};
 
@@ -44,7 +49,9 @@ impl Into<geojson::JsonValue> for Color {
 }
 
 pub struct RenderCells {
-    /// Rarely, this might be empty if the area is very small
+    /// One per cell, in the same order. Rarely, this might be empty if the area is very small.
+    /// Unimportant cells aren't drawn, so they're always empty; their roads are in
+    /// `unimportant_roads` instead.
     pub polygons_per_cell: Vec<MultiPolygon>,
     /// Colors per cell, such that adjacent cells are colored differently
     pub colors: Vec<Color>,
@@ -62,6 +69,17 @@ impl RenderCells {
     /// a grid, and then extracts a polygon from the raster. The results don't look perfect, but
     /// it's fast.
     pub fn new(map: &MapModel, neighbourhood: &Neighbourhood, cells: &Vec<Cell>) -> RenderCells {
+        if map.exact_cell_polygons {
+            match RenderCells::new_exact(map, neighbourhood, cells) {
+                Ok(result) => {
+                    return result;
+                }
+                Err(err) => {
+                    warn!("Exact cell polygons failed, falling back to the grid: {err}");
+                }
+            }
+        }
+
         let boundary_polygon = neighbourhood.boundary_polygon().clone();
         // Make a 2D grid covering the polygon. Each tile in the grid contains a cell index, which
         // will become a color by the end. None means no cell is assigned yet.
@@ -81,7 +99,11 @@ impl RenderCells {
                 unimportant_roads.extend(cell.roads.keys().cloned());
                 continue;
             }
-            for (r, interval) in &cell.roads {
+            for (r, interval) in cell
+                .roads
+                .iter()
+                .flat_map(|(r, intervals)| intervals.iter().map(move |x| (r, x)))
+            {
                 let road = map.get_r(*r);
                 let slice = slice_linestring(&road.linestring, interval.start, interval.end);
                 // Walk along the center line
@@ -91,8 +113,9 @@ impl RenderCells {
                         ((pt.y - bounds.min().y) / RESOLUTION_M) as usize,
                     );
                     // Due to tunnels/bridges, sometimes a road belongs to a neighbourhood, but
-                    // leaks outside the neighbourhood's boundary. Avoid crashing. The real fix
-                    // is to better define boundaries in the face of z-order changes.
+                    // leaks outside the neighbourhood's boundary. Grade-separated roads not
+                    // connected to the neighbourhood are excluded, but a bridge joining its
+                    // streets may still leak. Avoid crashing.
                     //
                     // Example is https://www.openstreetmap.org/way/87298633
                     if grid_idx >= grid.data.len() {
@@ -133,23 +156,7 @@ impl RenderCells {
 
         let adjacencies = diffusion(&mut grid, boundary_marker);
         let mut cell_colors = color_cells(cells.len(), adjacencies);
-
-        // Color some special cells
-        for (idx, cell) in cells.iter().enumerate() {
-            // If there's only one cell, it's not disconnected -- there are likely no main roads
-            if cell.is_disconnected() && cells.len() > 1 {
-                // Communicate pedestrianized differently
-                if cell.roads.keys().all(|r| {
-                    map.get_r(*r)
-                        .tags
-                        .is_any("highway", vec!["pedestrian", "service"])
-                }) {
-                    cell_colors[idx] = Color::Pedestrianized;
-                } else {
-                    cell_colors[idx] = Color::Disconnected;
-                }
-            }
-        }
+        color_special_cells(map, cells, &mut cell_colors);
 
         let mut result = RenderCells {
             polygons_per_cell: Vec::new(),
@@ -176,6 +183,149 @@ impl RenderCells {
     }
 }
 
+impl RenderCells {
+    /// Partition a neighbourhood's boundary polygon based on the cells, without a grid. Points
+    /// along each cell's roads and along the boundary are triangulated, and each point claims the
+    /// nearby part of every triangle it touches. This approximates a Voronoi diagram of the roads,
+    /// clipped to the boundary. It's slower than `new`, but the polygons are clean enough to
+    /// print. Like `new`, unimportant cells get no polygon.
+    fn new_exact(
+        map: &MapModel,
+        neighbourhood: &Neighbourhood,
+        cells: &Vec<Cell>,
+    ) -> Result<RenderCells> {
+        let boundary_polygon = neighbourhood.boundary_polygon().clone();
+
+        let mut unimportant_roads = HashSet::new();
+        // Each seed point belongs to a cell. Deduplicate by rounding to centimeters, because the
+        // triangulation merges identical points.
+        let mut seeds: HashMap<(isize, isize), usize> = HashMap::new();
+        for (cell_idx, cell) in cells.iter().enumerate() {
+            if cell.unimportant {
+                unimportant_roads.extend(cell.roads.keys().cloned());
+                continue;
+            }
+            for (r, interval) in cell
+                .roads
+                .iter()
+                .flat_map(|(r, intervals)| intervals.iter().map(move |x| (r, x)))
+            {
+                let slice =
+                    slice_linestring(&map.get_r(*r).linestring, interval.start, interval.end);
+                for pt in Euclidean.densify(&slice, RESOLUTION_M / 2.0).0 {
+                    // If roads from two different cells overlap, the first one wins
+                    seeds.entry(seed_key(pt)).or_insert(cell_idx);
+                }
+            }
+        }
+        if seeds.is_empty() {
+            bail!("no cells have roads to seed from");
+        }
+
+        // Points along the boundary make sure the triangulation covers the whole polygon. They
+        // belong to the cell with the closest road.
+        let closest_seed = RTree::bulk_load(
+            seeds
+                .iter()
+                .map(|((x, y), cell_idx)| {
+                    GeomWithData::new(Point::new(*x as f64 / 100.0, *y as f64 / 100.0), *cell_idx)
+                })
+                .collect(),
+        );
+        for pt in Euclidean
+            .densify(boundary_polygon.exterior(), RESOLUTION_M / 2.0)
+            .0
+        {
+            let key = seed_key(pt);
+            if !seeds.contains_key(&key) {
+                let cell_idx = closest_seed
+                    .nearest_neighbor(&Point::from(pt))
+                    .unwrap()
+                    .data;
+                seeds.insert(key, cell_idx);
+            }
+        }
+
+        let points = LineString::new(
+            seeds
+                .keys()
+                .map(|(x, y)| Coord {
+                    x: *x as f64 / 100.0,
+                    y: *y as f64 / 100.0,
+                })
+                .collect(),
+        );
+        let triangles = points.unconstrained_triangulation()?;
+
+        let mut pieces_per_cell: Vec<Vec<Polygon>> = vec![Vec::new(); cells.len()];
+        let mut adjacencies = HashSet::new();
+        for triangle in triangles {
+            let [a, b, c] = triangle.to_array();
+            let labels = [a, b, c].map(|pt| seeds.get(&seed_key(pt)).cloned());
+            let [Some(label_a), Some(label_b), Some(label_c)] = labels else {
+                bail!("triangulation produced an unknown vertex");
+            };
+
+            if label_a == label_b && label_b == label_c {
+                pieces_per_cell[label_a].push(triangle.to_polygon());
+                continue;
+            }
+
+            // Split the triangle between its vertices, using the midpoints of the edges and the
+            // centroid
+            let centroid = (a + b + c) / 3.0;
+            for (pt, label, prev, next) in
+                [(a, label_a, c, b), (b, label_b, a, c), (c, label_c, b, a)]
+            {
+                pieces_per_cell[label].push(Polygon::new(
+                    LineString::new(vec![pt, (pt + next) / 2.0, centroid, (pt + prev) / 2.0, pt]),
+                    Vec::new(),
+                ));
+            }
+            for (x, y) in [(label_a, label_b), (label_b, label_c), (label_c, label_a)] {
+                if x != y {
+                    adjacencies.insert((x, y));
+                    adjacencies.insert((y, x));
+                }
+            }
+        }
+
+        let mut cell_colors = color_cells(cells.len(), adjacencies);
+        color_special_cells(map, cells, &mut cell_colors);
+
+        let boundary = MultiPolygon::new(vec![boundary_polygon]);
+        let mut result = RenderCells {
+            polygons_per_cell: Vec::new(),
+            colors: Vec::new(),
+            colors_per_border: HashMap::new(),
+            colors_per_road: HashMap::new(),
+            unimportant_roads,
+        };
+        for (idx, color) in cell_colors.into_iter().enumerate() {
+            result
+                .polygons_per_cell
+                .push(unary_union(&pieces_per_cell[idx]).intersection(&boundary));
+            result.colors.push(color);
+
+            for i in &cells[idx].border_intersections {
+                result.colors_per_border.insert(*i, color);
+            }
+            // For roads crossing cells, arbitrarily overwrite
+            for r in cells[idx].roads.keys() {
+                result.colors_per_road.insert(*r, color);
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn seed_key(pt: Coord) -> (isize, isize) {
+    (
+        (pt.x * 100.0).round() as isize,
+        (pt.y * 100.0).round() as isize,
+    )
+}
+
 fn finalize(
     result: &mut RenderCells,
     main_grid: Grid<Option<usize>>,
@@ -378,6 +528,25 @@ fn color_cells(num_cells: usize, adjacencies: HashSet<(usize, usize)>) -> Vec<Co
         .collect()
 }
 
+/// Disconnected and pedestrianized cells get special colors
+fn color_special_cells(map: &MapModel, cells: &Vec<Cell>, cell_colors: &mut Vec<Color>) {
+    for (idx, cell) in cells.iter().enumerate() {
+        // If there's only one cell, it's not disconnected -- there are likely no main roads
+        if cell.is_disconnected() && cells.len() > 1 {
+            // Communicate pedestrianized differently
+            if cell.roads.keys().all(|r| {
+                map.get_r(*r)
+                    .tags
+                    .is_any("highway", vec!["pedestrian", "service"])
+            }) {
+                cell_colors[idx] = Color::Pedestrianized;
+            } else {
+                cell_colors[idx] = Color::Disconnected;
+            }
+        }
+    }
+}
+
 // Return the linestring in an interval, or the whole thing if something breaks
 fn slice_linestring(linestring: &LineString, start: f64, end: f64) -> LineString {
     linestring
@@ -385,3 +554,59 @@ fn slice_linestring(linestring: &LineString, start: f64, end: f64) -> LineString
         .and_then(|result| result.into_second())
         .unwrap_or_else(|| linestring.clone())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_tests::{load_osm_xml, rectangle_neighbourhood, WEST_NEIGHBOURHOOD};
+    use crate::{FilterKind, ModalFilter};
+    use geo::{Contains, InterpolatableLine, Intersects};
+
+    #[test]
+    fn exact_polygons_cover_their_roads() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let neighbourhood = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        // Filter every road touching a main road, splitting off a small cell at each entrance
+        // and disconnecting the middle
+        let entrances: Vec<RoadID> = neighbourhood
+            .interior_roads
+            .iter()
+            .filter(|r| {
+                let road = map.get_r(**r);
+                neighbourhood.border_intersections.contains(&road.src_i)
+                    || neighbourhood.border_intersections.contains(&road.dst_i)
+            })
+            .cloned()
+            .collect();
+        for r in entrances {
+            map.modal_filters.insert(
+                r,
+                vec![ModalFilter {
+                    kind: FilterKind::WalkCycleOnly,
+                    percent_along: 0.5,
+                }],
+            );
+        }
+
+        let cells = Cell::find_all(&map, &neighbourhood);
+        assert_eq!(cells.len(), 7);
+        let render_cells = RenderCells::new_exact(&map, &neighbourhood, &cells).unwrap();
+        assert_eq!(render_cells.polygons_per_cell.len(), cells.len());
+
+        for (idx, cell) in cells.iter().enumerate() {
+            for (r, intervals) in &cell.roads {
+                for interval in intervals {
+                    let slice =
+                        slice_linestring(&map.get_r(*r).linestring, interval.start, interval.end);
+                    let pt = slice.point_at_ratio_from_start(&Euclidean, 0.5).unwrap();
+                    assert!(render_cells.polygons_per_cell[idx].intersects(&pt));
+                    for (other_idx, other) in render_cells.polygons_per_cell.iter().enumerate() {
+                        if other_idx != idx {
+                            assert!(!other.contains(&pt));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
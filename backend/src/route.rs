@@ -1,14 +1,15 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use fast_paths::{deserialize_32, serialize_32, FastGraph, InputGraph, PathCalculator};
+use anyhow::Result;
+use fast_paths::{deserialize_32, serialize_32, FastGraph, InputGraph, NodeId, PathCalculator};
 use geo::{Coord, Euclidean, Length, LineLocatePoint, LineString};
 use itertools::Itertools;
 use rstar::{primitives::GeomWithData, RTree};
 use serde::{Deserialize, Serialize};
 use utils::{deserialize_nodemap, LineSplit, NodeMap};
 
-use crate::map_model::{DiagonalFilter, Direction};
+use crate::map_model::{DiagonalFilter, Direction, ViaWayRestriction};
 use crate::{
     Intersection, IntersectionID, MapModel, ModalFilter, Position, Road, RoadID, TravelFlow,
 };
@@ -20,8 +21,13 @@ pub struct Router {
     ch: FastGraph,
     #[serde(skip_serializing, skip_deserializing)]
     path_calculator: RefCell<Option<PathCalculator>>,
+    /// Nodes are a road travelled in some direction. Via-way turn restrictions need copies of
+    /// some nodes, only reachable partway through a restricted sequence of roads. The copies are
+    /// numbered by the third value.
     #[serde(deserialize_with = "deserialize_nodemap")]
-    node_map: NodeMap<(RoadID, Direction)>,
+    node_map: NodeMap<(RoadID, Direction, Option<usize>)>,
+    /// Every copy of a node
+    via_way_copies: BTreeMap<(RoadID, Direction), Vec<NodeId>>,
     pub main_road_penalty: f64,
 }
 
@@ -30,11 +36,13 @@ impl Clone for Router {
         let ch = self.ch.clone();
         let path_calculator = RefCell::new(Some(fast_paths::create_calculator(&ch)));
         let node_map = self.node_map.clone();
+        let via_way_copies = self.via_way_copies.clone();
         let main_road_penalty = self.main_road_penalty;
         Self {
             ch,
             path_calculator,
             node_map,
+            via_way_copies,
             main_road_penalty,
         }
     }
@@ -57,13 +65,38 @@ pub trait RouterInput {
 
     fn get_r(&self, r: RoadID) -> &Road;
     fn get_i(&self, i: IntersectionID) -> &Intersection;
-    fn modal_filter(&self, r: RoadID) -> Option<&ModalFilter>;
+    /// All of the filters on a road, sorted by `percent_along`
+    fn modal_filters(&self, r: RoadID) -> &[ModalFilter];
+    /// The kind of vehicle being routed
+    fn vehicle_profile(&self) -> VehicleProfile {
+        VehicleProfile::ThroughTraffic
+    }
+    /// Modal filters on the road that the vehicle being routed can't pass through, sorted by
+    /// `percent_along`
+    fn blocking_filters(&self, r: RoadID) -> impl Iterator<Item = &ModalFilter> {
+        let profile = self.vehicle_profile();
+        self.modal_filters(r)
+            .iter()
+            .filter(move |filter| filter.kind.blocks(profile))
+    }
     fn has_modal_filter(&self, r: RoadID) -> bool {
-        self.modal_filter(r).is_some()
+        self.blocking_filters(r).next().is_some()
+    }
+    /// The `percent_along` of the first and last blocking filters on the road, if there are any
+    fn blocking_filter_range(&self, r: RoadID) -> Option<(f64, f64)> {
+        let mut filters = self.blocking_filters(r);
+        let first = filters.next()?.percent_along;
+        let last = filters.last().map_or(first, |filter| filter.percent_along);
+        Some((first, last))
     }
     fn travel_flow(&self, r: RoadID) -> TravelFlow;
+    /// The speed limit, which may be edited
+    fn speed_mph(&self, r: RoadID) -> f64 {
+        self.get_r(r).speed_mph
+    }
     fn diagonal_filter(&self, i: IntersectionID) -> Option<&DiagonalFilter>;
     fn turn_restrictions(&self, i: IntersectionID) -> &Vec<(RoadID, RoadID)>;
+    fn via_way_restrictions(&self) -> &Vec<ViaWayRestriction>;
 
     fn snap_to_road(&self, pt: Coord) -> Position {
         let r = self
@@ -80,6 +113,97 @@ pub trait RouterInput {
     }
 }
 
+/// Different kinds of vehicles can pass through different kinds of modal filters
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VehicleProfile {
+    /// Private vehicles passing through. Every kind of filter blocks these.
+    ThroughTraffic,
+    /// Private vehicles belonging to residents, or otherwise needing access
+    Resident,
+    Bus,
+    Emergency,
+}
+
+impl VehicleProfile {
+    pub const ALL: [VehicleProfile; 4] = [
+        VehicleProfile::ThroughTraffic,
+        VehicleProfile::Resident,
+        VehicleProfile::Bus,
+        VehicleProfile::Emergency,
+    ];
+
+    pub fn to_string(self) -> &'static str {
+        match self {
+            Self::ThroughTraffic => "through_traffic",
+            Self::Resident => "resident",
+            Self::Bus => "bus",
+            Self::Emergency => "emergency",
+        }
+    }
+
+    pub fn from_string(x: &str) -> Result<Self> {
+        match x {
+            "through_traffic" => Ok(Self::ThroughTraffic),
+            "resident" => Ok(Self::Resident),
+            "bus" => Ok(Self::Bus),
+            "emergency" => Ok(Self::Emergency),
+            _ => bail!("Invalid VehicleProfile: {x}"),
+        }
+    }
+}
+
+/// Wraps another `RouterInput` to route a different kind of vehicle
+pub struct ProfileRouterInput<'a, R: RouterInput> {
+    pub inner: &'a R,
+    pub profile: VehicleProfile,
+}
+
+impl<R: RouterInput> RouterInput for ProfileRouterInput<'_, R> {
+    fn roads_iter(&self) -> impl Iterator<Item = &Road> {
+        self.inner.roads_iter()
+    }
+
+    fn closest_road(&self) -> &RTree<GeomWithData<LineString, RoadID>> {
+        self.inner.closest_road()
+    }
+
+    fn get_r(&self, r: RoadID) -> &Road {
+        self.inner.get_r(r)
+    }
+
+    fn get_i(&self, i: IntersectionID) -> &Intersection {
+        self.inner.get_i(i)
+    }
+
+    fn modal_filters(&self, r: RoadID) -> &[ModalFilter] {
+        self.inner.modal_filters(r)
+    }
+
+    fn vehicle_profile(&self) -> VehicleProfile {
+        self.profile
+    }
+
+    fn travel_flow(&self, r: RoadID) -> TravelFlow {
+        self.inner.travel_flow(r)
+    }
+
+    fn speed_mph(&self, r: RoadID) -> f64 {
+        self.inner.speed_mph(r)
+    }
+
+    fn diagonal_filter(&self, i: IntersectionID) -> Option<&DiagonalFilter> {
+        self.inner.diagonal_filter(i)
+    }
+
+    fn turn_restrictions(&self, i: IntersectionID) -> &Vec<(RoadID, RoadID)> {
+        self.inner.turn_restrictions(i)
+    }
+
+    fn via_way_restrictions(&self) -> &Vec<ViaWayRestriction> {
+        self.inner.via_way_restrictions()
+    }
+}
+
 impl Router {
     pub fn empty() -> Self {
         let mut input_graph = InputGraph::new();
@@ -92,6 +216,7 @@ impl Router {
             ch,
             path_calculator,
             node_map,
+            via_way_copies: BTreeMap::new(),
             main_road_penalty: 1.0,
         }
     }
@@ -100,6 +225,27 @@ impl Router {
         let mut input_graph = InputGraph::new();
         let mut node_map = NodeMap::new();
 
+        // Each via-way restriction is a sequence of steps. Every prefix of that sequence, after
+        // the first via road, gets a copy of its last node. Normal nodes lead to copies at the
+        // start of a sequence, and copies only lead to the next copy or back to normal nodes, so
+        // the banned movement after the full sequence can be left out.
+        let mut copies: BTreeMap<Vec<(RoadID, Direction)>, usize> = BTreeMap::new();
+        let mut banned_after: BTreeMap<Vec<(RoadID, Direction)>, Vec<RoadID>> = BTreeMap::new();
+        for restriction in router_input.via_way_restrictions() {
+            let mut steps = vec![restriction.from];
+            for step in &restriction.via {
+                steps.push(*step);
+                let next_copy = copies.len();
+                copies.entry(steps.clone()).or_insert(next_copy);
+            }
+            banned_after.entry(steps).or_default().push(restriction.to);
+        }
+        let first_copies: HashMap<((RoadID, Direction), (RoadID, Direction)), usize> = copies
+            .iter()
+            .filter(|(steps, _)| steps.len() == 2)
+            .map(|(steps, copy)| ((steps[0], steps[1]), *copy))
+            .collect();
+
         for road in router_input.roads_iter() {
             if router_input.has_modal_filter(road.id) {
                 continue;
@@ -109,21 +255,25 @@ impl Router {
                 continue;
             }
 
-            let penalty = if road.is_severance() {
-                main_road_penalty
-            } else {
-                1.0
-            };
-            let cost = (penalty * road.cost_seconds() * 100.0) as usize;
+            let cost = road_cost(
+                road,
+                router_input.speed_mph(road.id),
+                main_road_penalty,
+                1.0,
+            );
 
             let mut link_through_intersection =
                 |intersection: &Intersection, direction: Direction| {
                     // a given NodeId might refer to a different physical feature across rebuilds of
                     // the routing graph - do not assume they are stable.
-                    let from = node_map.get_or_insert((road.id, direction));
-                    for outgoing_road in intersection.allowed_movements_from(road.id, router_input)
+                    let from = node_map.get_or_insert((road.id, direction, None));
+                    for (to_road, to_direction) in
+                        intersection.allowed_movements_from(road.id, router_input)
                     {
-                        let to = node_map.get_or_insert(outgoing_road);
+                        let copy = first_copies
+                            .get(&((road.id, direction), (to_road, to_direction)))
+                            .cloned();
+                        let to = node_map.get_or_insert((to_road, to_direction, copy));
                         input_graph.add_edge(from, to, cost);
                     }
                 };
@@ -136,6 +286,43 @@ impl Router {
                 link_through_intersection(router_input.get_i(road.src_i), Direction::Backwards);
             }
         }
+
+        let mut via_way_copies: BTreeMap<(RoadID, Direction), Vec<NodeId>> = BTreeMap::new();
+        for (steps, copy) in &copies {
+            let (r, direction) = *steps.last().unwrap();
+            // Only reachable through a movement allowed in this graph
+            let Some(from) = node_map.get((r, direction, Some(*copy))) else {
+                continue;
+            };
+            via_way_copies.entry((r, direction)).or_default().push(from);
+
+            let road = router_input.get_r(r);
+            let cost = road_cost(
+                road,
+                router_input.speed_mph(road.id),
+                main_road_penalty,
+                1.0,
+            );
+            let i = match direction {
+                Direction::Forwards => road.dst_i,
+                Direction::Backwards => road.src_i,
+            };
+            let banned = banned_after.get(steps);
+            for (to_road, to_direction) in router_input
+                .get_i(i)
+                .allowed_movements_from(r, router_input)
+            {
+                if banned.is_some_and(|list| list.contains(&to_road)) {
+                    continue;
+                }
+                let mut next_steps = steps.clone();
+                next_steps.push((to_road, to_direction));
+                let next_copy = copies.get(&next_steps).cloned();
+                let to = node_map.get_or_insert((to_road, to_direction, next_copy));
+                input_graph.add_edge(from, to, cost);
+            }
+        }
+
         input_graph.freeze();
         let ch = fast_paths::prepare(&input_graph);
         let path_calculator = RefCell::new(Some(fast_paths::create_calculator(&ch)));
@@ -144,6 +331,7 @@ impl Router {
             ch,
             path_calculator,
             node_map,
+            via_way_copies,
             main_road_penalty,
         }
     }
@@ -194,9 +382,9 @@ impl Router {
         if start.road == end.road {
             let mut one_step = true;
 
-            // Edge case: if the route starts and ends on the same road AND there's a filter, check
-            // if the start and end position are on the same side of the filter or not.
-            if let Some(filter) = router_input.modal_filter(start.road) {
+            // Edge case: if the route starts and ends on the same road AND there are filters,
+            // check if any filter is between the start and end position.
+            for filter in router_input.blocking_filters(start.road) {
                 let start_before = start.percent_along <= filter.percent_along;
                 let end_before = end.percent_along <= filter.percent_along;
                 if start_before != end_before {
@@ -218,68 +406,140 @@ impl Router {
 
         // Nodes in the contraction hierarchy are (road, direction) pairs. The start and end of the
         // search can happen in two directions.
-        let mut start_nodes = vec![];
-        let mut end_nodes = vec![];
-        for (nodes, position, is_start) in [
-            (&mut start_nodes, start, true),
-            (&mut end_nodes, end, false),
-        ] {
-            for direction in [Direction::Forwards, Direction::Backwards] {
-                if let Some(node) = self.node_map.get((position.road, direction)) {
-                    // Calculate the cost of the first or last road, which usually doesn't use the
-                    // entire length of the road.
-                    // Note this extra cost gets double-counted -- the contraction hierachy edge
-                    // weights count crossing the full start and end road. But this is OK; the sum
-                    // cost is only used to pick the shortest path.
-                    let road = router_input.get_r(position.road);
-                    let percent_of_length = if (direction == Direction::Forwards) == is_start {
-                        // From the position to the end (dst_i) of this road
-                        1.0 - position.percent_along
-                    } else {
-                        // From the position to the start (src_i) of this road
-                        position.percent_along
-                    };
-                    let extra_cost = self.cost_for_road(road, percent_of_length);
+        let start_nodes = self.position_nodes(router_input, start, true)?;
+        let end_nodes = self.position_nodes(router_input, end, false)?;
+        let mut steps = self.calc_steps(start_nodes, end_nodes)?;
 
-                    nodes.push((node, extra_cost));
-                }
-            }
+        // If we started or ended on a filtered road, we need to insert the step for that
+        if start.road != steps[0].0 {
+            let (first, _) = router_input
+                .blocking_filter_range(start.road)
+                .expect("start road must have a filter");
+            steps.insert(
+                0,
+                (start.road, Direction::forwards(start.percent_along > first)),
+            );
+        }
+        if end.road != steps.last().unwrap().0 {
+            let (first, _) = router_input
+                .blocking_filter_range(end.road)
+                .expect("end road must have a filter");
+            steps.push((end.road, Direction::forwards(end.percent_along <= first)));
+        }
+
+        Some(Route { steps, start, end })
+    }
+
+    /// Routes from the beginning of whichever of `starts` is quickest to `end`. Each start is a
+    /// road and the direction to travel along it.
+    pub fn route_from_any(
+        &self,
+        router_input: &impl RouterInput,
+        starts: &[(RoadID, Direction)],
+        end: Position,
+    ) -> Option<Route> {
+        let start_nodes = starts
+            .iter()
+            .flat_map(|step| self.nodes_for(*step, true))
+            .map(|node| (node, 0))
+            .collect();
+        let end_nodes = self.position_nodes(router_input, end, false)?;
+        let mut steps = self.calc_steps(start_nodes, end_nodes)?;
+
+        let (first_road, first_direction) = steps[0];
+        let start = Position {
+            road: first_road,
+            percent_along: if first_direction == Direction::Forwards {
+                0.0
+            } else {
+                1.0
+            },
+        };
+        if end.road != steps.last().unwrap().0 {
+            let (first, _) = router_input
+                .blocking_filter_range(end.road)
+                .expect("end road must have a filter");
+            steps.push((end.road, Direction::forwards(end.percent_along <= first)));
+        }
 
-            // If the start or end road has a modal filter, then it won't be in the contraction
-            // hierarchy. Depending on the filter position, we can only travel in one direction.
-            // (If the position is EXACTLY on the filter position, arbitrarily pick one side)
-            if let Some(filter) = router_input.modal_filter(position.road) {
-                debug_assert!(nodes.is_empty(), "a road with a filter is in the CH");
+        Some(Route { steps, start, end })
+    }
+
+    /// The nodes to start or end a search at a position, with the cost to reach them. None if the
+    /// position is between two filters.
+    fn position_nodes(
+        &self,
+        router_input: &impl RouterInput,
+        position: Position,
+        is_start: bool,
+    ) -> Option<Vec<(NodeId, usize)>> {
+        let mut nodes = Vec::new();
+        for direction in [Direction::Forwards, Direction::Backwards] {
+            for node in self.nodes_for((position.road, direction), is_start) {
+                // Calculate the cost of the first or last road, which usually doesn't use the
+                // entire length of the road.
+                // Note this extra cost gets double-counted -- the contraction hierachy edge
+                // weights count crossing the full start and end road. But this is OK; the sum
+                // cost is only used to pick the shortest path.
                 let road = router_input.get_r(position.road);
-                let (i, percent_of_length) = if position.percent_along <= filter.percent_along {
-                    (road.src_i, position.percent_along)
+                let percent_of_length = if (direction == Direction::Forwards) == is_start {
+                    // From the position to the end (dst_i) of this road
+                    1.0 - position.percent_along
                 } else {
-                    (road.dst_i, 1.0 - position.percent_along)
+                    // From the position to the start (src_i) of this road
+                    position.percent_along
                 };
-                let extra_cost = self.cost_for_road(road, percent_of_length);
+                let extra_cost = self.cost_for_road(router_input, road, percent_of_length);
 
-                if is_start {
-                    for outgoing_road in router_input
-                        .get_i(i)
-                        .allowed_movements_from(position.road, router_input)
-                    {
-                        if let Some(node) = self.node_map.get(outgoing_road) {
-                            nodes.push((node, extra_cost));
-                        }
+                nodes.push((node, extra_cost));
+            }
+        }
+
+        // If the start or end road has a modal filter, then it won't be in the contraction
+        // hierarchy. Depending on the filter positions, we can only travel in one direction,
+        // or not at all when the position is between two filters. (If the position is EXACTLY
+        // on a filter position, arbitrarily pick one side)
+        if let Some((first, last)) = router_input.blocking_filter_range(position.road) {
+            debug_assert!(nodes.is_empty(), "a road with a filter is in the CH");
+            let road = router_input.get_r(position.road);
+            let (i, percent_of_length) = if position.percent_along <= first {
+                (road.src_i, position.percent_along)
+            } else if position.percent_along > last {
+                (road.dst_i, 1.0 - position.percent_along)
+            } else {
+                return None;
+            };
+            let extra_cost = self.cost_for_road(router_input, road, percent_of_length);
+
+            if is_start {
+                for outgoing_road in router_input
+                    .get_i(i)
+                    .allowed_movements_from(position.road, router_input)
+                {
+                    for node in self.nodes_for(outgoing_road, is_start) {
+                        nodes.push((node, extra_cost));
                     }
-                } else {
-                    for incoming_road in router_input
-                        .get_i(i)
-                        .allowed_movements_to(position.road, router_input)
-                    {
-                        if let Some(node) = self.node_map.get(incoming_road) {
-                            nodes.push((node, extra_cost));
-                        }
+                }
+            } else {
+                for incoming_road in router_input
+                    .get_i(i)
+                    .allowed_movements_to(position.road, router_input)
+                {
+                    for node in self.nodes_for(incoming_road, is_start) {
+                        nodes.push((node, extra_cost));
                     }
                 }
             }
         }
 
+        Some(nodes)
+    }
+
+    fn calc_steps(
+        &self,
+        start_nodes: Vec<(NodeId, usize)>,
+        end_nodes: Vec<(NodeId, usize)>,
+    ) -> Option<Vec<(RoadID, Direction)>> {
         if start_nodes.is_empty() || end_nodes.is_empty() {
             return None;
         }
@@ -293,34 +553,10 @@ impl Router {
 
         let mut steps = Vec::new();
         for node in shortest_path.get_nodes() {
-            let (road, direction) = self.node_map.translate_id(*node);
+            let (road, direction, _) = self.node_map.translate_id(*node);
             steps.push((road, direction));
         }
-
-        // If we started or ended on a filtered road, we need to insert the step for that
-        if start.road != steps[0].0 {
-            let filter = router_input
-                .modal_filter(start.road)
-                .expect("start road must have a filter");
-            steps.insert(
-                0,
-                (
-                    start.road,
-                    Direction::forwards(start.percent_along > filter.percent_along),
-                ),
-            );
-        }
-        if end.road != steps.last().unwrap().0 {
-            let filter = router_input
-                .modal_filter(end.road)
-                .expect("end road must have a filter");
-            steps.push((
-                end.road,
-                Direction::forwards(end.percent_along <= filter.percent_along),
-            ));
-        }
-
-        Some(Route { steps, start, end })
+        Some(steps)
     }
 
     /// Produce routes for all the requests and count how many routes cross each road
@@ -340,16 +576,46 @@ impl Router {
         results
     }
 
-    fn cost_for_road(&self, road: &Road, percent_of_length: f64) -> usize {
-        let penalty = if road.is_severance() {
-            self.main_road_penalty
-        } else {
-            1.0
-        };
-        (penalty * percent_of_length * road.cost_seconds() * 100.0) as usize
+    fn cost_for_road(
+        &self,
+        router_input: &impl RouterInput,
+        road: &Road,
+        percent_of_length: f64,
+    ) -> usize {
+        road_cost(
+            road,
+            router_input.speed_mph(road.id),
+            self.main_road_penalty,
+            percent_of_length,
+        )
+    }
+
+    /// The nodes for travelling along a road in one direction. A route can end partway through a
+    /// via-way restriction, so the end may be any copy of the node.
+    fn nodes_for(&self, step: (RoadID, Direction), is_start: bool) -> Vec<NodeId> {
+        let mut nodes: Vec<NodeId> = self
+            .node_map
+            .get((step.0, step.1, None))
+            .into_iter()
+            .collect();
+        if !is_start {
+            if let Some(copies) = self.via_way_copies.get(&step) {
+                nodes.extend(copies);
+            }
+        }
+        nodes
     }
 }
 
+fn road_cost(road: &Road, speed_mph: f64, main_road_penalty: f64, percent_of_length: f64) -> usize {
+    let penalty = if road.is_severance() {
+        main_road_penalty
+    } else {
+        1.0
+    };
+    (penalty * percent_of_length * road.cost_seconds(speed_mph) * 100.0) as usize
+}
+
 impl Route {
     pub fn to_linestring(&self, map: &MapModel) -> LineString {
         let mut pts = Vec::new();
@@ -368,11 +634,11 @@ impl Route {
     }
 
     /// Returns (meters, seconds)
-    pub fn get_distance_and_time(&self, map: &MapModel) -> (f64, f64) {
+    pub fn get_distance_and_time(&self, router_input: &impl RouterInput) -> (f64, f64) {
         let mut distance = 0.0;
         let mut time = 0.0;
         for (pos, (r, dir)) in self.steps.iter().with_position() {
-            let road = &map.roads[r.0];
+            let road = router_input.get_r(*r);
 
             let percent_of_length = match pos {
                 itertools::Position::Only => {
@@ -395,7 +661,7 @@ impl Route {
                 }
             };
             distance += percent_of_length * Euclidean.length(&road.linestring);
-            time += percent_of_length * road.cost_seconds();
+            time += percent_of_length * road.cost_seconds(router_input.speed_mph(*r));
         }
         (distance, time)
     }
@@ -455,6 +721,7 @@ impl Route {
 mod tests {
     use super::*;
     use crate::{osm_tests::load_osm_xml, FilterKind};
+    use geo::InterpolatableLine;
 
     fn r(road_id: usize) -> RoadID {
         RoadID(road_id)
@@ -577,4 +844,94 @@ mod tests {
         );
         assert!(left_turn_path.is_none());
     }
+
+    #[test]
+    fn speed_limit_route() {
+        let mut map = load_osm_xml("simple_four_way_intersection");
+        let route_time = |map: &mut MapModel| {
+            map.rebuild_router(1.0);
+            let router_input = map.router_input_after();
+            map.router_after
+                .as_ref()
+                .unwrap()
+                .route_from_roads(&router_input, r(3), r(2))
+                .unwrap()
+                .get_distance_and_time(&router_input)
+                .1
+        };
+        let original_time = route_time(&mut map);
+
+        // Halving the speed limit on both roads doubles the time
+        let original_speed = map.get_r(r(3)).speed_mph;
+        map.set_speed_limit(r(3), original_speed / 2.0).unwrap();
+        map.set_speed_limit(r(2), original_speed / 2.0).unwrap();
+        approx::assert_relative_eq!(route_time(&mut map), 2.0 * original_time);
+
+        map.undo().unwrap();
+        map.undo().unwrap();
+        approx::assert_relative_eq!(route_time(&mut map), original_time);
+        map.redo().unwrap();
+        map.redo().unwrap();
+
+        // The edits survive a savefile round-trip
+        let savefile = map.to_savefile();
+        map.load_savefile(savefile, false).unwrap();
+        assert_eq!(map.speed_limits[&r(3)], original_speed / 2.0);
+        approx::assert_relative_eq!(route_time(&mut map), 2.0 * original_time);
+    }
+
+    #[test]
+    fn multiple_filters_route() {
+        // Same topology as basic_route. Filter both ends of r2, like a school street.
+        let mut map = load_osm_xml("simple_four_way_intersection");
+        for percent in [0.25, 0.75] {
+            let pt = map
+                .get_r(r(2))
+                .linestring
+                .point_at_ratio_from_start(&Euclidean, percent)
+                .unwrap();
+            map.add_modal_filter(pt.into(), Some(vec![r(2)]), FilterKind::NoEntry);
+        }
+        assert_eq!(map.modal_filters[&r(2)].len(), 2);
+
+        map.rebuild_router(1.0);
+        let router_input = map.router_input_after();
+        let router = map.router_after.as_ref().unwrap();
+        let pos = |percent_along| Position {
+            road: r(2),
+            percent_along,
+        };
+        // Between the filters, only the same stretch is reachable
+        assert!(router
+            .route_from_positions(&router_input, pos(0.4), pos(0.6))
+            .is_some());
+        assert!(router
+            .route_from_positions(&router_input, pos(0.5), pos(0.9))
+            .is_none());
+        assert!(router
+            .route_from_positions(&router_input, pos(0.1), pos(0.9))
+            .is_none());
+        // Past the last filter, the road still connects to i1
+        let end = Position {
+            road: r(3),
+            percent_along: 0.5,
+        };
+        let Route { steps, .. } = router
+            .route_from_positions(&router_input, pos(0.9), end)
+            .unwrap();
+        assert_eq!(
+            steps,
+            vec![(r(2), Direction::Forwards), (r(3), Direction::Forwards)]
+        );
+
+        // Undo only removes the second filter
+        map.undo().unwrap();
+        assert_eq!(map.modal_filters[&r(2)].len(), 1);
+        map.redo().unwrap();
+
+        // Both filters survive a savefile round-trip
+        let savefile = map.to_savefile();
+        map.load_savefile(savefile, false).unwrap();
+        assert_eq!(map.modal_filters[&r(2)].len(), 2);
+    }
 }
@@ -0,0 +1,460 @@
+use std::fmt;
+
+use anyhow::Result;
+use geo::{Coord, Distance, Euclidean, Length, LineLocatePoint, LineString, Point};
+use geojson::{Feature, FeatureCollection};
+use rstar::RTreeObject;
+use utils::buffer_aabb;
+
+use crate::geo_helpers::{frechet_distance, linestring_follows};
+use crate::{IntersectionID, MapModel, RoadID};
+
+/// Within this distance, a saved feature confidently matches the current map
+const GOOD_MATCH_M: f64 = 5.0;
+/// Beyond this distance, a saved feature doesn't match anything
+const MAX_MATCH_M: f64 = 20.0;
+/// When a saved point is about as close to two roads, it could belong to either
+const AMBIGUOUS_M: f64 = 1.0;
+/// Resolution for comparing road shapes
+const FRECHET_STEP_M: f64 = 5.0;
+
+/// Savefiles refer to roads and intersections by geometry, because IDs change when the basemap is
+/// regenerated from newer OSM data. This is how well a saved feature matched the current map.
+pub enum Match<T> {
+    Good(T),
+    /// Probably right, but the user should check, for the reason given
+    LowConfidence(T, String),
+    /// Nothing is close enough, so the edit shouldn't be applied anywhere
+    NotFound(String),
+}
+
+impl<T> Match<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Match<U> {
+        match self {
+            Match::Good(x) => Match::Good(f(x)),
+            Match::LowConfidence(x, reason) => Match::LowConfidence(f(x), reason),
+            Match::NotFound(reason) => Match::NotFound(reason),
+        }
+    }
+
+    /// Like `map`, for conversions that can fail
+    pub fn try_map<U>(self, f: impl FnOnce(T) -> Result<U>) -> Result<Match<U>> {
+        Ok(match self {
+            Match::Good(x) => Match::Good(f(x)?),
+            Match::LowConfidence(x, reason) => Match::LowConfidence(f(x)?, reason),
+            Match::NotFound(reason) => Match::NotFound(reason),
+        })
+    }
+
+    /// Matches something else using this result, keeping the reasons from both
+    pub fn and_then<U>(self, f: impl FnOnce(T) -> Match<U>) -> Match<U> {
+        match self {
+            Match::Good(x) => f(x),
+            Match::LowConfidence(x, reason) => match f(x) {
+                Match::Good(y) => Match::LowConfidence(y, reason),
+                Match::LowConfidence(y, reason2) => {
+                    Match::LowConfidence(y, format!("{reason}; {reason2}"))
+                }
+                Match::NotFound(reason2) => Match::NotFound(reason2),
+            },
+            Match::NotFound(reason) => Match::NotFound(reason),
+        }
+    }
+}
+
+/// A savefile feature that's malformed, such as a hand-edited file missing a property
+#[derive(Debug)]
+pub struct InvalidFeature {
+    /// The position in the savefile's list of features
+    pub index: usize,
+    /// None if the feature doesn't have a string `kind`
+    pub kind: Option<String>,
+    pub reason: String,
+}
+
+impl InvalidFeature {
+    pub fn new(index: usize, f: &Feature, err: anyhow::Error) -> Self {
+        Self {
+            index,
+            kind: f
+                .property("kind")
+                .and_then(|x| x.as_str())
+                .map(|x| x.to_string()),
+            reason: format!("{err:#}"),
+        }
+    }
+}
+
+impl fmt::Display for InvalidFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Savefile feature {} ({}) is invalid: {}",
+            self.index,
+            self.kind.as_deref().unwrap_or("unknown kind"),
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for InvalidFeature {}
+
+/// Saved features that matched the current map with low confidence or not at all, or that were
+/// skipped for being invalid
+#[derive(Default)]
+pub struct MatchReport {
+    features: Vec<Feature>,
+    num_low_confidence: usize,
+    num_dropped: usize,
+    num_invalid: usize,
+}
+
+impl MatchReport {
+    /// Returns the matched value, remembering the saved feature if the match was poor
+    pub fn check<T>(&mut self, index: usize, f: &Feature, result: Match<T>) -> Option<T> {
+        let (value, mismatch, reason) = match result {
+            Match::Good(x) => return Some(x),
+            Match::LowConfidence(x, reason) => {
+                self.num_low_confidence += 1;
+                (Some(x), "low_confidence", reason)
+            }
+            Match::NotFound(reason) => {
+                self.num_dropped += 1;
+                (None, "dropped", reason)
+            }
+        };
+        warn!("Savefile feature {index} {mismatch}: {reason}");
+        self.add(index, f, mismatch, reason);
+        value
+    }
+
+    /// Remembers a feature that couldn't be loaded at all
+    pub fn skip_invalid(&mut self, f: &Feature, invalid: InvalidFeature) {
+        warn!("Skipping: {invalid}");
+        self.num_invalid += 1;
+        self.add(invalid.index, f, "invalid", invalid.reason);
+    }
+
+    fn add(&mut self, index: usize, f: &Feature, mismatch: &str, reason: String) {
+        let mut f = f.clone();
+        f.set_property("feature_index", index);
+        f.set_property("mismatch", mismatch);
+        f.set_property("reason", reason);
+        self.features.push(f);
+    }
+
+    /// The saved features, each with a `mismatch` property of `low_confidence`, `dropped`, or
+    /// `invalid`, a `reason`, and the `feature_index` in the savefile. Has `num_low_confidence`,
+    /// `num_dropped`, and `num_invalid` foreign members.
+    pub fn to_gj(self) -> FeatureCollection {
+        FeatureCollection {
+            features: self.features,
+            bbox: None,
+            foreign_members: Some(
+                serde_json::json!({
+                    "num_low_confidence": self.num_low_confidence,
+                    "num_dropped": self.num_dropped,
+                    "num_invalid": self.num_invalid,
+                })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ),
+        }
+    }
+}
+
+impl MapModel {
+    /// Matches a saved road to the current roads, also returning if each road points the same way
+    /// as the saved line. If newer OSM data splits the road, all of the pieces match.
+    pub fn match_road(&self, saved: &LineString) -> Match<Vec<(RoadID, bool)>> {
+        if saved.0.len() < 2 {
+            return Match::NotFound("The saved road has no length".to_string());
+        }
+        let saved_length = Euclidean.length(saved);
+        let bbox = buffer_aabb(saved.envelope(), MAX_MATCH_M);
+        let candidates: Vec<RoadID> = self
+            .closest_road
+            .locate_in_envelope_intersecting(&bbox)
+            .map(|obj| obj.data)
+            .collect();
+
+        // The road with the most similar shape
+        let mut best: Option<(f64, RoadID, bool)> = None;
+        for r in &candidates {
+            for same_direction in [true, false] {
+                let mut linestring = self.get_r(*r).linestring.clone();
+                if !same_direction {
+                    linestring.0.reverse();
+                }
+                // The distance between endpoints is a much cheaper lower bound
+                let endpoints = Euclidean.distance(linestring.0[0], saved.0[0]).max(
+                    Euclidean.distance(*linestring.0.last().unwrap(), *saved.0.last().unwrap()),
+                );
+                if endpoints > MAX_MATCH_M || best.is_some_and(|(dist, _, _)| endpoints >= dist) {
+                    continue;
+                }
+                let dist = frechet_distance(saved, &linestring, FRECHET_STEP_M);
+                if best.is_none_or(|(best_dist, _, _)| dist < best_dist) {
+                    best = Some((dist, *r, same_direction));
+                }
+            }
+        }
+        if let Some((dist, r, same_direction)) = best {
+            if dist <= GOOD_MATCH_M {
+                return Match::Good(vec![(r, same_direction)]);
+            }
+        }
+
+        // The road was split into pieces that cover the saved line
+        let pieces: Vec<(RoadID, bool)> = candidates
+            .iter()
+            .filter_map(|r| {
+                linestring_follows(&self.get_r(*r).linestring, saved, GOOD_MATCH_M)
+                    .map(|same_direction| (*r, same_direction))
+            })
+            .collect();
+        let pieces_length: f64 = pieces
+            .iter()
+            .map(|(r, _)| Euclidean.length(&self.get_r(*r).linestring))
+            .sum();
+        if pieces.len() > 1
+            && (pieces_length - saved_length).abs() <= GOOD_MATCH_M * pieces.len() as f64
+        {
+            return Match::Good(pieces);
+        }
+
+        // The road was merged into a longer one, which the edit now entirely covers
+        if let Some((r, same_direction, length)) = candidates
+            .iter()
+            .filter_map(|r| {
+                let linestring = &self.get_r(*r).linestring;
+                let length = Euclidean.length(linestring);
+                if length <= saved_length {
+                    return None;
+                }
+                linestring_follows(saved, linestring, GOOD_MATCH_M)
+                    .map(|same_direction| (*r, same_direction, length))
+            })
+            .min_by_key(|(_, _, length)| (*length * 100.0) as usize)
+        {
+            return Match::LowConfidence(
+                vec![(r, same_direction)],
+                format!(
+                    "The road is now part of a longer one, so the edit covers {length:.0}m instead of {saved_length:.0}m"
+                ),
+            );
+        }
+
+        if let Some((dist, r, same_direction)) = best {
+            if dist <= MAX_MATCH_M {
+                return Match::LowConfidence(
+                    vec![(r, same_direction)],
+                    format!("The closest road has a different shape, up to {dist:.0}m away"),
+                );
+            }
+        }
+        Match::NotFound(format!("No road within {MAX_MATCH_M}m has a similar shape"))
+    }
+
+    /// Matches a saved point along a road, returning the road and the percent along it
+    pub fn match_point_on_road(&self, pt: Coord) -> Match<(RoadID, f64)> {
+        let pt = Point(pt);
+        let mut nearest = self
+            .closest_road
+            .nearest_neighbor_iter(&pt)
+            .map(|obj| (obj.data, Euclidean.distance(&pt, obj.geom())));
+        let Some((r, dist)) = nearest.next() else {
+            return Match::NotFound("The map has no roads".to_string());
+        };
+        if dist > MAX_MATCH_M {
+            return Match::NotFound(format!("No road within {MAX_MATCH_M}m"));
+        }
+        let Some(percent_along) = self.get_r(r).linestring.line_locate_point(&pt) else {
+            return Match::NotFound(format!("Couldn't find the point along {r}"));
+        };
+
+        if dist > GOOD_MATCH_M {
+            return Match::LowConfidence(
+                (r, percent_along),
+                format!("The closest road is {dist:.0}m away"),
+            );
+        }
+        // Near a junction, another road might be just as close
+        if let Some((other, other_dist)) = nearest.next() {
+            if other_dist - dist < AMBIGUOUS_M {
+                return Match::LowConfidence(
+                    (r, percent_along),
+                    format!("{r} and {other} are about as close"),
+                );
+            }
+        }
+        Match::Good((r, percent_along))
+    }
+
+    pub fn match_intersection(&self, pt: Coord) -> Match<IntersectionID> {
+        let pt = Point(pt);
+        let Some(obj) = self.closest_intersection.nearest_neighbor(&pt) else {
+            return Match::NotFound("The map has no intersections".to_string());
+        };
+        let dist = Euclidean.distance(pt, *obj.geom());
+        if dist > MAX_MATCH_M {
+            Match::NotFound(format!("No intersection within {MAX_MATCH_M}m"))
+        } else if dist > GOOD_MATCH_M {
+            Match::LowConfidence(
+                obj.data,
+                format!("The closest intersection is {dist:.0}m away"),
+            )
+        } else {
+            Match::Good(obj.data)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_tests::load_osm_xml;
+    use geo::InterpolatableLine;
+    use geojson::{Geometry, Value};
+
+    #[test]
+    fn split_and_merged_roads() {
+        // See route.rs for the topology. r2 and r3 meet at i1 in a straight line.
+        let map = load_osm_xml("simple_four_way_intersection");
+        let r2 = &map.get_r(RoadID(2)).linestring;
+        let r3 = &map.get_r(RoadID(3)).linestring;
+
+        // Saved as one road, but the current map splits it
+        let mut joined = r2.0.clone();
+        joined.extend(r3.0.iter().skip(1));
+        let Match::Good(roads) = map.match_road(&LineString::new(joined)) else {
+            panic!("r2 and r3 should match");
+        };
+        assert_eq!(roads, vec![(RoadID(2), true), (RoadID(3), true)]);
+
+        // Saved as half of a road, but the current map merges it
+        let half = LineString::new(vec![
+            r3.0[0],
+            r3.point_at_ratio_from_start(&Euclidean, 0.5)
+                .unwrap()
+                .into(),
+        ]);
+        let Match::LowConfidence(roads, _) = map.match_road(&half) else {
+            panic!("half of r3 should match r3");
+        };
+        assert_eq!(roads, vec![(RoadID(3), true)]);
+
+        // Nothing nearby
+        let mut far_away = r3.clone();
+        for pt in &mut far_away.0 {
+            pt.y += 1000.0;
+        }
+        assert!(matches!(map.match_road(&far_away), Match::NotFound(_)));
+    }
+
+    #[test]
+    fn corrupted_savefiles() {
+        let mut map = load_osm_xml("simple_four_way_intersection");
+        map.set_speed_limit(RoadID(2), 10.0).unwrap();
+        map.set_speed_limit(RoadID(3), 10.0).unwrap();
+        let savefile = map.to_savefile();
+        let index = savefile
+            .features
+            .iter()
+            .position(|f| f.property("kind").and_then(|x| x.as_str()) == Some("speed_limit"))
+            .unwrap();
+        let num_edited = |map: &MapModel| {
+            [RoadID(2), RoadID(3)]
+                .into_iter()
+                .filter(|r| map.speed_limits[r] == 10.0)
+                .count()
+        };
+
+        let diagonal_pt = map
+            .mercator
+            .pt_to_wgs84(map.get_i(IntersectionID(1)).point.into());
+        let cases: Vec<(&str, Option<&str>, Box<dyn Fn(&mut Feature)>)> = vec![
+            (
+                "missing kind",
+                None,
+                Box::new(|f: &mut Feature| {
+                    f.properties.as_mut().unwrap().remove("kind");
+                }),
+            ),
+            (
+                "non-string kind",
+                None,
+                Box::new(|f: &mut Feature| f.set_property("kind", 5)),
+            ),
+            (
+                "unknown kind",
+                Some("mystery"),
+                Box::new(|f: &mut Feature| f.set_property("kind", "mystery")),
+            ),
+            (
+                "missing geometry",
+                Some("speed_limit"),
+                Box::new(|f: &mut Feature| f.geometry = None),
+            ),
+            (
+                "missing property",
+                Some("speed_limit"),
+                Box::new(|f: &mut Feature| {
+                    f.properties.as_mut().unwrap().remove("speed_mph");
+                }),
+            ),
+            (
+                "negative speed",
+                Some("speed_limit"),
+                Box::new(|f: &mut Feature| f.set_property("speed_mph", -10.0)),
+            ),
+            (
+                "zero speed",
+                Some("speed_limit"),
+                Box::new(|f: &mut Feature| f.set_property("speed_mph", 0.0)),
+            ),
+            (
+                "diagonal filter missing is_rotated",
+                Some("modal_filter"),
+                Box::new(move |f: &mut Feature| {
+                    f.set_property("kind", "modal_filter");
+                    f.set_property("filter_kind", "diagonal_filter");
+                    f.geometry = Some(Geometry::new(Value::from(&Point(diagonal_pt))));
+                }),
+            ),
+        ];
+
+        for (name, kind, corrupt) in cases {
+            let mut corrupted = savefile.clone();
+            corrupt(&mut corrupted.features[index]);
+
+            // The whole load fails and the current edits stay
+            let err = map.load_savefile(corrupted.clone(), false).unwrap_err();
+            let invalid = err
+                .downcast_ref::<InvalidFeature>()
+                .unwrap_or_else(|| panic!("{name}: wrong error {err}"));
+            assert_eq!(invalid.index, index, "{name}");
+            assert_eq!(invalid.kind.as_deref(), kind, "{name}");
+            assert_eq!(num_edited(&map), 2, "{name}");
+
+            // Skipping the bad feature keeps the other edit
+            let report = map.load_savefile(corrupted, true).unwrap();
+            assert_eq!(num_edited(&map), 1, "{name}");
+            assert_eq!(report.features.len(), 1, "{name}");
+            let f = &report.features[0];
+            assert_eq!(
+                f.property("mismatch").and_then(|x| x.as_str()),
+                Some("invalid"),
+                "{name}"
+            );
+            assert_eq!(
+                f.property("feature_index").and_then(|x| x.as_u64()),
+                Some(index as u64),
+                "{name}"
+            );
+
+            map.load_savefile(savefile.clone(), false).unwrap();
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::Result;
+use geojson::FeatureCollection;
+
+use crate::scenarios::DEFAULT_SCENARIO;
+
+/// The layout of savefiles written by `to_savefile`, stored as the `savefile_version` foreign
+/// member. When changing the layout, bump this and add a migration from the previous version.
+pub const SAVEFILE_VERSION: u32 = 1;
+
+/// `MIGRATIONS[v]` upgrades a savefile from version `v` to `v + 1`
+const MIGRATIONS: [fn(&mut FeatureCollection); SAVEFILE_VERSION as usize] = [v0_to_v1];
+
+/// Upgrades a savefile in place to the current layout. Fails if it's from a newer version of the
+/// app.
+pub fn migrate_savefile(gj: &mut FeatureCollection) -> Result<()> {
+    let Some(json) = gj.foreign_members.as_ref() else {
+        bail!("Savefile is missing project details");
+    };
+    // Savefiles didn't have a version before migrations were added
+    let version = match json.get("savefile_version") {
+        Some(x) => {
+            let Some(version) = x.as_u64() else {
+                bail!("Savefile's savefile_version isn't a number");
+            };
+            version as u32
+        }
+        None => 0,
+    };
+    if version > SAVEFILE_VERSION {
+        bail!(
+            "This savefile is version {version}, but this version of the app only understands up \
+             to {SAVEFILE_VERSION}. Try reloading the page to get the latest version."
+        );
+    }
+
+    for v in version..SAVEFILE_VERSION {
+        info!("Upgrading savefile from version {v} to {}", v + 1);
+        MIGRATIONS[v as usize](gj);
+    }
+    gj.foreign_members
+        .as_mut()
+        .unwrap()
+        .insert("savefile_version".to_string(), SAVEFILE_VERSION.into());
+    Ok(())
+}
+
+/// Diagonal filters used to store the whole internal filter, which refers to roads by IDs that
+/// aren't stable across basemap updates; only `is_rotated` is needed. Savefiles from before
+/// scenarios have just the default one. Malformed features are left for `load_savefile` to
+/// report.
+fn v0_to_v1(gj: &mut FeatureCollection) {
+    for f in &mut gj.features {
+        if f.property("filter_kind").and_then(|x| x.as_str()) != Some("diagonal_filter") {
+            continue;
+        }
+        f.remove_property("intersection_id");
+        if let Some(filter) = f.remove_property("filter") {
+            if let Some(is_rotated) = filter.get("is_rotated") {
+                f.set_property("is_rotated", is_rotated.clone());
+            }
+        }
+    }
+
+    let json = gj.foreign_members.as_mut().unwrap();
+    if !json.contains_key("current_scenario") {
+        json.insert("current_scenario".to_string(), DEFAULT_SCENARIO.into());
+        json.insert("scenarios".to_string(), vec![DEFAULT_SCENARIO].into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_tests::load_osm_xml;
+    use crate::{IntersectionID, MapModel, RoadID, TravelFlow};
+    use geojson::Feature;
+
+    fn load_fixture(map: &mut MapModel, name: &str) -> FeatureCollection {
+        let path = format!(
+            "{}/src/savefile_fixtures/{name}.geojson",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let savefile: FeatureCollection = std::fs::read_to_string(path).unwrap().parse().unwrap();
+        map.load_savefile(savefile, false).unwrap()
+    }
+
+    fn edited_roads(map: &MapModel, edited: impl Fn(&MapModel, RoadID) -> bool) -> usize {
+        (0..4).filter(|r| edited(map, RoadID(*r))).count()
+    }
+
+    #[test]
+    fn v0_before_scenarios() {
+        let mut map = load_osm_xml("simple_four_way_intersection");
+        let report = load_fixture(&mut map, "v0_before_scenarios");
+        assert!(report.features.is_empty());
+
+        assert_eq!(map.current_scenario, DEFAULT_SCENARIO);
+        assert_eq!(map.modal_filters.len(), 1);
+        assert!(map.diagonal_filters[&IntersectionID(1)].is_rotated);
+        assert_eq!(
+            edited_roads(&map, |map, r| map.travel_flows[&r]
+                != TravelFlow::from_osm(&map.get_r(r).tags)),
+            1
+        );
+        assert_eq!(
+            edited_roads(&map, |map, r| map.is_main_road[&r]
+                != map.get_r(r).is_severance()),
+            1
+        );
+        assert_eq!(map.turn_restrictions[1].len(), 1);
+
+        // Saving again writes the current layout
+        let savefile = map.to_savefile();
+        let json = savefile.foreign_members.as_ref().unwrap();
+        assert_eq!(json["savefile_version"], SAVEFILE_VERSION);
+        let is_diagonal =
+            |f: &&Feature| f.property("filter_kind") == Some(&"diagonal_filter".into());
+        let diagonal = savefile.features.iter().find(is_diagonal).unwrap();
+        assert!(diagonal.property("filter").is_none());
+        assert_eq!(diagonal.property("is_rotated"), Some(&true.into()));
+    }
+
+    #[test]
+    fn current_version_round_trip() {
+        let mut map = load_osm_xml("simple_four_way_intersection");
+        map.new_scenario("Option B".to_string(), false).unwrap();
+        map.switch_scenario("Option B").unwrap();
+        map.add_diagonal_filter(IntersectionID(1));
+        map.set_speed_limit(RoadID(0), 10.0).unwrap();
+        let savefile = map.to_savefile();
+        let json = savefile.foreign_members.as_ref().unwrap();
+        assert_eq!(json["savefile_version"], SAVEFILE_VERSION);
+
+        let mut map = load_osm_xml("simple_four_way_intersection");
+        let report = map.load_savefile(savefile, false).unwrap();
+        assert!(report.features.is_empty());
+
+        assert_eq!(map.current_scenario, "Option B");
+        assert!(map.has_scenario(DEFAULT_SCENARIO));
+        assert!(map.diagonal_filters.contains_key(&IntersectionID(1)));
+        assert_eq!(
+            edited_roads(&map, |map, r| map.speed_limits[&r]
+                != map.get_r(r).speed_mph),
+            1
+        );
+        assert!(map.modal_filters.is_empty());
+    }
+
+    #[test]
+    fn newer_version() {
+        let mut map = load_osm_xml("simple_four_way_intersection");
+        let mut savefile = map.to_savefile();
+        savefile.foreign_members.as_mut().unwrap().insert(
+            "savefile_version".to_string(),
+            (SAVEFILE_VERSION + 1).into(),
+        );
+        assert!(migrate_savefile(&mut savefile.clone()).is_err());
+        assert!(map.load_savefile(savefile, false).is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use geo::Coord;
+use geojson::{FeatureCollection, GeoJson};
+
+use crate::annotations::{Annotation, Intervention};
+use crate::impact::counts_to_gj;
+use crate::map_model::{DiagonalFilter, EditHistory};
+use crate::{IntersectionID, MapModel, ModalFilter, RoadID, TravelFlow};
+
+/// The scenario every project starts with
+pub const DEFAULT_SCENARIO: &str = "Default";
+
+/// Everything a user can edit about the map. A scenario is one of these, as an alternative set of
+/// edits over the basemap.
+#[derive(Clone)]
+pub struct EditState {
+    /// Undo and redo history for each neighbourhood, while this isn't the current scenario.
+    /// Commands are relative to the edits of one scenario, so each keeps its own.
+    pub(crate) histories: BTreeMap<String, EditHistory>,
+    modal_filters: BTreeMap<RoadID, Vec<ModalFilter>>,
+    diagonal_filters: BTreeMap<IntersectionID, DiagonalFilter>,
+    turn_restrictions: Vec<Vec<(RoadID, RoadID)>>,
+    travel_flows: BTreeMap<RoadID, TravelFlow>,
+    is_main_road: BTreeMap<RoadID, bool>,
+    speed_limits: BTreeMap<RoadID, f64>,
+    annotations: BTreeMap<Intervention, Annotation>,
+}
+
+impl MapModel {
+    /// The state of the basemap, without any edits
+    pub fn original_edit_state(&self) -> EditState {
+        EditState {
+            histories: BTreeMap::new(),
+            modal_filters: self.original_modal_filters.clone(),
+            diagonal_filters: BTreeMap::new(),
+            turn_restrictions: self.original_turn_restrictions.clone(),
+            travel_flows: self
+                .roads
+                .iter()
+                .map(|r| (r.id, TravelFlow::from_osm(&r.tags)))
+                .collect(),
+            is_main_road: self
+                .roads
+                .iter()
+                .map(|r| (r.id, r.is_severance()))
+                .collect(),
+            speed_limits: self.roads.iter().map(|r| (r.id, r.speed_mph)).collect(),
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    pub fn current_edit_state(&self) -> EditState {
+        EditState {
+            histories: BTreeMap::new(),
+            modal_filters: self.modal_filters.clone(),
+            diagonal_filters: self.diagonal_filters.clone(),
+            turn_restrictions: self.turn_restrictions.clone(),
+            travel_flows: self.travel_flows.clone(),
+            is_main_road: self.is_main_road.clone(),
+            speed_limits: self.speed_limits.clone(),
+            annotations: self.annotations.clone(),
+        }
+    }
+
+    /// Exchange the map's current edits with `state`, but not the edit histories. This doesn't
+    /// invalidate anything derived from the edits; callers should use `after_edited` if needed.
+    pub fn swap_edit_state(&mut self, state: &mut EditState) {
+        std::mem::swap(&mut self.modal_filters, &mut state.modal_filters);
+        std::mem::swap(&mut self.diagonal_filters, &mut state.diagonal_filters);
+        std::mem::swap(&mut self.turn_restrictions, &mut state.turn_restrictions);
+        std::mem::swap(&mut self.travel_flows, &mut state.travel_flows);
+        std::mem::swap(&mut self.is_main_road, &mut state.is_main_road);
+        std::mem::swap(&mut self.speed_limits, &mut state.speed_limits);
+        std::mem::swap(&mut self.annotations, &mut state.annotations);
+    }
+
+    /// The current scenario first, then the others alphabetically
+    pub fn list_scenarios(&self) -> Vec<String> {
+        std::iter::once(self.current_scenario.clone())
+            .chain(self.scenarios.keys().cloned())
+            .collect()
+    }
+
+    /// Creates a scenario, either starting from the basemap or copying the current edits. Doesn't
+    /// switch to it.
+    pub fn new_scenario(&mut self, name: String, copy_current: bool) -> Result<()> {
+        if self.has_scenario(&name) {
+            bail!("There's already a scenario called {name}");
+        }
+        let state = if copy_current {
+            self.current_edit_state()
+        } else {
+            self.original_edit_state()
+        };
+        self.scenarios.insert(name, state);
+        Ok(())
+    }
+
+    /// Changes the current edits to another scenario. Each scenario keeps its own undo and redo
+    /// history.
+    pub fn switch_scenario(&mut self, name: &str) -> Result<()> {
+        if name == self.current_scenario {
+            return Ok(());
+        }
+        let Some(mut state) = self.scenarios.remove(name) else {
+            bail!("No scenario called {name}");
+        };
+        self.swap_edit_state(&mut state);
+        state.histories = self.swap_all_histories(std::mem::take(&mut state.histories));
+        let previous = std::mem::replace(&mut self.current_scenario, name.to_string());
+        self.scenarios.insert(previous, state);
+        self.after_edited();
+        Ok(())
+    }
+
+    pub fn rename_scenario(&mut self, old_name: &str, new_name: String) -> Result<()> {
+        if self.has_scenario(&new_name) {
+            bail!("There's already a scenario called {new_name}");
+        }
+        if old_name == self.current_scenario {
+            self.current_scenario = new_name;
+        } else {
+            let Some(state) = self.scenarios.remove(old_name) else {
+                bail!("No scenario called {old_name}");
+            };
+            self.scenarios.insert(new_name, state);
+        }
+        Ok(())
+    }
+
+    pub fn delete_scenario(&mut self, name: &str) -> Result<()> {
+        if name == self.current_scenario {
+            bail!("Can't delete the current scenario");
+        }
+        if self.scenarios.remove(name).is_none() {
+            bail!("No scenario called {name}");
+        }
+        Ok(())
+    }
+
+    /// Temporarily switch to another scenario and run the callback. The current scenario's edit
+    /// history is kept.
+    pub fn with_scenario<T, F: FnOnce(&mut MapModel) -> T>(
+        &mut self,
+        name: &str,
+        cb: F,
+    ) -> Result<T> {
+        if name == self.current_scenario {
+            return Ok(cb(self));
+        }
+        let Some(mut state) = self.scenarios.remove(name) else {
+            bail!("No scenario called {name}");
+        };
+        let history = EditHistory {
+            undo_stack: std::mem::take(&mut self.undo_stack),
+            redo_stack: std::mem::take(&mut self.redo_stack),
+        };
+
+        self.swap_edit_state(&mut state);
+        self.after_edited();
+        let output = cb(self);
+        self.swap_edit_state(&mut state);
+        self.after_edited();
+
+        self.scenarios.insert(name.to_string(), state);
+        self.undo_stack = history.undo_stack;
+        self.redo_stack = history.redo_stack;
+        Ok(output)
+    }
+
+    /// Returns GJ with a LineString for the route in each scenario, with `kind` set to `before`
+    /// for scenario `a` and `after` for `b`
+    pub fn compare_route_between_scenarios(
+        &mut self,
+        a: &str,
+        b: &str,
+        pt1: Coord,
+        pt2: Coord,
+        main_road_penalty: f64,
+    ) -> Result<GeoJson> {
+        let mut features = Vec::new();
+        for (name, kind) in [(a, "before"), (b, "after")] {
+            let route = self.with_scenario(name, |map| {
+                map.rebuild_router(main_road_penalty);
+                let route = map.router_after.as_ref().unwrap().route_from_points(
+                    &map.router_input_after(),
+                    pt1,
+                    pt2,
+                )?;
+                let (distance, time) = route.get_distance_and_time(&map.router_input_after());
+                Some((route.to_linestring(map), distance, time))
+            })?;
+            if let Some((linestring, distance, time)) = route {
+                let mut f = self.mercator.to_wgs84_gj(&linestring);
+                f.set_property("kind", kind);
+                f.set_property("scenario", name);
+                f.set_property("distance", distance);
+                f.set_property("time", time);
+                features.push(f);
+            }
+        }
+        Ok(GeoJson::from(features))
+    }
+
+    /// Like `Impact::recalculate`, but comparing two scenarios. `before` counts are from `a`, and
+    /// `after` from `b`.
+    pub fn predict_impact_between_scenarios(
+        &mut self,
+        a: &str,
+        b: &str,
+        fast_sample: bool,
+    ) -> Result<FeatureCollection> {
+        let mut impact = self.impact.take().unwrap();
+        let mut counts = Vec::new();
+        for name in [a, b] {
+            let result = self.with_scenario(name, |map| {
+                map.rebuild_router(1.0);
+                impact.counts_after_edits(map, fast_sample)
+            });
+            match result {
+                Ok(x) => counts.push(x),
+                Err(err) => {
+                    self.impact = Some(impact);
+                    return Err(err);
+                }
+            }
+        }
+        self.impact = Some(impact);
+        Ok(counts_to_gj(self, &counts[0], &counts[1]))
+    }
+
+    pub fn has_scenario(&self, name: &str) -> bool {
+        name == self.current_scenario || self.scenarios.contains_key(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_model::Command;
+    use crate::osm_tests::{get_road_by_name, load_osm_xml};
+    use crate::FilterKind;
+
+    fn filter() -> Vec<ModalFilter> {
+        vec![ModalFilter {
+            kind: FilterKind::WalkCycleOnly,
+            percent_along: 0.5,
+        }]
+    }
+
+    #[test]
+    fn with_scenario_keeps_current_edits_and_history() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let r = get_road_by_name(&map, "west cross");
+        map.new_scenario("filtered".to_string(), false).unwrap();
+        map.switch_scenario("filtered").unwrap();
+        map.apply_command(Command::SetModalFilters(r, filter()));
+        map.switch_scenario(DEFAULT_SCENARIO).unwrap();
+
+        map.switch_history("west");
+        map.set_speed_limit(r, 10.0).unwrap();
+        let has_filter = map
+            .with_scenario("filtered", |map| map.modal_filters.contains_key(&r))
+            .unwrap();
+        assert!(has_filter);
+        assert!(!map.modal_filters.contains_key(&r));
+        assert_eq!(map.speed_limits[&r], 10.0);
+        assert_eq!(map.undo_stack.len(), 1);
+        assert!(map.with_scenario("missing", |_| ()).is_err());
+    }
+
+    #[test]
+    fn switch_scenario_keeps_each_history() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let r = get_road_by_name(&map, "west cross");
+        let original_speed = map.speed_limits[&r];
+        map.switch_history("west");
+        map.set_speed_limit(r, 10.0).unwrap();
+
+        map.new_scenario("other".to_string(), false).unwrap();
+        map.switch_scenario("other").unwrap();
+        assert!(map.undo_stack.is_empty());
+        assert_eq!(map.speed_limits[&r], original_speed);
+        map.set_speed_limit(r, 20.0).unwrap();
+        map.set_speed_limit(r, 30.0).unwrap();
+        map.undo().unwrap();
+
+        map.switch_scenario(DEFAULT_SCENARIO).unwrap();
+        assert_eq!(map.undo_stack.len(), 1);
+        map.undo().unwrap();
+        assert_eq!(map.speed_limits[&r], original_speed);
+
+        map.switch_scenario("other").unwrap();
+        assert_eq!(map.speed_limits[&r], 20.0);
+        assert_eq!((map.undo_stack.len(), map.redo_stack.len()), (1, 1));
+        map.redo().unwrap();
+        assert_eq!(map.speed_limits[&r], 30.0);
+    }
+
+    #[test]
+    fn scenarios_survive_savefile_round_trip() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        map.osm_timestamp = Some(123);
+        let r = get_road_by_name(&map, "west cross");
+        map.switch_history("west");
+        map.set_speed_limit(r, 10.0).unwrap();
+        map.new_scenario("filtered".to_string(), false).unwrap();
+        map.new_scenario("empty".to_string(), false).unwrap();
+        map.switch_scenario("filtered").unwrap();
+        map.apply_command(Command::SetModalFilters(r, filter()));
+
+        let savefile = map.to_savefile();
+        map.load_savefile(savefile, false).unwrap();
+        assert_eq!(
+            map.list_scenarios(),
+            vec!["filtered", DEFAULT_SCENARIO, "empty"]
+        );
+        assert!(map.modal_filters.contains_key(&r));
+
+        // Each scenario's edits and histories come back
+        map.switch_history("west");
+        assert_eq!(map.undo_stack.len(), 1);
+        map.switch_scenario(DEFAULT_SCENARIO).unwrap();
+        assert!(!map.modal_filters.contains_key(&r));
+        assert_eq!(map.speed_limits[&r], 10.0);
+        map.undo().unwrap();
+        assert_eq!(map.speed_limits[&r], map.get_r(r).speed_mph);
+        map.switch_scenario("empty").unwrap();
+        assert!(map.undo_stack.is_empty());
+    }
+
+    #[test]
+    fn predict_impact_between_scenarios() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let r = get_road_by_name(&map, "middle");
+        map.new_scenario("filtered".to_string(), true).unwrap();
+        map.switch_scenario("filtered").unwrap();
+        map.apply_command(Command::SetModalFilters(r, filter()));
+        map.switch_scenario(DEFAULT_SCENARIO).unwrap();
+
+        let same = map
+            .predict_impact_between_scenarios(DEFAULT_SCENARIO, DEFAULT_SCENARIO, false)
+            .unwrap();
+        assert!(same.features.is_empty());
+
+        let diff = map
+            .predict_impact_between_scenarios(DEFAULT_SCENARIO, "filtered", false)
+            .unwrap();
+        assert!(!diff.features.is_empty());
+        assert!(map.impact.is_some());
+        assert!(!map.modal_filters.contains_key(&r));
+
+        assert!(map
+            .predict_impact_between_scenarios(DEFAULT_SCENARIO, "missing", false)
+            .is_err());
+        assert!(map.impact.is_some());
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use geo::{Euclidean, Length};
+use serde::Serialize;
+
+use crate::boundary_stats::BoundaryStats;
+use crate::render_cells::Color;
+use crate::{FilterKind, MapModel, Neighbourhood, RoadID};
+
+/// A summary of a neighbourhood's design, meant for comparing neighbourhoods against each other
+/// and before/after edits.
+#[derive(Serialize)]
+pub struct Scorecard {
+    pub num_cells: usize,
+    pub num_disconnected_cells: usize,
+    pub num_pedestrianized_cells: usize,
+    pub num_shortcuts: usize,
+    /// The most shortcuts crossing any one interior road
+    pub worst_shortcuts_per_road: usize,
+    /// New modal filters on the neighbourhood's roads and diagonal filters inside it, keyed by
+    /// `FilterKind::to_string`. Filters already in the basemap aren't counted.
+    pub filters_per_kind: BTreeMap<&'static str, usize>,
+    pub interior_road_length_meters: f64,
+    pub num_border_entries: usize,
+    pub boundary_stats: BoundaryStats,
+}
+
+impl Neighbourhood {
+    pub fn scorecard(&self, map: &MapModel) -> Scorecard {
+        let derived = self
+            .derived
+            .as_ref()
+            .expect("neighbourhood has no derived state yet");
+
+        let colors = &derived.render_cells.colors;
+        let num_disconnected_cells = colors
+            .iter()
+            .filter(|c| matches!(c, Color::Disconnected))
+            .count();
+        let num_pedestrianized_cells = colors
+            .iter()
+            .filter(|c| matches!(c, Color::Pedestrianized))
+            .count();
+
+        let mut filters_per_kind = BTreeMap::new();
+        let editable_roads: BTreeSet<RoadID> = self.editable_roads().into_iter().collect();
+        for (r, filter) in map.new_modal_filters() {
+            if editable_roads.contains(&r) {
+                *filters_per_kind.entry(filter.kind.to_string()).or_insert(0) += 1;
+            }
+        }
+        for i in &self.editable_intersections {
+            if map.diagonal_filters.contains_key(i) {
+                *filters_per_kind
+                    .entry(FilterKind::DiagonalFilter.to_string())
+                    .or_insert(0) += 1;
+            }
+        }
+
+        Scorecard {
+            num_cells: colors.len(),
+            num_disconnected_cells,
+            num_pedestrianized_cells,
+            num_shortcuts: derived.shortcuts.paths.len(),
+            worst_shortcuts_per_road: derived
+                .shortcuts
+                .count_per_road
+                .values()
+                .max()
+                .cloned()
+                .unwrap_or(0),
+            filters_per_kind,
+            interior_road_length_meters: self
+                .interior_roads
+                .iter()
+                .map(|r| Euclidean.length(&map.get_r(*r).linestring))
+                .sum(),
+            num_border_entries: self.num_border_entries(map),
+            boundary_stats: self.boundary.boundary_stats.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_model::Command;
+    use crate::osm_tests::{
+        get_road_by_name, load_osm_xml, rectangle_neighbourhood, WEST_NEIGHBOURHOOD,
+    };
+    use crate::ModalFilter;
+
+    #[test]
+    fn basemap_filters_are_not_counted() {
+        let filter = |kind, percent_along| ModalFilter {
+            kind,
+            percent_along,
+        };
+        let mut map = load_osm_xml("two_neighbourhoods");
+        // Pretend the basemap already has a filter
+        let existing = get_road_by_name(&map, "west 1");
+        map.original_modal_filters
+            .insert(existing, vec![filter(FilterKind::NoEntry, 0.5)]);
+        map.modal_filters
+            .insert(existing, vec![filter(FilterKind::NoEntry, 0.5)]);
+
+        let mut neighbourhood = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        assert!(neighbourhood.scorecard(&map).filters_per_kind.is_empty());
+
+        map.apply_command(Command::SetModalFilters(
+            existing,
+            vec![
+                filter(FilterKind::NoEntry, 0.5),
+                filter(FilterKind::BusGate, 0.8),
+            ],
+        ));
+        map.apply_command(Command::SetModalFilters(
+            get_road_by_name(&map, "west cross"),
+            vec![filter(FilterKind::WalkCycleOnly, 0.5)],
+        ));
+        neighbourhood.after_edit(&map);
+        assert_eq!(
+            neighbourhood.scorecard(&map).filters_per_kind,
+            BTreeMap::from([("bus_gate", 1), ("walk_cycle_only", 1)])
+        );
+    }
+}
@@ -1,20 +1,26 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 
 use crate::map_model::Direction;
 use crate::route::Router;
 use crate::{Cell, MapModel, Neighbourhood, RoadID};
 use geo::{Euclidean, Length, LineString};
-use geojson::Feature;
+use geojson::{Feature, FeatureCollection};
 
 pub struct Shortcuts {
     pub paths: Vec<Path>,
     pub count_per_road: HashMap<RoadID, usize>,
+    /// Only calculated in demand-weighted mode. The estimated number of trips per interior road
+    /// that would use a shortcut.
+    pub through_traffic_per_road: Option<HashMap<RoadID, usize>>,
 }
 
 pub struct Path {
     // TODO: dedupe - make this `Route`
     steps: Vec<(RoadID, Direction)>,
     directness: f64,
+    /// Only calculated in demand-weighted mode. How many trips entered and exited the
+    /// neighbourhood at the same main roads as this shortcut, before any edits?
+    through_traffic: Option<usize>,
 }
 
 impl Shortcuts {
@@ -26,6 +32,11 @@ impl Shortcuts {
 
         let mut paths = Vec::new();
         let mut count_per_road = HashMap::new();
+        let through_traffic_pairs = neighbourhood.through_traffic_pairs.as_ref();
+        let mut through_traffic_per_road = through_traffic_pairs.map(|_| HashMap::new());
+        // A main road can touch several border intersections of a cell, so the same pair of main
+        // roads can be found more than once. Only count its trips once.
+        let mut pairs_with_through_traffic = HashSet::new();
 
         // A shortcut is contained within a cell, because cells can't cross modal filters or main
         // roads, and neither can shortcuts. Only looking for candidate shortcuts in each cell is
@@ -99,11 +110,23 @@ impl Shortcuts {
                                 shortcut_roads.push(*r);
                             }
 
+                            let through_traffic = through_traffic_pairs
+                                .map(|pairs| pairs.get(&(*start_r, *end_r)).cloned().unwrap_or(0));
+                            let add_through_traffic =
+                                pairs_with_through_traffic.insert((*start_r, *end_r));
+
                             // Only increase count_per_road after verifying above that the shortcut
                             // is indeed valid and doesn't cross any main roads
                             for r in shortcut_roads {
                                 let road = map.get_r(r);
                                 *count_per_road.entry(road.id).or_insert(0) += 1;
+                                if let (Some(per_road), Some(trips), true) = (
+                                    through_traffic_per_road.as_mut(),
+                                    through_traffic,
+                                    add_through_traffic,
+                                ) {
+                                    *per_road.entry(road.id).or_insert(0) += trips;
+                                }
                                 shortcut_length += Euclidean.length(&road.linestring);
                             }
 
@@ -124,6 +147,7 @@ impl Shortcuts {
                             paths.push(Path {
                                 steps: route.steps,
                                 directness,
+                                through_traffic,
                             });
                         }
                     }
@@ -136,6 +160,7 @@ impl Shortcuts {
         Self {
             paths,
             count_per_road,
+            through_traffic_per_road,
         }
     }
 
@@ -145,9 +170,96 @@ impl Shortcuts {
             .filter(|path| path.steps.iter().any(|(r, _)| *r == crosses))
             .collect()
     }
+
+    /// Compares shortcuts before edits (`before`) with `self`, after edits. Paths are matched by
+    /// the steps where they enter and exit the neighbourhood, and are reported as `eliminated`,
+    /// `remaining` or `new`. Every road used by a shortcut before or after is also reported with
+    /// its count from both times.
+    pub fn diff_to_gj(&self, before: &Shortcuts, map: &MapModel) -> FeatureCollection {
+        // Several shortcuts can share an entry and exit. Pair them up in order of directness.
+        let mut unmatched_before: BTreeMap<_, VecDeque<&Path>> = BTreeMap::new();
+        for path in &before.paths {
+            unmatched_before
+                .entry(path.entry_and_exit())
+                .or_default()
+                .push_back(path);
+        }
+
+        let mut after_features = Vec::new();
+        let mut num_remaining = 0;
+        let mut num_new = 0;
+        for path in &self.paths {
+            let mut f = path.to_gj(map);
+            f.set_property("kind", "shortcut");
+            if let Some(before_path) = unmatched_before
+                .get_mut(&path.entry_and_exit())
+                .and_then(|paths| paths.pop_front())
+            {
+                f.set_property("change", "remaining");
+                f.set_property("directness_before", before_path.directness);
+                num_remaining += 1;
+            } else {
+                f.set_property("change", "new");
+                num_new += 1;
+            }
+            after_features.push(f);
+        }
+
+        let mut features = Vec::new();
+        let mut num_eliminated = 0;
+        for path in unmatched_before.into_values().flatten() {
+            let mut f = path.to_gj(map);
+            f.set_property("kind", "shortcut");
+            f.set_property("change", "eliminated");
+            features.push(f);
+            num_eliminated += 1;
+        }
+        features.extend(after_features);
+
+        let roads: BTreeSet<RoadID> = before
+            .count_per_road
+            .keys()
+            .chain(self.count_per_road.keys())
+            .cloned()
+            .collect();
+        for r in roads {
+            let mut f = map.mercator.to_wgs84_gj(&map.get_r(r).linestring);
+            f.set_property("kind", "road");
+            f.set_property("id", r.0);
+            f.set_property(
+                "shortcuts_before",
+                before.count_per_road.get(&r).cloned().unwrap_or(0),
+            );
+            f.set_property(
+                "shortcuts_after",
+                self.count_per_road.get(&r).cloned().unwrap_or(0),
+            );
+            features.push(f);
+        }
+
+        FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: Some(
+                serde_json::json!({
+                    "num_eliminated": num_eliminated,
+                    "num_remaining": num_remaining,
+                    "num_new": num_new,
+                })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ),
+        }
+    }
 }
 
 impl Path {
+    /// The first and last steps, on the main roads where the shortcut enters and exits
+    fn entry_and_exit(&self) -> ((RoadID, Direction), (RoadID, Direction)) {
+        (self.steps[0], *self.steps.last().unwrap())
+    }
+
     pub fn to_gj(&self, map: &MapModel) -> Feature {
         let mut pts = Vec::new();
         for (r, direction) in &self.steps {
@@ -167,6 +279,152 @@ impl Path {
         let mut f = map.mercator.to_wgs84_gj(&linestring);
         f.set_property("directness", self.directness);
         f.set_property("length_meters", length);
+        if let Some(through_traffic) = self.through_traffic {
+            f.set_property("through_traffic", through_traffic);
+        }
         f
     }
 }
+
+/// Using the demand model, route sampled trips before any edits and find the portions cutting
+/// through the neighbourhood's interior. Returns the number of trips per (entry main road, exit
+/// main road) pair. A trip leaving and re-entering the interior counts once per pass through it.
+pub fn through_traffic_pairs(
+    map: &MapModel,
+    neighbourhood: &Neighbourhood,
+) -> HashMap<(RoadID, RoadID), usize> {
+    let mut pairs = HashMap::new();
+    let Some(ref demand) = map.demand else {
+        return pairs;
+    };
+    let router_input = map.router_input_before();
+
+    for (r1, r2, count) in demand.make_requests(true) {
+        let Some(route) = map.router_before.route_from_roads(&router_input, r1, r2) else {
+            continue;
+        };
+
+        // The last main road of this neighbourhood we were on, and whether we've entered the
+        // interior since then
+        let mut entry: Option<RoadID> = None;
+        let mut inside = false;
+        for (r, _) in &route.steps {
+            if neighbourhood.main_roads.contains(r) {
+                if let (Some(entry), true) = (entry, inside) {
+                    *pairs.entry((entry, *r)).or_insert(0) += count;
+                }
+                entry = Some(*r);
+                inside = false;
+            } else if neighbourhood.interior_roads.contains(r) {
+                inside = entry.is_some();
+            } else {
+                // The route left the neighbourhood without using one of its main roads
+                entry = None;
+                inside = false;
+            }
+        }
+    }
+
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_tests::{
+        get_roads_by_name, load_osm_xml, rectangle_neighbourhood, WEST_NEIGHBOURHOOD,
+    };
+
+    /// The main road of a side of the neighbourhood between its two border intersections
+    fn middle_segment(map: &MapModel, neighbourhood: &Neighbourhood, name: &str) -> RoadID {
+        get_roads_by_name(map, name)
+            .into_iter()
+            .find(|r| {
+                let road = map.get_r(*r);
+                neighbourhood.border_intersections.contains(&road.src_i)
+                    && neighbourhood.border_intersections.contains(&road.dst_i)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn through_traffic_counted_once_per_pair() {
+        let map = load_osm_xml("two_neighbourhoods");
+        let mut neighbourhood = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+
+        // These main roads each touch two border intersections, so shortcuts between them are
+        // found from four pairs of border intersections
+        let entry = middle_segment(&map, &neighbourhood, "west");
+        let exit = middle_segment(&map, &neighbourhood, "middle");
+        neighbourhood.through_traffic_pairs = Some(HashMap::from([((entry, exit), 10)]));
+
+        let cells = Cell::find_all(&map, &neighbourhood);
+        let shortcuts = Shortcuts::new(&map, &neighbourhood, &cells);
+        let per_road = shortcuts.through_traffic_per_road.unwrap();
+        assert!(per_road.values().all(|trips| *trips == 0 || *trips == 10));
+        assert!(per_road.values().any(|trips| *trips == 10));
+    }
+
+    #[test]
+    fn diff_matches_shortcuts_by_entry_and_exit() {
+        let map = load_osm_xml("two_neighbourhoods");
+        let neighbourhood = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        let entry = middle_segment(&map, &neighbourhood, "west");
+        let exit = middle_segment(&map, &neighbourhood, "middle");
+        // Both routes enter and exit on the same main roads, using different interior roads
+        let path = |name: &str, exit_dir| Path {
+            steps: std::iter::once((entry, Direction::Forwards))
+                .chain(
+                    get_roads_by_name(&map, name)
+                        .into_iter()
+                        .map(|r| (r, Direction::Forwards)),
+                )
+                .chain(std::iter::once((exit, exit_dir)))
+                .collect(),
+            directness: 1.0,
+            through_traffic: None,
+        };
+        let shortcuts = |paths| Shortcuts {
+            paths,
+            count_per_road: HashMap::new(),
+            through_traffic_per_road: None,
+        };
+
+        let diff = |before, after| {
+            shortcuts(after)
+                .diff_to_gj(&shortcuts(before), &map)
+                .foreign_members
+                .unwrap()
+        };
+
+        // One of two shortcuts between the same main roads is gone
+        let json = diff(
+            vec![
+                path("west 1", Direction::Forwards),
+                path("west 2", Direction::Forwards),
+            ],
+            vec![path("west 2", Direction::Forwards)],
+        );
+        assert_eq!(json["num_eliminated"], 1);
+        assert_eq!(json["num_remaining"], 1);
+        assert_eq!(json["num_new"], 0);
+
+        // Diverting through a different interior road is still the same shortcut
+        let json = diff(
+            vec![path("west 1", Direction::Forwards)],
+            vec![path("west 2", Direction::Forwards)],
+        );
+        assert_eq!(json["num_eliminated"], 0);
+        assert_eq!(json["num_remaining"], 1);
+        assert_eq!(json["num_new"], 0);
+
+        // Exiting the other way along the main road is a different shortcut
+        let json = diff(
+            vec![path("west 1", Direction::Forwards)],
+            vec![path("west 1", Direction::Backwards)],
+        );
+        assert_eq!(json["num_eliminated"], 1);
+        assert_eq!(json["num_remaining"], 0);
+        assert_eq!(json["num_new"], 1);
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::BTreeSet;
+
+use geo::{line_measures::InterpolatableLine, Euclidean};
+use geojson::FeatureCollection;
+use serde::Serialize;
+
+use crate::geo_helpers::{angle_of_pt_on_line, limit_angle};
+use crate::map_model::{Command, DiagonalFilter};
+use crate::{
+    Cell, FilterKind, IntersectionID, MapModel, ModalFilter, Neighbourhood, RoadID, Shortcuts,
+};
+
+// Give up on an option after placing this many filters
+const MAX_FILTERS: usize = 20;
+
+/// One proposed set of filters for a neighbourhood, found automatically.
+pub struct FilterSuggestion {
+    /// Always a `Command::Multiple`, applying all of the filters at once
+    pub cmd: Command,
+    pub num_filters: usize,
+    /// Shortcuts left after placing all of the filters. Unless `hit_filter_limit`, no single
+    /// filter could reduce them without disconnecting a cell or blocking a bus route.
+    pub remaining_shortcuts: usize,
+    /// The search gave up after `MAX_FILTERS` filters with shortcuts still remaining
+    pub hit_filter_limit: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Candidate {
+    Road(RoadID),
+    Diagonal(IntersectionID, bool),
+}
+
+/// The state of a neighbourhood with some filters applied
+struct Evaluation {
+    num_shortcuts: usize,
+    num_disconnected_cells: usize,
+    shortcut_roads: BTreeSet<RoadID>,
+}
+
+impl Neighbourhood {
+    /// Propose up to `num_options` sets of modal filters that eliminate all shortcuts, without
+    /// disconnecting any cells and without filtering bus routes. Options are found greedily, each
+    /// starting from a different first filter, and are ranked best first. The map's edits are
+    /// unchanged afterwards.
+    pub fn suggest_filters(
+        &self,
+        map: &mut MapModel,
+        kind: FilterKind,
+        num_options: usize,
+    ) -> Vec<FilterSuggestion> {
+        // Filters are placed temporarily while searching. Work on a copy of the current edits, so
+        // the real ones are never touched.
+        let mut scratch = map.current_edit_state();
+        map.swap_edit_state(&mut scratch);
+        let mut suggestions = self.search(map, kind, num_options);
+        map.swap_edit_state(&mut scratch);
+
+        suggestions.sort_by_key(|s| (s.remaining_shortcuts, s.num_filters));
+        suggestions
+    }
+
+    fn search(
+        &self,
+        map: &mut MapModel,
+        kind: FilterKind,
+        num_options: usize,
+    ) -> Vec<FilterSuggestion> {
+        let initial = self.evaluate(map);
+        let mut suggestions = Vec::new();
+        if initial.num_shortcuts == 0 {
+            return suggestions;
+        }
+
+        // Rank every possible first filter, then continue greedily from the best few
+        let mut first_choices = self.rank_candidates(map, kind, &initial);
+        first_choices.truncate(num_options);
+
+        let mut seen = Vec::new();
+        for (first, mut current) in first_choices {
+            let mut chosen = vec![first];
+            self.apply(map, first, kind);
+
+            while current.num_shortcuts > 0 && chosen.len() < MAX_FILTERS {
+                let Some((next, evaluation)) =
+                    self.rank_candidates(map, kind, &current).into_iter().next()
+                else {
+                    break;
+                };
+                self.apply(map, next, kind);
+                chosen.push(next);
+                current = evaluation;
+            }
+
+            for candidate in &chosen {
+                candidate.remove(map);
+            }
+
+            // Different starting points can converge on the same set of filters
+            let mut key: Vec<String> = chosen.iter().map(|c| c.key()).collect();
+            key.sort();
+            if seen.contains(&key) {
+                continue;
+            }
+            seen.push(key);
+
+            suggestions.push(FilterSuggestion {
+                cmd: Command::Multiple(chosen.iter().map(|c| c.to_cmd(map, kind)).collect()),
+                num_filters: chosen.len(),
+                remaining_shortcuts: current.num_shortcuts,
+                hit_filter_limit: current.num_shortcuts > 0 && chosen.len() == MAX_FILTERS,
+            });
+        }
+        suggestions
+    }
+
+    /// Returns every candidate filter that reduces the number of shortcuts without disconnecting
+    /// a cell, with the best first.
+    fn rank_candidates(
+        &self,
+        map: &mut MapModel,
+        kind: FilterKind,
+        current: &Evaluation,
+    ) -> Vec<(Candidate, Evaluation)> {
+        let mut results = Vec::new();
+        for candidate in self.candidates(map, current) {
+            self.apply(map, candidate, kind);
+            let evaluation = self.evaluate_unless_disconnected(map, current);
+            candidate.remove(map);
+
+            let Some(evaluation) = evaluation else {
+                continue;
+            };
+            if evaluation.num_shortcuts >= current.num_shortcuts {
+                continue;
+            }
+            results.push((candidate, evaluation));
+        }
+        results.sort_by_key(|(_, evaluation)| evaluation.num_shortcuts);
+        results
+    }
+
+    /// Only places used by a shortcut are worth filtering
+    fn candidates(&self, map: &MapModel, current: &Evaluation) -> Vec<Candidate> {
+        let mut candidates = Vec::new();
+        for r in &current.shortcut_roads {
+            if map.modal_filters.contains_key(r) || map.get_bus_routes_on_road(*r).is_some() {
+                continue;
+            }
+            candidates.push(Candidate::Road(*r));
+        }
+        for i in &self.editable_intersections {
+            if map.diagonal_filters.contains_key(i) {
+                continue;
+            }
+            let roads = &map.get_i(*i).roads;
+            if !roads.iter().any(|r| current.shortcut_roads.contains(r)) {
+                continue;
+            }
+            if roads
+                .iter()
+                .any(|r| map.get_bus_routes_on_road(*r).is_some())
+            {
+                continue;
+            }
+            for is_rotated in [false, true] {
+                candidates.push(Candidate::Diagonal(*i, is_rotated));
+            }
+        }
+        candidates
+    }
+
+    fn apply(&self, map: &mut MapModel, candidate: Candidate, kind: FilterKind) {
+        match candidate.to_cmd(map, kind) {
+            Command::SetModalFilters(r, filters) => {
+                map.modal_filters.insert(r, filters);
+            }
+            Command::SetDiagonalFilter(i, Some(filter)) => {
+                map.diagonal_filters.insert(i, filter);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn evaluate(&self, map: &MapModel) -> Evaluation {
+        let cells = Cell::find_all(map, self);
+        let num_disconnected_cells = cells.iter().filter(|c| c.is_disconnected()).count();
+        self.evaluate_cells(map, &cells, num_disconnected_cells)
+    }
+
+    /// Like `evaluate`, but returns `None` without finding shortcuts, the slow part, if more cells
+    /// are disconnected than in `current`
+    fn evaluate_unless_disconnected(
+        &self,
+        map: &MapModel,
+        current: &Evaluation,
+    ) -> Option<Evaluation> {
+        let cells = Cell::find_all(map, self);
+        let num_disconnected_cells = cells.iter().filter(|c| c.is_disconnected()).count();
+        if num_disconnected_cells > current.num_disconnected_cells {
+            return None;
+        }
+        Some(self.evaluate_cells(map, &cells, num_disconnected_cells))
+    }
+
+    fn evaluate_cells(
+        &self,
+        map: &MapModel,
+        cells: &Vec<Cell>,
+        num_disconnected_cells: usize,
+    ) -> Evaluation {
+        let shortcuts = Shortcuts::new(map, self, cells);
+        Evaluation {
+            num_shortcuts: shortcuts.paths.len(),
+            num_disconnected_cells,
+            shortcut_roads: shortcuts.count_per_road.keys().cloned().collect(),
+        }
+    }
+}
+
+impl Candidate {
+    fn to_cmd(self, map: &MapModel, kind: FilterKind) -> Command {
+        match self {
+            // Candidate roads don't have any filters yet
+            Candidate::Road(r) => Command::SetModalFilters(
+                r,
+                vec![ModalFilter {
+                    kind,
+                    percent_along: 0.5,
+                }],
+            ),
+            Candidate::Diagonal(i, is_rotated) => Command::SetDiagonalFilter(
+                i,
+                Some(DiagonalFilter::new(map.get_i(i), is_rotated, map)),
+            ),
+        }
+    }
+
+    /// Undoes `Neighbourhood::apply`. Candidates are never placed where a filter already exists.
+    fn remove(self, map: &mut MapModel) {
+        match self {
+            Candidate::Road(r) => {
+                map.modal_filters.remove(&r);
+            }
+            Candidate::Diagonal(i, _) => {
+                map.diagonal_filters.remove(&i);
+            }
+        }
+    }
+
+    fn key(self) -> String {
+        match self {
+            Candidate::Road(r) => format!("road {}", r.0),
+            Candidate::Diagonal(i, is_rotated) => format!("intersection {} {is_rotated}", i.0),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SuggestionSummary {
+    rank: usize,
+    num_filters: usize,
+    remaining_shortcuts: usize,
+    hit_filter_limit: bool,
+    filters: FeatureCollection,
+}
+
+impl FilterSuggestion {
+    /// Describes the proposed filters as GeoJSON points, along with some summary stats
+    pub fn to_json(&self, map: &MapModel, rank: usize) -> serde_json::Value {
+        let Command::Multiple(ref cmds) = self.cmd else {
+            unreachable!("FilterSuggestion must be Command::Multiple");
+        };
+        let mut features = Vec::new();
+        for cmd in cmds {
+            features.push(match cmd {
+                Command::SetModalFilters(r, filters) => {
+                    let filter = &filters[0];
+                    let road = map.get_r(*r);
+                    let pt = road
+                        .linestring
+                        .point_at_ratio_from_start(&Euclidean, filter.percent_along)
+                        .unwrap();
+                    let angle =
+                        limit_angle(angle_of_pt_on_line(&road.linestring, pt.into()) + 90.0);
+                    let mut f = map.mercator.to_wgs84_gj(&pt);
+                    f.set_property("filter_kind", filter.kind.to_string());
+                    f.set_property("road", r.0);
+                    f.set_property("angle", angle);
+                    f
+                }
+                Command::SetDiagonalFilter(i, Some(filter)) => {
+                    let mut f = map.mercator.to_wgs84_gj(&map.get_i(*i).point);
+                    f.set_property("filter_kind", FilterKind::DiagonalFilter.to_string());
+                    f.set_property("intersection_id", i.0);
+                    f.set_property("filter", filter);
+                    f
+                }
+                _ => unreachable!(),
+            });
+        }
+        serde_json::to_value(SuggestionSummary {
+            rank,
+            num_filters: self.num_filters,
+            remaining_shortcuts: self.remaining_shortcuts,
+            hit_filter_limit: self.hit_filter_limit,
+            filters: FeatureCollection {
+                features,
+                bbox: None,
+                foreign_members: None,
+            },
+        })
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_tests::{load_osm_xml, rectangle_neighbourhood, WEST_NEIGHBOURHOOD};
+
+    #[test]
+    fn suggestions_reduce_shortcuts() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let neighbourhood = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        let initial = neighbourhood.evaluate(&map);
+        assert!(initial.num_shortcuts > 0);
+        assert_eq!(initial.num_disconnected_cells, 0);
+
+        let suggestions = neighbourhood.suggest_filters(&mut map, FilterKind::WalkCycleOnly, 3);
+        assert!(!suggestions.is_empty());
+
+        // Searching doesn't leave any filters behind
+        assert!(map.modal_filters.is_empty());
+        assert!(map.diagonal_filters.is_empty());
+
+        let best = &suggestions[0];
+        assert!(!best.hit_filter_limit);
+        assert!(best.remaining_shortcuts < initial.num_shortcuts);
+
+        // Applying the suggestion matches what the search found
+        map.apply_command(best.cmd.clone());
+        let after = neighbourhood.evaluate(&map);
+        assert_eq!(after.num_shortcuts, best.remaining_shortcuts);
+        assert_eq!(after.num_disconnected_cells, 0);
+        assert_eq!(
+            map.modal_filters.len() + map.diagonal_filters.len(),
+            best.num_filters
+        );
+    }
+}
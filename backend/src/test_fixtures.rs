@@ -79,7 +79,7 @@ impl NeighbourhoodFixture {
 
         // CNT files are pre-built with everything already
         if self.is_cnt {
-            let path = format!("../web/public/cnt/maps_v4/{}.bin.gz", self.study_area_name);
+            let path = format!("../web/public/cnt/maps_v5/{}.bin.gz", self.study_area_name);
             let input_bytes = std::fs::read(&path).context(format!("unable to read '{path}'"))?;
             let mut gunzipped = Vec::new();
             let mut decoder = flate2::read::GzDecoder::new(Cursor::new(input_bytes));
@@ -148,7 +148,7 @@ impl NeighbourhoodFixture {
 
     pub fn neighbourhood_params(&self) -> Result<(NeighbourhoodBoundary, MapModel)> {
         let mut map = self.map_model()?;
-        map.load_savefile(self.savefile()?)?;
+        map.load_savefile(self.savefile()?, false)?;
         Ok((map.boundaries[self.neighbourhood_name].clone(), map))
     }
 }
@@ -41,8 +41,10 @@ fn get_gj(neighbourhood_fixture: &NeighbourhoodFixture) -> Result<String> {
     for mut f in map.filters_to_gj().features {
         f.set_property("kind", "existing_modal_filter");
         f.remove_property("road");
+        f.remove_property("filter_idx");
         f.remove_property("edited");
         f.remove_property("angle");
+        f.remove_property("exempt_vehicles");
         gj.features.push(f);
     }
 
@@ -55,6 +57,11 @@ fn prune_features(mut gj: FeatureCollection) -> FeatureCollection {
     gj.features
         .retain(|f| f.property("kind").unwrap().as_str().unwrap() != "border_entries");
 
+    // Only keep the edit history, not neighbourhood-wide metrics
+    if let Some(ref mut foreign_members) = gj.foreign_members {
+        foreign_members.retain(|k, _| ["undo_length", "redo_length"].contains(&k.as_str()));
+    }
+
     for f in &mut gj.features {
         if matches!(
             f.geometry.as_ref().unwrap().value,
@@ -0,0 +1,448 @@
+use std::collections::BTreeSet;
+
+use geo::{Euclidean, InterpolatableLine, MultiLineString};
+use geojson::{Feature, FeatureCollection};
+
+use crate::cells::Cell;
+use crate::route::{RouterInput, VehicleProfile};
+use crate::{IntersectionID, MapModel, ModalFilter, Neighbourhood, RoadID, TravelFlow};
+
+impl MapModel {
+    /// Looks for edits that probably don't do what the user intended. Checks involving cells need
+    /// a neighbourhood. Returns a feature per warning, with `warning` set to the kind of problem
+    /// and a `message` describing it.
+    pub fn validate_edits(&mut self, neighbourhood: Option<&Neighbourhood>) -> FeatureCollection {
+        let mut features = Vec::new();
+        self.check_modal_filters(&mut features);
+        self.check_travel_flows(&mut features);
+        self.check_diagonal_filters(&mut features);
+        if let Some(neighbourhood) = neighbourhood {
+            self.check_redundant_filters(neighbourhood, &mut features);
+            self.check_disconnected_cells(neighbourhood, &mut features);
+        }
+
+        FeatureCollection {
+            features,
+            bbox: None,
+            foreign_members: None,
+        }
+    }
+
+    /// Modal filters the user added, not existing ones
+    pub(crate) fn new_modal_filters(&self) -> impl Iterator<Item = (RoadID, &ModalFilter)> + '_ {
+        self.modal_filters.iter().flat_map(move |(r, filters)| {
+            let original = self.original_modal_filters.get(r);
+            filters
+                .iter()
+                .filter(move |filter| !original.is_some_and(|list| list.contains(filter)))
+                .map(move |filter| (*r, filter))
+        })
+    }
+
+    fn check_modal_filters(&self, features: &mut Vec<Feature>) {
+        for (r, filter) in self.new_modal_filters() {
+            let road = self.get_r(r);
+            let pt = road
+                .linestring
+                .point_at_ratio_from_start(&Euclidean, filter.percent_along)
+                .unwrap();
+            let mut warn = |kind: &str, message: String| {
+                let mut f = self.mercator.to_wgs84_gj(&pt);
+                f.set_property("road", r.0);
+                features.push(warning(f, kind, message));
+            };
+
+            if self.is_main_road[&r] {
+                warn(
+                    "filter_on_main_road",
+                    format!("{r} is a main road, so this filter diverts traffic elsewhere"),
+                );
+            }
+
+            if [road.src_i, road.dst_i]
+                .into_iter()
+                .any(|i| self.get_i(i).roads.len() == 1)
+            {
+                warn(
+                    "filter_on_dead_end",
+                    format!("{r} is a dead-end, so there's no through-traffic to filter"),
+                );
+            }
+
+            if filter.kind.blocks(VehicleProfile::Bus) {
+                if let Some(routes) = self.get_bus_routes_on_road(r) {
+                    warn(
+                        "filter_blocks_bus_route",
+                        format!(
+                            "This filter blocks bus routes {}; consider a bus gate",
+                            routes.join(", ")
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Finds roads that could be entered and exited before edits, but not after any changed
+    /// travel flows
+    fn check_travel_flows(&self, features: &mut Vec<Feature>) {
+        let mut affected = BTreeSet::new();
+        for road in &self.roads {
+            if self.travel_flows[&road.id] != TravelFlow::from_osm(&road.tags) {
+                for i in [road.src_i, road.dst_i] {
+                    affected.extend(self.get_i(i).roads.iter().cloned());
+                }
+            }
+        }
+        if affected.is_empty() {
+            return;
+        }
+
+        let before = self.router_input_before();
+        let after = self.router_input_after();
+        for r in affected {
+            for (can_enter, kind, message) in [
+                (true, "unreachable_road", "can't be entered"),
+                (false, "trapped_road", "can't be left"),
+            ] {
+                if self.road_connects(&before, r, can_enter)
+                    && !self.road_connects(&after, r, can_enter)
+                {
+                    let f = self.mercator.to_wgs84_gj(&self.get_r(r).linestring);
+                    features.push(warning(
+                        f,
+                        kind,
+                        format!("After changing travel flows, {r} {message}"),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Can any vehicle enter (or leave) the road through some intersection?
+    fn road_connects(&self, router_input: &impl RouterInput, r: RoadID, can_enter: bool) -> bool {
+        let road = self.get_r(r);
+        let flow = router_input.travel_flow(r);
+        for (i, flows_away_from_i) in [
+            (road.src_i, flow.flows_forwards()),
+            (road.dst_i, flow.flows_backwards()),
+        ] {
+            let intersection = self.get_i(i);
+            if can_enter {
+                if flows_away_from_i
+                    && intersection
+                        .allowed_movements_to(r, router_input)
+                        .next()
+                        .is_some()
+                {
+                    return true;
+                }
+            } else {
+                let flows_into_i = if i == road.src_i {
+                    flow.flows_backwards()
+                } else {
+                    flow.flows_forwards()
+                };
+                if flows_into_i
+                    && intersection
+                        .allowed_movements_from(r, router_input)
+                        .next()
+                        .is_some()
+                {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// When roads are one-way, a diagonal filter in the wrong orientation can leave traffic
+    /// entering from one side with no way out
+    fn check_diagonal_filters(&self, features: &mut Vec<Feature>) {
+        for (i, filter) in &self.diagonal_filters {
+            for group in [&filter.group_a, &filter.group_b] {
+                if group.len() < 2 {
+                    // Closing off a single road is deliberate
+                    continue;
+                }
+                let stuck = group.iter().any(|from| {
+                    self.flows_into(*from, *i)
+                        && !group.iter().any(|to| to != from && self.flows_out(*to, *i))
+                });
+                if stuck {
+                    let f = self.mercator.to_wgs84_gj(&self.get_i(*i).point);
+                    features.push(warning(
+                        f,
+                        "diagonal_filter_blocks_exits",
+                        format!(
+                            "Some traffic entering {i} can't leave it; try rotating the filter"
+                        ),
+                    ));
+                    break;
+                }
+            }
+        }
+    }
+
+    fn flows_into(&self, r: RoadID, i: IntersectionID) -> bool {
+        let road = self.get_r(r);
+        let flow = self.travel_flows[&r];
+        (road.dst_i == i && flow.flows_forwards()) || (road.src_i == i && flow.flows_backwards())
+    }
+
+    fn flows_out(&self, r: RoadID, i: IntersectionID) -> bool {
+        let road = self.get_r(r);
+        let flow = self.travel_flows[&r];
+        (road.src_i == i && flow.flows_forwards()) || (road.dst_i == i && flow.flows_backwards())
+    }
+
+    /// A filter is redundant when both of its ends are in the same cell anyway
+    fn check_redundant_filters(&self, neighbourhood: &Neighbourhood, features: &mut Vec<Feature>) {
+        let cells = Cell::find_all(self, neighbourhood);
+        for (r, filter) in self.new_modal_filters() {
+            if !neighbourhood.interior_roads.contains(&r) {
+                continue;
+            }
+            let road = self.get_r(r);
+            if neighbourhood.border_intersections.contains(&road.src_i)
+                || neighbourhood.border_intersections.contains(&road.dst_i)
+            {
+                continue;
+            }
+            if cells.iter().any(|cell| {
+                self.cell_reaches(cell, road.src_i) && self.cell_reaches(cell, road.dst_i)
+            }) {
+                let pt = road
+                    .linestring
+                    .point_at_ratio_from_start(&Euclidean, filter.percent_along)
+                    .unwrap();
+                let mut f = self.mercator.to_wgs84_gj(&pt);
+                f.set_property("road", r.0);
+                features.push(warning(
+                    f,
+                    "redundant_filter",
+                    format!(
+                        "Traffic can already get around the filter on {r} within the same cell"
+                    ),
+                ));
+            }
+        }
+    }
+
+    /// Does an unfiltered road in the cell touch this intersection?
+    fn cell_reaches(&self, cell: &Cell, i: IntersectionID) -> bool {
+        cell.roads.keys().any(|r| {
+            let road = self.get_r(*r);
+            !self.modal_filters.contains_key(r) && (road.src_i == i || road.dst_i == i)
+        })
+    }
+
+    /// Finds cells that aren't connected to a main road, when they were before the edits
+    fn check_disconnected_cells(
+        &mut self,
+        neighbourhood: &Neighbourhood,
+        features: &mut Vec<Feature>,
+    ) {
+        let mut state = self.original_edit_state();
+        self.swap_edit_state(&mut state);
+        let disconnected_before: BTreeSet<RoadID> = Cell::find_all(self, neighbourhood)
+            .into_iter()
+            .filter(|cell| cell.is_disconnected())
+            .flat_map(|cell| cell.roads.into_keys())
+            .collect();
+        self.swap_edit_state(&mut state);
+
+        for cell in Cell::find_all(self, neighbourhood) {
+            if !cell.is_disconnected() || cell.roads.keys().all(|r| disconnected_before.contains(r))
+            {
+                continue;
+            }
+            let lines = MultiLineString(
+                cell.roads
+                    .keys()
+                    .map(|r| self.get_r(*r).linestring.clone())
+                    .collect(),
+            );
+            let mut f = self.mercator.to_wgs84_gj(&lines);
+            f.set_property("roads", cell.roads.keys().map(|r| r.0).collect::<Vec<_>>());
+            features.push(warning(
+                f,
+                "disconnected_cell",
+                format!(
+                    "{} roads can't be reached from a main road",
+                    cell.roads.len()
+                ),
+            ));
+        }
+    }
+}
+
+fn warning(mut f: Feature, kind: &str, message: String) -> Feature {
+    f.set_property("kind", "warning");
+    f.set_property("warning", kind);
+    f.set_property("message", message);
+    f
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_model::Command;
+    use crate::osm_tests::{
+        get_road_by_name, get_roads_by_name, load_osm_xml, rectangle_neighbourhood,
+        WEST_NEIGHBOURHOOD,
+    };
+    use crate::FilterKind;
+
+    fn warnings(map: &mut MapModel, neighbourhood: Option<&Neighbourhood>) -> Vec<String> {
+        map.validate_edits(neighbourhood)
+            .features
+            .into_iter()
+            .map(|f| f.property("warning").unwrap().as_str().unwrap().to_string())
+            .collect()
+    }
+
+    /// The 4-way junction of "west 1" and "west cross", inside the west neighbourhood
+    fn west_junction(map: &MapModel) -> IntersectionID {
+        let r = get_road_by_name(map, "west 1");
+        map.intersections
+            .iter()
+            .find(|i| i.roads.len() == 4 && i.roads.contains(&r))
+            .unwrap()
+            .id
+    }
+
+    /// The stretch of "west cross" between the two interior junctions
+    fn west_cross_middle(map: &MapModel) -> RoadID {
+        get_roads_by_name(map, "west cross")
+            .into_iter()
+            .find(|r| {
+                let road = map.get_r(*r);
+                [road.src_i, road.dst_i]
+                    .into_iter()
+                    .all(|i| map.get_i(i).roads.len() == 4)
+            })
+            .unwrap()
+    }
+
+    fn set_filter(map: &mut MapModel, r: RoadID, kind: FilterKind) {
+        map.apply_command(Command::SetModalFilters(
+            r,
+            vec![ModalFilter {
+                kind,
+                percent_along: 0.5,
+            }],
+        ));
+    }
+
+    /// Makes every road at the intersection one-way, into it or away from it
+    fn set_flows_at(map: &mut MapModel, i: IntersectionID, roads: &[RoadID], into: bool) {
+        for r in roads {
+            let flow = if (map.get_r(*r).dst_i == i) == into {
+                TravelFlow::FORWARDS
+            } else {
+                TravelFlow::BACKWARDS
+            };
+            map.apply_command(Command::SetTravelFlow(*r, flow));
+        }
+    }
+
+    #[test]
+    fn filter_on_dead_end() {
+        // Every road at this intersection leads to a dead-end
+        let mut map = load_osm_xml("simple_four_way_intersection");
+        assert!(map.validate_edits(None).features.is_empty());
+
+        let r = RoadID(0);
+        let pt = map
+            .get_r(r)
+            .linestring
+            .point_at_ratio_from_start(&Euclidean, 0.5)
+            .unwrap();
+        map.add_modal_filter(pt.into(), Some(vec![r]), FilterKind::WalkCycleOnly);
+        assert_eq!(warnings(&mut map, None), vec!["filter_on_dead_end"]);
+    }
+
+    #[test]
+    fn filter_blocks_bus_route() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let r = west_cross_middle(&map);
+        map.bus_routes_on_roads
+            .insert(map.get_r(r).way, vec!["X1".to_string()]);
+
+        set_filter(&mut map, r, FilterKind::BusGate);
+        assert!(warnings(&mut map, None).is_empty());
+
+        set_filter(&mut map, r, FilterKind::WalkCycleOnly);
+        assert_eq!(warnings(&mut map, None), vec!["filter_blocks_bus_route"]);
+    }
+
+    #[test]
+    fn unreachable_and_trapped_roads() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let i = west_junction(&map);
+        let roads = map.get_i(i).roads.clone();
+
+        // Nothing can leave the junction
+        set_flows_at(&mut map, i, &roads, true);
+        assert_eq!(warnings(&mut map, None), vec!["trapped_road"; 4]);
+
+        // Nothing can reach the junction
+        set_flows_at(&mut map, i, &roads, false);
+        assert_eq!(warnings(&mut map, None), vec!["unreachable_road"; 4]);
+    }
+
+    #[test]
+    fn diagonal_filter_blocks_exits() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let i = west_junction(&map);
+        map.add_diagonal_filter(i);
+        // Both roads on one side of the filter only lead into the junction
+        let group = map.diagonal_filters[&i].group_a.clone();
+        set_flows_at(&mut map, i, &group, true);
+        assert!(warnings(&mut map, None).contains(&"diagonal_filter_blocks_exits".to_string()));
+
+        // The other orientation pairs each of those roads with a two-way road
+        map.rotate_diagonal_filter(i);
+        assert!(!warnings(&mut map, None).contains(&"diagonal_filter_blocks_exits".to_string()));
+    }
+
+    #[test]
+    fn redundant_filter() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let r = west_cross_middle(&map);
+
+        // The filter splits the neighbourhood into two cells
+        let neighbourhood = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        set_filter(&mut map, r, FilterKind::WalkCycleOnly);
+        assert!(warnings(&mut map, Some(&neighbourhood)).is_empty());
+
+        // When "middle" is a local road, traffic can go around the filter through it
+        for middle in get_roads_by_name(&map, "middle") {
+            map.apply_command(Command::SetMainRoad(middle, false));
+        }
+        let neighbourhood = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        assert_eq!(
+            warnings(&mut map, Some(&neighbourhood)),
+            vec!["redundant_filter"]
+        );
+    }
+
+    #[test]
+    fn disconnected_cell() {
+        let mut map = load_osm_xml("two_neighbourhoods");
+        let neighbourhood = rectangle_neighbourhood(&map, "west", WEST_NEIGHBOURHOOD);
+        let middle = west_cross_middle(&map);
+
+        // Filter every road leading to the stretch of "west cross" in the middle
+        for r in neighbourhood.interior_roads.clone() {
+            if r != middle {
+                set_filter(&mut map, r, FilterKind::WalkCycleOnly);
+            }
+        }
+        assert_eq!(
+            warnings(&mut map, Some(&neighbourhood)),
+            vec!["disconnected_cell"]
+        );
+    }
+}
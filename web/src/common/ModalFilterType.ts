@@ -1,9 +1,13 @@
 import busGateIconUrl from "../../assets/filters/bus_gate_icon.gif?url";
 import busGateImageUrl from "../../assets/filters/bus_gate.gif?url";
+import cameraResidentExemptionIconUrl from "../../assets/filters/camera_resident_exemption_icon.png?url";
+import exceptAccessIconUrl from "../../assets/filters/except_access_icon.png?url";
 import noEntryIconUrl from "../../assets/filters/no_entry_icon.gif?url";
 import noEntryImageUrl from "../../assets/filters/no_entry.gif?url";
+import risingBollardIconUrl from "../../assets/filters/rising_bollard_icon.png?url";
 import schoolStreetIconUrl from "../../assets/filters/school_street_icon.gif?url";
 import schoolStreetImageUrl from "../../assets/filters/school_street.gif?url";
+import timedClosureIconUrl from "../../assets/filters/timed_closure_icon.png?url";
 import walkCycleOnlyIconUrl from "../../assets/filters/walk_cycle_only_icon.gif?url";
 import walkCycleOnlyImageUrl from "../../assets/filters/walk_cycle_only.gif?url";
 
@@ -46,6 +50,14 @@ export class ModalFilterType {
         return ModalFilterType.busGate;
       case "school_street":
         return ModalFilterType.schoolStreet;
+      case "camera_resident_exemption":
+        return ModalFilterType.cameraResidentExemption;
+      case "except_access":
+        return ModalFilterType.exceptAccess;
+      case "timed_closure":
+        return ModalFilterType.timedClosure;
+      case "rising_bollard":
+        return ModalFilterType.risingBollard;
       default:
         console.assert(`unknown filter type: "${filterType}"`);
         return undefined;
@@ -80,11 +92,44 @@ export class ModalFilterType {
     schoolStreetImageUrl,
     schoolStreetIconUrl,
   );
+  // There are no photos of these yet, so the icon doubles as the large image
+  static cameraResidentExemption = new ModalFilterType(
+    "camera_resident_exemption",
+    "Camera, residents exempt",
+    "Traffic cameras enforce the closure, but residents can register their vehicles to pass. Buses and emergency vehicles can also pass. There is no physical barrier.",
+    cameraResidentExemptionIconUrl,
+    cameraResidentExemptionIconUrl,
+  );
+  static exceptAccess = new ModalFilterType(
+    "except_access",
+    "Except for access",
+    "A sign bans motor vehicles except for access, so residents and emergency vehicles can still drive through.",
+    exceptAccessIconUrl,
+    exceptAccessIconUrl,
+  );
+  static timedClosure = new ModalFilterType(
+    "timed_closure",
+    "Timed closure",
+    "Closed to traffic except emergency vehicles during some hours. Routing treats the closure as always active.",
+    timedClosureIconUrl,
+    timedClosureIconUrl,
+  );
+  static risingBollard = new ModalFilterType(
+    "rising_bollard",
+    "Rising bollard",
+    "A bollard that lowers to let buses and emergency vehicles through, but blocks other vehicles.",
+    risingBollardIconUrl,
+    risingBollardIconUrl,
+  );
 
   static allTypes = [
     ModalFilterType.walkCycleOnly,
     ModalFilterType.noEntry,
     ModalFilterType.busGate,
     ModalFilterType.schoolStreet,
+    ModalFilterType.cameraResidentExemption,
+    ModalFilterType.exceptAccess,
+    ModalFilterType.timedClosure,
+    ModalFilterType.risingBollard,
   ];
 }
@@ -51,7 +51,7 @@ export async function loadProject(projectID: ProjectID) {
       ),
     );
     // TODO Rename savefile -> project? Or combine this call with the constructor?
-    get(backend)!.loadSavefile(project);
+    loadSavefile(get(backend)!, project);
     currentProjectID.set(projectID);
     console.timeEnd("load");
     afterProjectLoaded(projectID);
@@ -64,6 +64,36 @@ export async function loadProject(projectID: ProjectID) {
   loadingProgress.set(null);
 }
 
+// Loads a project's edits, offering to skip any malformed ones. Throws if the
+// user declines. Warns about edits that don't match the current map well.
+export function loadSavefile(b: Backend, project: ProjectFeatureCollection) {
+  let report;
+  try {
+    report = b.loadSavefile(project);
+  } catch (err) {
+    let question = `${err}\n\nOpen the project without the broken edits?`;
+    if (!window.confirm(question)) {
+      throw err;
+    }
+    report = b.loadSavefile(project, true);
+  }
+
+  if (report.num_low_confidence + report.num_dropped > 0) {
+    let lines = ["The map data has changed since this project was saved."];
+    if (report.num_low_confidence > 0) {
+      lines.push(
+        `${report.num_low_confidence} edit(s) were matched to roads or intersections that look different now. Check they're still in the right place.`,
+      );
+    }
+    if (report.num_dropped > 0) {
+      lines.push(
+        `${report.num_dropped} edit(s) couldn't be matched to anything and were dropped.`,
+      );
+    }
+    window.alert(lines.join("\n\n"));
+  }
+}
+
 // Returns input needed to set up the LTN backend, either from pre-hosted files
 // or from Overpass.
 async function getInputFiles(project: ProjectFeatureCollection): Promise<{
@@ -73,14 +103,16 @@ async function getInputFiles(project: ProjectFeatureCollection): Promise<{
   // Or this one
   mapModelBuffer?: Uint8Array<ArrayBufferLike>;
 }> {
+  // The maps_vN version changes with the MapModel bincode layout. v5 added active travel paths,
+  // via-way restrictions, and multiple modal filters per road.
   if (project.app_focus == "cnt") {
     let mapModelBuffer = await download(
-      assetUrl(`cnt/maps_v4/${project.study_area_name}.bin.gz`),
+      assetUrl(`cnt/maps_v5/${project.study_area_name}.bin.gz`),
     );
     return { mapModelBuffer };
   } else if (project.app_focus == "england") {
     let mapModelBuffer = await download(
-      assetUrl(`england/maps_v4/${project.study_area_name}.bin.gz`),
+      assetUrl(`england/maps_v5/${project.study_area_name}.bin.gz`),
     );
     return { mapModelBuffer };
   } else if (project.study_area_name) {
@@ -1,6 +1,7 @@
 import type {
   Feature,
   FeatureCollection,
+  Geometry,
   LineString,
   MultiPolygon,
   Point,
@@ -188,8 +189,8 @@ export class Backend {
     this.inner.addManyModalFilters(line, kind);
   }
 
-  deleteModalFilter(road: number) {
-    this.inner.deleteModalFilter(road);
+  deleteModalFilter(road: number, filterIdx?: number) {
+    this.inner.deleteModalFilter(road, filterIdx);
   }
 
   addDiagonalFilter(intersection: Intersection) {
@@ -261,8 +262,14 @@ export class Backend {
     return JSON.parse(this.inner.toSavefile());
   }
 
-  loadSavefile(gj: ProjectFeatureCollection) {
-    this.inner.loadSavefile(gj);
+  // Returns saved edits that matched the current map with low confidence or were
+  // dropped. Throws on malformed edits, unless skipInvalid is set; then they're
+  // returned too.
+  loadSavefile(
+    gj: ProjectFeatureCollection,
+    skipInvalid = false,
+  ): SavefileReport {
+    return JSON.parse(this.inner.loadSavefile(gj, skipInvalid));
   }
 
   changeProjectName(name: string) {
@@ -453,6 +460,20 @@ export type CompareRoute = FeatureCollection<
   { kind: "before" | "after"; distance: number; time: number }
 >;
 
+// Saved edits that didn't cleanly match the current map
+export type SavefileReport = FeatureCollection<
+  Geometry,
+  {
+    mismatch: "low_confidence" | "dropped" | "invalid";
+    reason: string;
+    feature_index: number;
+  }
+> & {
+  num_low_confidence: number;
+  num_dropped: number;
+  num_invalid: number;
+};
+
 export interface MetricBuckets {
   population_density: number[];
   collision_density: number[];